@@ -0,0 +1,111 @@
+//! Automata to match terms, used for both the sstable dictionary and template constants.
+
+use std::io;
+
+use tantivy_fst::{Automaton, Regex};
+
+/// Runs the automaton over the bytes and returns true if it ends in a match state.
+pub(crate) fn automaton_matches<A: Automaton>(automaton: &A, bytes: &[u8]) -> bool {
+    let mut state = automaton.start();
+    for &byte in bytes {
+        if !automaton.can_match(&state) {
+            return false;
+        }
+        state = automaton.accept(&state, byte);
+    }
+    automaton.is_match(&state)
+}
+
+/// A term pattern where `*` matches any sequence of characters.
+///
+/// The pattern has to match the whole term.
+#[derive(Debug)]
+pub(crate) struct Wildcard {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Wildcard {
+    pub(crate) fn new(pattern: &str) -> io::Result<Self> {
+        let regex_pattern = pattern
+            .split('*')
+            .map(regex_syntax::escape)
+            .collect::<Vec<_>>()
+            .join(".*");
+        let regex = new_regex(&regex_pattern)?;
+        Ok(Wildcard {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    /// Returns the literal prefix if the only wildcard is a trailing `*`.
+    ///
+    /// Such patterns can be answered with a range over the sorted dictionary.
+    pub(crate) fn as_prefix(&self) -> Option<&str> {
+        self.pattern
+            .strip_suffix('*')
+            .filter(|prefix| !prefix.contains('*'))
+    }
+
+    pub(crate) fn matches(&self, term: &[u8]) -> bool {
+        automaton_matches(&self.regex, term)
+    }
+
+    pub(crate) fn automaton(&self) -> &Regex {
+        &self.regex
+    }
+}
+
+/// Builds an anchored regex, which has to match the whole term.
+pub(crate) fn new_regex(pattern: &str) -> io::Result<Regex> {
+    Regex::new(pattern).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+}
+
+/// A pattern matched against whole terms, both template constants and dictionary terms.
+#[derive(Debug)]
+pub(crate) enum TermPattern {
+    Wildcard(Wildcard),
+    Regex(Regex),
+}
+
+impl TermPattern {
+    pub(crate) fn matches(&self, term: &[u8]) -> bool {
+        match self {
+            TermPattern::Wildcard(wildcard) => wildcard.matches(term),
+            TermPattern::Regex(regex) => automaton_matches(regex, term),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcard_matches_whole_term() {
+        let wildcard = Wildcard::new("blk_10740*").unwrap();
+        assert_eq!(wildcard.as_prefix(), Some("blk_10740"));
+        assert!(wildcard.matches(b"blk_10740"));
+        assert!(wildcard.matches(b"blk_1074072698"));
+        assert!(!wildcard.matches(b"xblk_10740"));
+
+        let wildcard = Wildcard::new("*Exception").unwrap();
+        assert_eq!(wildcard.as_prefix(), None);
+        assert!(wildcard.matches(b"java.io.IOException"));
+        assert!(!wildcard.matches(b"IOExceptions"));
+
+        let wildcard = Wildcard::new("a.*c").unwrap();
+        assert!(wildcard.matches(b"a.bc"));
+        assert!(!wildcard.matches(b"abc"));
+    }
+
+    #[test]
+    fn regex_is_anchored() {
+        let pattern = TermPattern::Regex(new_regex("blk_[0-9]+").unwrap());
+        assert!(pattern.matches(b"blk_123"));
+        assert!(!pattern.matches(b"blk_123x"));
+        assert!(!pattern.matches(b"xblk_123"));
+        assert!(new_regex("(").is_err());
+    }
+}
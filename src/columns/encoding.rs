@@ -0,0 +1,347 @@
+//! Encodings of the term IDs of a column block, applied before zstd compression.
+use std::io;
+
+use tantivy_bitpacker::{BitPacker, BitUnpacker, compute_num_bits};
+
+use super::COLUMN_BLOCK_SIZE;
+use super::read::read_varint;
+use super::write::write_varint;
+
+/// How the term IDs of a column are stored in each block, chosen per column by
+/// [`ColumnEncoding::choose`].
+///
+/// Every block also has the min and max term ID in the header of the column file, the
+/// encodings may rely on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnEncoding {
+    /// 4-byte little-endian term IDs.
+    Raw,
+    /// The difference of each term ID to the min of the block, with as many bits as the
+    /// difference between max and min needs.
+    Bitpacked,
+    /// The distinct term IDs of the block, followed by the bitpacked index of the term ID of
+    /// each row. Small for columns with few distinct terms.
+    Dictionary,
+    /// `(term ID, run length)` pairs, small for columns with long runs of the same term.
+    RunLength,
+    /// The first term ID followed by the bitpacked difference of each term ID to the previous
+    /// one, only for ascending columns.
+    Delta,
+}
+
+impl ColumnEncoding {
+    const ALL: [ColumnEncoding; 5] = [
+        ColumnEncoding::Raw,
+        ColumnEncoding::Bitpacked,
+        ColumnEncoding::Dictionary,
+        ColumnEncoding::RunLength,
+        ColumnEncoding::Delta,
+    ];
+
+    /// The id stored in the column file header.
+    pub(crate) fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub(crate) fn from_u8(id: u8) -> Option<ColumnEncoding> {
+        Self::ALL.get(id as usize).copied()
+    }
+
+    /// Returns the encoding with the smallest estimated size for the blocks of the column.
+    pub(crate) fn choose(column: &[u32]) -> ColumnEncoding {
+        let is_ascending = column.windows(2).all(|pair| pair[0] <= pair[1]);
+        Self::ALL
+            .into_iter()
+            .filter(|encoding| *encoding != ColumnEncoding::Delta || is_ascending)
+            .min_by_key(|encoding| {
+                column
+                    .chunks(COLUMN_BLOCK_SIZE)
+                    .map(|block| encoding.estimated_len(block))
+                    .sum::<usize>()
+            })
+            .unwrap_or(ColumnEncoding::Raw)
+    }
+
+    /// The size of the encoded block in bytes, exact apart from the varints of run lengths.
+    fn estimated_len(self, block: &[u32]) -> usize {
+        let packed_len = |num_bits: u8| (block.len() * num_bits as usize).div_ceil(8);
+        match self {
+            ColumnEncoding::Raw => block.len() * 4,
+            ColumnEncoding::Bitpacked => {
+                let (min, max) = min_max(block);
+                packed_len(compute_num_bits((max - min) as u64))
+            }
+            ColumnEncoding::Dictionary => {
+                let num_values = distinct_values(block).len();
+                4 + num_values * 4
+                    + packed_len(compute_num_bits(num_values.saturating_sub(1) as u64))
+            }
+            ColumnEncoding::RunLength => {
+                let num_runs = block.chunk_by(|left, right| left == right).count();
+                num_runs * 6
+            }
+            ColumnEncoding::Delta => 5 + packed_len(delta_num_bits(block)),
+        }
+    }
+
+    /// Appends the encoded term IDs of a block to `output`.
+    pub(crate) fn encode(self, block: &[u32], output: &mut Vec<u8>) -> io::Result<()> {
+        match self {
+            ColumnEncoding::Raw => {
+                for term_id in block {
+                    output.extend_from_slice(&term_id.to_le_bytes());
+                }
+            }
+            ColumnEncoding::Bitpacked => {
+                let (min, max) = min_max(block);
+                let num_bits = compute_num_bits((max - min) as u64);
+                bitpack(block.iter().map(|term_id| term_id - min), num_bits, output)?;
+            }
+            ColumnEncoding::Dictionary => {
+                let values = distinct_values(block);
+                output.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                for value in &values {
+                    output.extend_from_slice(&value.to_le_bytes());
+                }
+                let num_bits = compute_num_bits(values.len().saturating_sub(1) as u64);
+                let indices = block
+                    .iter()
+                    .map(|term_id| values.binary_search(term_id).unwrap() as u32);
+                bitpack(indices, num_bits, output)?;
+            }
+            ColumnEncoding::RunLength => {
+                for run in block.chunk_by(|left, right| left == right) {
+                    output.extend_from_slice(&run[0].to_le_bytes());
+                    write_varint(run.len() as u32, output);
+                }
+            }
+            ColumnEncoding::Delta => {
+                let num_bits = delta_num_bits(block);
+                output.extend_from_slice(&block.first().copied().unwrap_or(0).to_le_bytes());
+                output.push(num_bits);
+                let deltas = block.windows(2).map(|pair| pair[1] - pair[0]);
+                bitpack(deltas, num_bits, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the `num_rows` term IDs of a block with the given min and max term ID and
+    /// appends them to `output`.
+    pub(crate) fn decode(
+        self,
+        mut data: &[u8],
+        num_rows: usize,
+        min_term_id: u32,
+        max_term_id: u32,
+        output: &mut Vec<u32>,
+    ) -> io::Result<()> {
+        let start = output.len();
+        match self {
+            ColumnEncoding::Raw => {
+                output.extend(
+                    data.chunks_exact(4)
+                        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap())),
+                );
+            }
+            ColumnEncoding::Bitpacked => {
+                let num_bits = compute_num_bits(max_term_id.saturating_sub(min_term_id) as u64);
+                unpack(data, num_bits, num_rows, output, |value| {
+                    min_term_id.checked_add(value)
+                })?;
+            }
+            ColumnEncoding::Dictionary => {
+                let num_values = read_u32(&mut data)? as usize;
+                let values_len = num_values.checked_mul(4).ok_or_else(truncated)?;
+                let values = data.get(..values_len).ok_or_else(truncated)?;
+                let values: Vec<u32> = values
+                    .chunks_exact(4)
+                    .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect();
+                data = &data[values_len..];
+                let num_bits = compute_num_bits(num_values.saturating_sub(1) as u64);
+                unpack(data, num_bits, num_rows, output, |index| {
+                    values.get(index as usize).copied()
+                })?;
+            }
+            ColumnEncoding::RunLength => {
+                while !data.is_empty() {
+                    let term_id = read_u32(&mut data)?;
+                    let run_len = read_varint(&mut data)?;
+                    if output.len() - start + run_len as usize > num_rows {
+                        return Err(truncated());
+                    }
+                    output.extend(std::iter::repeat_n(term_id, run_len as usize));
+                }
+            }
+            ColumnEncoding::Delta => {
+                let first = read_u32(&mut data)?;
+                let (&num_bits, data) = data.split_first().ok_or_else(truncated)?;
+                if num_bits > 32 {
+                    return Err(truncated());
+                }
+                if num_rows > 0 {
+                    output.push(first);
+                    let mut previous = first;
+                    unpack(data, num_bits, num_rows - 1, output, |delta| {
+                        previous = previous.checked_add(delta)?;
+                        Some(previous)
+                    })?;
+                }
+            }
+        }
+        if output.len() - start != num_rows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected {num_rows} term ids in {self:?} block, found {}",
+                    output.len() - start
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn min_max(block: &[u32]) -> (u32, u32) {
+    let min = block.iter().min().copied().unwrap_or_default();
+    let max = block.iter().max().copied().unwrap_or_default();
+    (min, max)
+}
+
+fn distinct_values(block: &[u32]) -> Vec<u32> {
+    let mut values = block.to_vec();
+    values.sort_unstable();
+    values.dedup();
+    values
+}
+
+/// The number of bits of the largest difference between consecutive term IDs.
+fn delta_num_bits(block: &[u32]) -> u8 {
+    let max_delta = block
+        .windows(2)
+        .map(|pair| pair[1].wrapping_sub(pair[0]))
+        .max()
+        .unwrap_or(0);
+    compute_num_bits(max_delta as u64)
+}
+
+fn bitpack(
+    values: impl Iterator<Item = u32>,
+    num_bits: u8,
+    output: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut bit_packer = BitPacker::new();
+    for value in values {
+        bit_packer.write(value as u64, num_bits, output)?;
+    }
+    bit_packer.close(output)
+}
+
+/// Unpacks `num_values` values and appends them mapped to `output`, a value mapped to `None` is
+/// an invalid block.
+fn unpack(
+    data: &[u8],
+    num_bits: u8,
+    num_values: usize,
+    output: &mut Vec<u32>,
+    mut map: impl FnMut(u32) -> Option<u32>,
+) -> io::Result<()> {
+    let bit_unpacker = BitUnpacker::new(num_bits);
+    output.reserve(num_values);
+    for idx in 0..num_values as u32 {
+        output.push(map(bit_unpacker.get(idx, data) as u32).ok_or_else(truncated)?);
+    }
+    Ok(())
+}
+
+fn read_u32(data: &mut &[u8]) -> io::Result<u32> {
+    let (bytes, rest) = data.split_first_chunk::<4>().ok_or_else(truncated)?;
+    *data = rest;
+    Ok(u32::from_le_bytes(*bytes))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "truncated or invalid column block",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(encoding: ColumnEncoding, block: &[u32]) -> Vec<u32> {
+        let mut encoded = Vec::new();
+        encoding.encode(block, &mut encoded).unwrap();
+        let (min, max) = min_max(block);
+        let mut decoded = vec![7];
+        encoding
+            .decode(&encoded, block.len(), min, max, &mut decoded)
+            .unwrap();
+        decoded.split_off(1)
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        let blocks: [Vec<u32>; 5] = [
+            vec![42],
+            vec![5; 100],
+            (1000..1300).collect(),
+            (0..500).map(|i| (i * 7919) % 1013 + 20).collect(),
+            vec![0, u32::MAX, 3, u32::MAX, 0],
+        ];
+        for encoding in ColumnEncoding::ALL {
+            for block in &blocks {
+                let is_ascending = block.windows(2).all(|pair| pair[0] <= pair[1]);
+                if encoding == ColumnEncoding::Delta && !is_ascending {
+                    continue;
+                }
+                assert_eq!(&round_trip(encoding, block), block, "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn choose_smallest_encoding() {
+        // Bitpacking a constant block takes no bytes, the block header has its only value.
+        assert_eq!(
+            ColumnEncoding::choose(&[9; 5000]),
+            ColumnEncoding::Bitpacked
+        );
+        let runs: Vec<u32> = (0..5000).map(|i| (i / 1000) * 100_000).collect();
+        assert_eq!(ColumnEncoding::choose(&runs), ColumnEncoding::RunLength);
+        let ascending: Vec<u32> = (0..5000).map(|i| 100_000 + i * 3).collect();
+        assert_eq!(ColumnEncoding::choose(&ascending), ColumnEncoding::Delta);
+        let few_values: Vec<u32> = (0..5000).map(|i| [3, 900_000][i % 2]).collect();
+        assert_eq!(
+            ColumnEncoding::choose(&few_values),
+            ColumnEncoding::Dictionary
+        );
+        let dense: Vec<u32> = (0..5000).map(|i| (i * 7919) % 5000).collect();
+        assert_eq!(ColumnEncoding::choose(&dense), ColumnEncoding::Bitpacked);
+    }
+
+    #[test]
+    fn truncated_block_is_an_error() {
+        let block: Vec<u32> = (0..100).collect();
+        let mut encoded = Vec::new();
+        ColumnEncoding::RunLength
+            .encode(&block, &mut encoded)
+            .unwrap();
+        let mut decoded = Vec::new();
+        let result = ColumnEncoding::RunLength.decode(&encoded[..20], 100, 0, 99, &mut decoded);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn overflowing_delta_block_is_an_error() {
+        let mut encoded = (u32::MAX - 1).to_le_bytes().to_vec();
+        encoded.push(8);
+        bitpack([1, 5].into_iter(), 8, &mut encoded).unwrap();
+        let mut decoded = Vec::new();
+        let result = ColumnEncoding::Delta.decode(&encoded, 3, 0, u32::MAX, &mut decoded);
+        assert!(result.is_err());
+    }
+}
@@ -2,12 +2,68 @@
 
 use std::path::{Path, PathBuf};
 
+use serde::{Deserialize, Serialize};
+
 use crate::TemplateId;
 
+pub(crate) mod encoding;
 pub mod read;
 pub(crate) mod write;
 
+/// The number of rows of a column that are compressed together.
+///
+/// Each block stores the min and max term ID it contains, so blocks that cannot contain a
+/// searched term are skipped without decompressing them.
+pub const COLUMN_BLOCK_SIZE: usize = 4096;
+
+/// The size of the header entry of a block: end offset (u64), min and max term ID (u32).
+const BLOCK_META_LEN: usize = 16;
+
 /// Returns the path to the template file for a given template ID.
 pub fn get_template_path(folder: &Path, template_id: TemplateId) -> PathBuf {
     folder.join(format!("{template_id:?}.col"))
 }
+
+/// Returns the path to the doc id file for a given template ID.
+pub fn get_doc_ids_path(folder: &Path, template_id: TemplateId) -> PathBuf {
+    folder.join(format!("{template_id:?}.ids"))
+}
+
+/// Returns the path to the column stats file for a given template ID.
+pub fn get_column_stats_path(folder: &Path, template_id: TemplateId) -> PathBuf {
+    folder.join(format!("{template_id:?}.stats"))
+}
+
+/// Returns the path to the timestamp file for a given template ID.
+pub fn get_timestamps_path(folder: &Path, template_id: TemplateId) -> PathBuf {
+    folder.join(format!("{template_id:?}.ts"))
+}
+
+/// Min and max of the numbers in a column, used to skip columns in numeric range queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnStats {
+    /// Smallest number in the column.
+    pub min: u64,
+    /// Largest number in the column.
+    pub max: u64,
+    /// True if every term of the column is a number.
+    pub all_numbers: bool,
+}
+
+impl ColumnStats {
+    /// Combines the stats of two parts of a column, `None` for a part without numbers.
+    pub fn merge(left: Option<ColumnStats>, right: Option<ColumnStats>) -> Option<ColumnStats> {
+        match (left, right) {
+            (Some(left), Some(right)) => Some(ColumnStats {
+                min: left.min.min(right.min),
+                max: left.max.max(right.max),
+                all_numbers: left.all_numbers && right.all_numbers,
+            }),
+            (Some(stats), None) | (None, Some(stats)) => Some(ColumnStats {
+                all_numbers: false,
+                ..stats
+            }),
+            (None, None) => None,
+        }
+    }
+}
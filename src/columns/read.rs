@@ -1,11 +1,16 @@
 //! This module provides functionality for reading columns of data.
-use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::ops::Range;
 use std::path::Path;
 
 use crate::TemplateId;
+use crate::settings::Compression;
 
-use super::get_template_path;
+use super::encoding::ColumnEncoding;
+use super::{
+    BLOCK_META_LEN, COLUMN_BLOCK_SIZE, ColumnStats, get_column_stats_path, get_doc_ids_path,
+    get_template_path, get_timestamps_path,
+};
 
 // Note: uncompressed size computation moved to IndexInner
 
@@ -13,19 +18,16 @@ use super::get_template_path;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Column {
     data: Vec<u32>,
+    /// The min and max term ID of each block of [`COLUMN_BLOCK_SIZE`] rows.
+    block_term_ranges: Vec<(u32, u32)>,
 }
 impl Column {
-    /// Creates a new column from a vector of term IDs.
-    pub(crate) fn new(data: Vec<u32>) -> Self {
-        Column { data }
-    }
-
     /// Returns the term ID at a given index.
     fn term_at(&self, index: usize) -> Option<u32> {
         self.data.get(index).copied()
     }
     /// Returns an iterator over the term IDs in this column.
-    fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+    pub(crate) fn iter(&self) -> impl Iterator<Item = u32> + '_ {
         self.data.iter().copied()
     }
 
@@ -33,54 +35,267 @@ impl Column {
     pub(crate) fn len(&self) -> usize {
         self.data.len()
     }
+
+    /// Returns the indices of the rows containing the term ID, skipping blocks whose term ID
+    /// range doesn't contain it.
+    fn get_doc_ids(&self, term_id: u32) -> impl Iterator<Item = usize> + '_ {
+        self.data
+            .chunks(COLUMN_BLOCK_SIZE)
+            .zip(&self.block_term_ranges)
+            .enumerate()
+            .filter(move |(_, (_, (min, max)))| *min <= term_id && term_id <= *max)
+            .flat_map(move |(block, (terms, _))| {
+                let first_row = block * COLUMN_BLOCK_SIZE;
+                terms
+                    .iter()
+                    .enumerate()
+                    .filter(move |(_, term)| **term == term_id)
+                    .map(move |(row, _)| first_row + row)
+            })
+    }
 }
 
-/// A collection of columns.
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// A collection of columns covering a range of rows, possibly with only some of the columns
+/// decompressed.
+///
+/// Accessing a column that was not loaded panics, the caller decides upfront which columns it
+/// needs when reading them from the column file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct Columns {
-    data: Vec<Column>,
+    /// The blocks of the file covered by the columns.
+    blocks: Range<usize>,
+    data: Vec<Option<Column>>,
 }
 impl Columns {
-    /// Creates a new collection of columns.
-    pub(crate) fn new(data: Vec<Column>) -> Self {
-        Columns { data }
+    fn column(&self, column: usize) -> Option<&Column> {
+        let column = self.data.get(column)?;
+        Some(column.as_ref().expect("column should be loaded"))
+    }
+
+    /// The row of the template at index 0 of the columns.
+    fn first_row(&self) -> u32 {
+        (self.blocks.start * COLUMN_BLOCK_SIZE) as u32
     }
 
     /// Returns an iterator over the columns.
-    fn iter_columns(&self) -> impl Iterator<Item = &Column> {
-        self.data.iter()
+    pub(crate) fn iter_columns(&self) -> impl Iterator<Item = &Column> {
+        self.data
+            .iter()
+            .map(|column| column.as_ref().expect("column should be loaded"))
     }
 
-    /// Returns the uncompressed size in bytes of all columns combined.
+    /// Returns the uncompressed size in bytes of all loaded columns combined.
     /// Assumes each term ID is a 4-byte little-endian `u32`.
     pub(crate) fn size_in_bytes(&self) -> u64 {
-        let total_terms: u64 = self.data.iter().map(|c| c.len() as u64).sum();
+        let total_terms: u64 = self.data.iter().flatten().map(|c| c.len() as u64).sum();
         total_terms * 4u64
     }
     /// Returns an iterator over the term IDs for a given document ID.
     pub(crate) fn get_term_ids(&self, doc: u32) -> impl Iterator<Item = u32> + '_ {
+        let index = (doc - self.first_row()) as usize;
         self.iter_columns()
-            .flat_map(move |column| column.term_at(doc as usize))
-    }
-
-    /// Returns an iterator over the document IDs that match a given predicate.
-    pub(crate) fn get_doc_ids<'a>(
-        &'a self,
-        match_fn: &'a impl Fn(u32) -> bool,
-    ) -> impl Iterator<Item = u32> + 'a {
-        self.iter_columns().flat_map(move |column| {
-            column.iter().enumerate().filter_map(move |(docid, term)| {
-                if match_fn(term) {
-                    Some(docid as u32)
-                } else {
-                    None
+            .flat_map(move |column| column.term_at(index))
+    }
+
+    /// Returns the term ID of a single column for a given document ID.
+    pub(crate) fn term_at(&self, column: usize, doc: u32) -> Option<u32> {
+        self.column(column)?
+            .term_at((doc - self.first_row()) as usize)
+    }
+
+    /// Returns the sorted document IDs where any loaded column contains the term ID.
+    ///
+    /// Blocks whose term ID range doesn't contain the term ID are not scanned.
+    pub(crate) fn get_doc_ids(&self, term_id: u32) -> Vec<u32> {
+        let mut doc_ids: Vec<u32> = self
+            .data
+            .iter()
+            .flatten()
+            .flat_map(|column| column.get_doc_ids(term_id))
+            .map(|row| self.first_row() + row as u32)
+            .collect();
+        doc_ids.sort_unstable();
+        doc_ids.dedup();
+        doc_ids
+    }
+}
+
+/// The position and term ID range of a compressed block of a column.
+struct BlockMeta {
+    range: Range<usize>,
+    min_term_id: u32,
+    max_term_id: u32,
+}
+
+/// The compressed columns of a template, see `write_columns` for the layout.
+///
+/// Columns are only decompressed when requested, one block of [`COLUMN_BLOCK_SIZE`] rows at a
+/// time.
+pub(crate) struct ColumnFile {
+    data: Vec<u8>,
+    compression: Compression,
+    /// The encoding of the term IDs of each column.
+    encodings: Vec<ColumnEncoding>,
+    /// The blocks of each column.
+    blocks: Vec<Vec<BlockMeta>>,
+    num_docs: usize,
+}
+
+impl ColumnFile {
+    /// Reads the column file of a template without decompressing any column.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the column file cannot be read or its header is invalid.
+    pub(crate) fn open(
+        folder: &Path,
+        template_id: TemplateId,
+        num_docs: usize,
+        compression: &Compression,
+    ) -> io::Result<ColumnFile> {
+        let data = std::fs::read(get_template_path(folder, template_id))?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid column file header for {template_id:?}"),
+            )
+        };
+        let (num_columns, rest) = data.split_first_chunk::<4>().ok_or_else(invalid)?;
+        let num_columns = u32::from_le_bytes(*num_columns) as usize;
+        let encodings = rest
+            .get(..num_columns)
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|id| ColumnEncoding::from_u8(*id).ok_or_else(invalid))
+            .collect::<io::Result<Vec<_>>>()?;
+        let num_blocks = num_docs.div_ceil(COLUMN_BLOCK_SIZE);
+        let header_len = 4 + num_columns + num_columns * num_blocks * BLOCK_META_LEN;
+        let metas = data.get(4 + num_columns..header_len).ok_or_else(invalid)?;
+        let mut blocks = Vec::with_capacity(num_columns);
+        let mut start = header_len;
+        let mut metas = metas.chunks_exact(BLOCK_META_LEN);
+        for _ in 0..num_columns {
+            let mut column_blocks = Vec::with_capacity(num_blocks);
+            for meta in metas.by_ref().take(num_blocks) {
+                let (end, term_ids) = meta.split_at(8);
+                let end = header_len + u64::from_le_bytes(end.try_into().unwrap()) as usize;
+                if end < start || end > data.len() {
+                    return Err(invalid());
                 }
+                column_blocks.push(BlockMeta {
+                    range: start..end,
+                    min_term_id: u32::from_le_bytes(term_ids[..4].try_into().unwrap()),
+                    max_term_id: u32::from_le_bytes(term_ids[4..].try_into().unwrap()),
+                });
+                start = end;
+            }
+            blocks.push(column_blocks);
+        }
+        Ok(ColumnFile {
+            data,
+            compression: compression.clone(),
+            encodings,
+            blocks,
+            num_docs,
+        })
+    }
+
+    /// Returns the number of columns in the file.
+    pub(crate) fn num_columns(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Returns the number of blocks of each column.
+    pub(crate) fn num_blocks(&self) -> usize {
+        self.num_docs.div_ceil(COLUMN_BLOCK_SIZE)
+    }
+
+    /// Returns the rows of a block.
+    pub(crate) fn block_rows(&self, block: usize) -> Range<u32> {
+        let start = block * COLUMN_BLOCK_SIZE;
+        let end = (start + COLUMN_BLOCK_SIZE).min(self.num_docs);
+        start as u32..end as u32
+    }
+
+    /// Returns true if the block of the column may contain the term ID, without decompressing
+    /// it.
+    pub(crate) fn block_may_contain(&self, column: usize, block: usize, term_id: u32) -> bool {
+        let meta = &self.blocks[column][block];
+        meta.min_term_id <= term_id && term_id <= meta.max_term_id
+    }
+
+    /// Decompresses the columns for which `is_needed` returns true.
+    pub(crate) fn read_columns(&self, is_needed: impl Fn(usize) -> bool) -> io::Result<Columns> {
+        self.read_blocks(0..self.num_blocks(), is_needed)
+    }
+
+    /// Decompresses a single block of the columns for which `is_needed` returns true.
+    pub(crate) fn read_block(
+        &self,
+        block: usize,
+        is_needed: impl Fn(usize) -> bool,
+    ) -> io::Result<Columns> {
+        self.read_blocks(block..block + 1, is_needed)
+    }
+
+    fn read_blocks(
+        &self,
+        blocks: Range<usize>,
+        is_needed: impl Fn(usize) -> bool,
+    ) -> io::Result<Columns> {
+        let data = (0..self.num_columns())
+            .map(|column| {
+                is_needed(column)
+                    .then(|| self.read_column(column, blocks.clone()))
+                    .transpose()
             })
+            .collect::<io::Result<_>>()?;
+        Ok(Columns { blocks, data })
+    }
+
+    /// Decompresses the columns that are not loaded yet in `columns`.
+    pub(crate) fn read_missing_columns(&self, columns: &mut Columns) -> io::Result<()> {
+        for (idx, column) in columns.data.iter_mut().enumerate() {
+            if column.is_none() {
+                *column = Some(self.read_column(idx, columns.blocks.clone())?);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_column(&self, column: usize, blocks: Range<usize>) -> io::Result<Column> {
+        let mut data = Vec::new();
+        let mut block_term_ranges = Vec::new();
+        for block in blocks {
+            let meta = &self.blocks[column][block];
+            let decompressed_data = self
+                .compression
+                .decompress(&self.data[meta.range.clone()])?;
+            let num_rows = self.block_rows(block).len();
+            self.encodings[column]
+                .decode(
+                    &decompressed_data,
+                    num_rows,
+                    meta.min_term_id,
+                    meta.max_term_id,
+                    &mut data,
+                )
+                .map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("block {block} of column {column}: {err}"),
+                    )
+                })?;
+            block_term_ranges.push((meta.min_term_id, meta.max_term_id));
+        }
+        Ok(Column {
+            data,
+            block_term_ranges,
         })
     }
 }
 
-/// Decompresses a column from a file.
+/// Decompresses all columns of a template.
 ///
 /// # Arguments
 ///
@@ -95,24 +310,95 @@ pub(crate) fn decompress_column(
     folder: &Path,
     template_id: TemplateId,
     num_docs: usize,
+    compression: &Compression,
 ) -> std::io::Result<Columns> {
-    let file_path = get_template_path(folder, template_id);
-    let file = File::open(file_path)?;
-    let mut decoder = zstd::Decoder::new(file)?;
-    let mut decompressed_data = Vec::new();
-    decoder.read_to_end(&mut decompressed_data)?;
-
-    let mut columns = Vec::new();
-    // Convert the decompressed data into a vector of u32
-    let mut terms = Vec::new();
-    for chunk in decompressed_data.chunks_exact(4) {
-        let term_id = u32::from_le_bytes(chunk.try_into().unwrap());
-        terms.push(term_id);
-        if terms.len() == num_docs {
-            columns.push(Column::new(terms.clone()));
-            terms.clear();
-        }
+    ColumnFile::open(folder, template_id, num_docs, compression)?.read_columns(|_| true)
+}
+
+/// Reads the doc ids (line numbers) of the rows of a template.
+///
+/// # Errors
+///
+/// Returns an error if the doc id file cannot be read or decompressed.
+pub(crate) fn read_doc_ids(
+    folder: &Path,
+    template_id: TemplateId,
+    num_docs: usize,
+    compression: &Compression,
+) -> io::Result<Vec<u32>> {
+    let file_path = get_doc_ids_path(folder, template_id);
+    let decompressed_data = compression.decompress(&std::fs::read(file_path)?)?;
+
+    let mut doc_ids = Vec::with_capacity(num_docs);
+    let mut data = decompressed_data.as_slice();
+    let mut previous = 0;
+    while !data.is_empty() {
+        previous += read_varint(&mut data)?;
+        doc_ids.push(previous);
+    }
+    if doc_ids.len() != num_docs {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected {num_docs} doc ids for {template_id:?}, found {}",
+                doc_ids.len()
+            ),
+        ));
     }
+    Ok(doc_ids)
+}
+
+/// Reads the [`ColumnStats`] of each column of a template.
+///
+/// # Errors
+///
+/// Returns an error if the stats file cannot be read or deserialized.
+pub(crate) fn read_column_stats(
+    folder: &Path,
+    template_id: TemplateId,
+) -> io::Result<Vec<Option<ColumnStats>>> {
+    let bytes = std::fs::read(get_column_stats_path(folder, template_id))?;
+    postcard::from_bytes(&bytes).map_err(io::Error::other)
+}
+
+/// Reads the timestamp of each row of a template, see `write_timestamps`.
+///
+/// # Errors
+///
+/// Returns an error if the timestamp file cannot be read or decompressed.
+pub(crate) fn read_timestamps(
+    folder: &Path,
+    template_id: TemplateId,
+    compression: &Compression,
+) -> io::Result<Vec<Option<i64>>> {
+    let bytes =
+        compression.decompress(&std::fs::read(get_timestamps_path(folder, template_id))?)?;
+    let deltas: Vec<Option<i64>> = postcard::from_bytes(&bytes).map_err(io::Error::other)?;
+    let mut previous = 0;
+    Ok(deltas
+        .into_iter()
+        .map(|delta| {
+            let timestamp = previous + delta?;
+            previous = timestamp;
+            Some(timestamp)
+        })
+        .collect())
+}
 
-    Ok(Columns::new(columns))
+pub(super) fn read_varint(data: &mut &[u8]) -> io::Result<u32> {
+    let mut value = 0u32;
+    for shift in (0..32).step_by(7) {
+        let Some((&byte, rest)) = data.split_first() else {
+            break;
+        };
+        *data = rest;
+        value |= ((byte & 0x7F) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "truncated or overlong varint",
+    ))
 }
@@ -1,32 +1,286 @@
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-use crate::indexing::DocGroup;
+use fxhash::FxHashMap;
 
-use super::get_template_path;
+use crate::TemplateId;
+use crate::indexing::{DocGroup, IndexingTemplateToken};
+use crate::settings::Compression;
+use crate::tokenizer::Number;
 
-/// The columns are flattened as [Column1Term1, Column1Term2, ..., Column2Term1, ...]
-/// Each column has the same number of terms
+use super::encoding::ColumnEncoding;
+use super::{
+    COLUMN_BLOCK_SIZE, ColumnStats, get_column_stats_path, get_doc_ids_path, get_template_path,
+    get_timestamps_path,
+};
+
+/// Writes the columns of a group with the term IDs remapped, see [`write_columns`] for the
+/// layout.
 pub fn write_column_and_remap(
     folder: &Path,
     group: &DocGroup,
     old_to_new_id_map: &[u32],
+    compression: &Compression,
+) -> std::io::Result<()> {
+    let columns = remapped_columns(group, old_to_new_id_map);
+    write_column_file(folder, group.template.template_id, columns, compression)
+}
+
+fn remapped_columns<'a>(
+    group: &'a DocGroup,
+    old_to_new_id_map: &'a [u32],
+) -> impl Iterator<Item = impl Iterator<Item = u32> + 'a> + 'a {
+    group.iter_columns().map(|column| {
+        column
+            .iter()
+            .map(|term_id| old_to_new_id_map[*term_id as usize])
+    })
+}
+
+/// Appends the encoded blocks of the columns of a group to `samples`, as written by
+/// [`write_column_and_remap`] before compression.
+///
+/// Used to train a zstd dictionary for the column blocks of a segment.
+pub fn group_column_samples(
+    group: &DocGroup,
+    old_to_new_id_map: &[u32],
+    samples: &mut Vec<Vec<u8>>,
+) -> std::io::Result<()> {
+    column_samples(remapped_columns(group, old_to_new_id_map), samples)
+}
+
+/// Appends the encoded blocks of the columns to `samples`, see [`group_column_samples`].
+pub fn column_samples<C: Iterator<Item = u32>>(
+    columns: impl Iterator<Item = C>,
+    samples: &mut Vec<Vec<u8>>,
 ) -> std::io::Result<()> {
+    for column in columns {
+        let terms: Vec<u32> = column.collect();
+        let encoding = ColumnEncoding::choose(&terms);
+        for block in terms.chunks(COLUMN_BLOCK_SIZE) {
+            let mut sample = Vec::new();
+            encoding.encode(block, &mut sample)?;
+            samples.push(sample);
+        }
+    }
+    Ok(())
+}
+
+/// Writes columns whose term IDs are already final.
+///
+/// Each column is split into blocks of [`COLUMN_BLOCK_SIZE`] rows, and each block is compressed
+/// on its own, so a reader can decompress only the blocks and columns it needs. Before
+/// compression the term IDs of a block are encoded with the [`ColumnEncoding`] chosen for the
+/// column. The file starts with the number of columns, the encoding of each column and a header
+/// entry per block, with the end offset of the compressed block relative to the end of the
+/// header and the min and max term ID of the block:
+///
+/// `[num_columns: u32][encoding: u8; num_columns]`
+/// `[(end_offset: u64, min: u32, max: u32); num_columns * num_blocks]`
+/// `[column 0 block 0][column 0 block 1]...[column 1 block 0]...`
+///
+/// The number of blocks follows from the number of docs of the template.
+pub fn write_columns(
+    folder: &Path,
+    template_id: TemplateId,
+    columns: &[Vec<u32>],
+    compression: &Compression,
+) -> std::io::Result<()> {
+    let columns = columns.iter().map(|column| column.iter().copied());
+    write_column_file(folder, template_id, columns, compression)
+}
+
+fn write_column_file<C: Iterator<Item = u32>>(
+    folder: &Path,
+    template_id: TemplateId,
+    columns: impl Iterator<Item = C>,
+    compression: &Compression,
+) -> std::io::Result<()> {
+    let mut num_columns = 0u32;
+    let mut encodings = Vec::new();
+    let mut header = Vec::new();
+    let mut blocks = Vec::new();
     let mut byte_buffer = Vec::new();
-    for term_id in group.iter_columns() {
-        for term_id in term_id {
-            // Convert the term ID to the new ID using the mapping
-            let new_term_id = &old_to_new_id_map[*term_id as usize];
-            // Append the new term ID to the byte buffer
-            byte_buffer.extend_from_slice(&new_term_id.to_le_bytes());
+    let mut terms = Vec::new();
+    for column in columns {
+        num_columns += 1;
+        terms.clear();
+        terms.extend(column);
+        let encoding = ColumnEncoding::choose(&terms);
+        encodings.push(encoding.to_u8());
+        for block in terms.chunks(COLUMN_BLOCK_SIZE) {
+            byte_buffer.clear();
+            encoding.encode(block, &mut byte_buffer)?;
+            compression.compress(&byte_buffer, &mut blocks)?;
+            let min = block.iter().min().copied().unwrap_or_default();
+            let max = block.iter().max().copied().unwrap_or_default();
+            header.extend_from_slice(&(blocks.len() as u64).to_le_bytes());
+            header.extend_from_slice(&min.to_le_bytes());
+            header.extend_from_slice(&max.to_le_bytes());
         }
     }
 
-    let compressed_data = zstd::stream::encode_all(&*byte_buffer, 6).unwrap();
+    let file_path = get_template_path(folder, template_id);
+    let mut file = BufWriter::new(File::create(file_path)?);
+    file.write_all(&num_columns.to_le_bytes())?;
+    file.write_all(&encodings)?;
+    file.write_all(&header)?;
+    file.write_all(&blocks)?;
+    file.flush()
+}
 
-    let file_path = get_template_path(folder, group.template.template_id);
-    let mut file = File::create(file_path).unwrap();
-    file.write_all(&compressed_data).unwrap();
+/// Writes the doc ids (line numbers) of the rows of a template.
+///
+/// The doc ids are ascending, so they are stored as varint encoded deltas.
+pub fn write_doc_ids(
+    folder: &Path,
+    template_id: TemplateId,
+    doc_ids: &[u32],
+    compression: &Compression,
+) -> std::io::Result<()> {
+    let mut byte_buffer = Vec::new();
+    let mut previous = 0;
+    for &doc_id in doc_ids {
+        debug_assert!(doc_id >= previous, "doc ids should be sorted");
+        write_varint(doc_id - previous, &mut byte_buffer);
+        previous = doc_id;
+    }
+
+    let mut compressed_data = Vec::new();
+    compression.compress(&byte_buffer, &mut compressed_data)?;
+
+    let file_path = get_doc_ids_path(folder, template_id);
+    let mut file = File::create(file_path)?;
+    file.write_all(&compressed_data)?;
+    Ok(())
+}
+
+/// Writes the [`ColumnStats`] of each column of a template, `None` for columns without numbers.
+///
+/// The stats are in the same order as the columns in the column file.
+pub fn write_column_stats(
+    folder: &Path,
+    template_id: TemplateId,
+    stats: &[Option<ColumnStats>],
+) -> std::io::Result<()> {
+    let bytes = postcard::to_allocvec(stats).map_err(std::io::Error::other)?;
+    let file_path = get_column_stats_path(folder, template_id);
+    File::create(file_path)?.write_all(&bytes)?;
+    Ok(())
+}
+
+/// Computes the [`ColumnStats`] of each column of a group, in the order of the column file.
+///
+/// `number_values` holds the value of each term, indexed by the term ID used in the group.
+pub fn group_column_stats(
+    group: &DocGroup,
+    number_values: &[Option<u64>],
+) -> Vec<Option<ColumnStats>> {
+    group
+        .template
+        .tokens
+        .iter()
+        .filter_map(|token| match token.token {
+            IndexingTemplateToken::Variable {
+                token_type,
+                column_index,
+                ..
+            } => Some(
+                Number::column_may_hold_numbers(token_type)
+                    .then(|| column_stats(&group.columns[column_index], number_values))
+                    .flatten(),
+            ),
+            IndexingTemplateToken::Constant(_) => None,
+        })
+        .collect()
+}
+
+fn column_stats(column: &[u32], number_values: &[Option<u64>]) -> Option<ColumnStats> {
+    let mut min_max: Option<(u64, u64)> = None;
+    let mut all_numbers = true;
+    for term_id in column {
+        let Some(value) = number_values[*term_id as usize] else {
+            all_numbers = false;
+            continue;
+        };
+        min_max = Some(match min_max {
+            Some((min, max)) => (min.min(value), max.max(value)),
+            None => (value, value),
+        });
+    }
+    min_max.map(|(min, max)| ColumnStats {
+        min,
+        max,
+        all_numbers,
+    })
+}
+
+/// Writes the timestamp of each row of a template.
+///
+/// The timestamps of a template are mostly ascending, so each one is stored as the delta to the
+/// previous timestamp.
+pub fn write_timestamps(
+    folder: &Path,
+    template_id: TemplateId,
+    timestamps: impl IntoIterator<Item = Option<i64>>,
+    compression: &Compression,
+) -> std::io::Result<()> {
+    let mut previous = 0;
+    let deltas: Vec<Option<i64>> = timestamps
+        .into_iter()
+        .map(|timestamp| {
+            let timestamp = timestamp?;
+            let delta = timestamp - previous;
+            previous = timestamp;
+            Some(delta)
+        })
+        .collect();
+    let bytes = postcard::to_allocvec(&deltas).map_err(std::io::Error::other)?;
+    let mut compressed_data = Vec::new();
+    compression.compress(&bytes, &mut compressed_data)?;
+
+    let file_path = get_timestamps_path(folder, template_id);
+    File::create(file_path)?.write_all(&compressed_data)?;
     Ok(())
 }
+
+/// Returns the sorted rows of each term that is in at most `max_rows` rows of a template.
+///
+/// The rows of a term are stored in its [`TermInfo`](crate::indexing::write_dict::TermInfo)
+/// in the dictionary. `term_id_map` maps the term IDs of the columns to the keys of the result.
+pub fn template_postings(
+    columns: &[&[u32]],
+    max_rows: usize,
+    term_id_map: impl Fn(u32) -> u32,
+) -> FxHashMap<u32, Vec<u32>> {
+    // `None` once a term is in too many rows, so memory stays bounded by `max_rows` per term.
+    let mut rows: FxHashMap<u32, Option<Vec<u32>>> = FxHashMap::default();
+    let num_rows = columns.first().map_or(0, |column| column.len());
+    for row in 0..num_rows as u32 {
+        for column in columns {
+            let term_rows = rows
+                .entry(term_id_map(column[row as usize]))
+                .or_insert_with(|| Some(Vec::new()));
+            match term_rows {
+                None => {}
+                // The term is in several columns of the row.
+                Some(rows) if rows.last() == Some(&row) => {}
+                Some(rows) if rows.len() == max_rows => *term_rows = None,
+                Some(rows) => rows.push(row),
+            }
+        }
+    }
+    rows.into_iter()
+        .filter_map(|(term_id, rows)| Some((term_id, rows?)))
+        .collect()
+}
+
+/// LEB128 encoding, 7 bits per byte, the high bit marks that more bytes follow.
+pub(super) fn write_varint(mut value: u32, output: &mut Vec<u8>) {
+    while value >= 0x80 {
+        output.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
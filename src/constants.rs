@@ -7,3 +7,20 @@ pub const DICTIONARY_NAME: &str = "dictionary";
 pub const TEMPLATE_FILE_NAME: &str = "templates";
 /// The name of the template debug file.
 pub const TEMPLATE_DEBUG_FILE_NAME: &str = "templates_debug";
+
+/// The name of the file listing the segments of an index.
+pub const SEGMENTS_FILE_NAME: &str = "segments";
+
+/// The name of the file with the settings of an index.
+pub const SETTINGS_FILE_NAME: &str = "settings";
+
+/// The name of the zstd dictionary trained for a segment.
+pub const ZSTD_DICTIONARY_NAME: &str = "zstd_dictionary";
+
+/// The name of the schema tree of an index of JSON documents.
+pub const SCHEMA_TREE_FILE_NAME: &str = "schema_tree";
+
+/// The name of the file with the schema of each JSON document of a segment.
+pub const JSON_DOCS_NAME: &str = "json_docs";
+/// The name of the file with the non-string leaf values of the JSON documents of a segment.
+pub const JSON_COLUMNS_NAME: &str = "json_columns";
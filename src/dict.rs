@@ -1,22 +1,42 @@
 use std::io;
 use std::path::Path;
 
+use fxhash::FxHashMap;
 use tantivy_common::file_slice::FileSlice;
+use tantivy_fst::Automaton;
+use tantivy_sstable::{StreamerBuilder, TermOrdinal};
 
 use crate::TemplateId;
 use crate::constants::DICTIONARY_NAME;
-use crate::indexing::write_dict::VecU32ValueSSTable;
+use crate::indexing::write_dict::{TermInfo, TermInfoSSTable};
 
 pub(crate) struct Dict {
-    dictionary: tantivy_sstable::Dictionary<VecU32ValueSSTable>,
+    dictionary: tantivy_sstable::Dictionary<TermInfoSSTable>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
     term_id: u32,
     template_ids: Vec<TemplateId>,
+    postings: Vec<(TemplateId, Vec<u32>)>,
 }
 impl SearchResult {
+    fn new(term_id: u32, term_info: &TermInfo) -> Self {
+        SearchResult {
+            term_id,
+            template_ids: term_info
+                .template_ids
+                .iter()
+                .map(|template_id| (*template_id).into())
+                .collect(),
+            postings: term_info
+                .postings
+                .iter()
+                .map(|(template_id, rows)| ((*template_id).into(), rows.clone()))
+                .collect(),
+        }
+    }
+
     pub fn term_id(&self) -> u32 {
         self.term_id
     }
@@ -26,11 +46,66 @@ impl SearchResult {
     }
 }
 
+/// The rows of selective terms in the templates of a segment.
+///
+/// The dictionary lists the templates of a term, and the rows of the term in the templates where
+/// it is in few rows. For all other terms the columns have to be scanned.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Postings {
+    rows: FxHashMap<(TemplateId, u32), Vec<u32>>,
+}
+
+impl Postings {
+    /// Adds the postings of a term found in the dictionary.
+    pub fn add(&mut self, search_result: &SearchResult) {
+        for (template_id, rows) in &search_result.postings {
+            self.rows
+                .insert((*template_id, search_result.term_id), rows.clone());
+        }
+    }
+
+    /// Adds the postings of all terms of `other`.
+    pub fn extend(&mut self, other: &Postings) {
+        self.rows
+            .extend(other.rows.iter().map(|(key, rows)| (*key, rows.clone())));
+    }
+
+    /// Returns the sorted rows of the template containing the term ID in any column, `None` if
+    /// the term has no postings in the template.
+    pub fn rows(&self, template_id: TemplateId, term_id: u32) -> Option<&[u32]> {
+        self.rows.get(&(template_id, term_id)).map(Vec::as_slice)
+    }
+
+    /// Returns true if no term has postings.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+/// All terms of a dictionary, stored contiguously and indexed by term ordinal.
+pub struct AllTerms {
+    bytes: Vec<u8>,
+    offsets: Vec<usize>,
+}
+impl AllTerms {
+    /// Returns the number of terms.
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Returns the term bytes for the ordinal.
+    pub fn get(&self, term_ord: u32) -> Option<&[u8]> {
+        let start = *self.offsets.get(term_ord as usize)?;
+        let end = *self.offsets.get(term_ord as usize + 1)?;
+        Some(&self.bytes[start..end])
+    }
+}
+
 impl Dict {
-    pub fn new(output_folder: &str) -> io::Result<Self> {
-        let dictionary_path = Path::new(output_folder).join(DICTIONARY_NAME);
+    pub fn new(folder: &Path) -> io::Result<Self> {
+        let dictionary_path = folder.join(DICTIONARY_NAME);
         let file = FileSlice::open(&dictionary_path)?;
-        let dictionary = tantivy_sstable::Dictionary::<VecU32ValueSSTable>::open(file).unwrap();
+        let dictionary = tantivy_sstable::Dictionary::<TermInfoSSTable>::open(file).unwrap();
         Ok(Dict { dictionary })
     }
 
@@ -41,30 +116,98 @@ impl Dict {
             return Ok(self
                 .dictionary
                 .term_info_from_ord(term_ord)?
-                .map(|template_ids| {
-                    Some(SearchResult {
-                        term_id: term_ord as u32,
-                        template_ids: template_ids
-                            .into_iter()
-                            .map(|template_id| template_id.into())
-                            .collect(),
-                    })
-                })
+                .map(|term_info| Some(SearchResult::new(term_ord as u32, &term_info)))
                 .expect("Term info should be present"));
         }
         Ok(None)
     }
 
-    pub fn get_term_for_ord(&self, term_ord: u32) -> io::Result<Option<String>> {
-        let mut out = Vec::new();
-        if self.dictionary.ord_to_term(term_ord as u64, &mut out)? {
-            return Ok(Some(String::from_utf8(out).map_err(|_| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Failed to convert term bytes to String",
-                )
-            })?));
+    /// Search for all terms starting with `prefix`.
+    pub fn search_prefix(&self, prefix: &[u8]) -> io::Result<Vec<SearchResult>> {
+        Self::collect_terms(
+            self.dictionary.prefix_range(prefix),
+            |_| true,
+            |_, term_ord| Ok(term_ord),
+        )
+    }
+
+    /// Search for all terms accepted by `matches_term`, this scans the whole dictionary.
+    pub fn search_by(&self, matches_term: impl Fn(&[u8]) -> bool) -> io::Result<Vec<SearchResult>> {
+        Self::collect_terms(self.dictionary.range(), matches_term, |_, term_ord| {
+            Ok(term_ord)
+        })
+    }
+
+    /// Search for all terms accepted by the automaton.
+    ///
+    /// The automaton is run over every term, unless it can rule out whole sstable blocks.
+    pub fn search_automaton<A: Automaton>(&self, automaton: A) -> io::Result<Vec<SearchResult>>
+    where
+        A::State: Clone,
+    {
+        // The streamer doesn't count the terms of the blocks it skips, so its ordinals are off
+        // once a block was ruled out. Look them up instead.
+        Self::collect_terms(
+            self.dictionary.search(automaton),
+            |_| true,
+            |term, _| {
+                self.dictionary
+                    .term_ord(term)?
+                    .ok_or_else(|| io::Error::other("streamed term missing from the dictionary"))
+            },
+        )
+    }
+
+    fn collect_terms<A: Automaton>(
+        streamer_builder: StreamerBuilder<'_, TermInfoSSTable, A>,
+        matches_term: impl Fn(&[u8]) -> bool,
+        term_ord: impl Fn(&[u8], TermOrdinal) -> io::Result<TermOrdinal>,
+    ) -> io::Result<Vec<SearchResult>>
+    where
+        A::State: Clone,
+    {
+        let mut results = Vec::new();
+        let mut streamer = streamer_builder.into_stream()?;
+        while streamer.advance() {
+            if !matches_term(streamer.key()) {
+                continue;
+            }
+            let term_id = term_ord(streamer.key(), streamer.term_ord())? as u32;
+            results.push(SearchResult::new(term_id, streamer.value()));
         }
-        Ok(None)
+        Ok(results)
+    }
+
+    /// Writes the term bytes for the ordinal into `out`. Returns false if the ordinal is out of
+    /// range.
+    pub fn term_bytes_for_ord(&self, term_ord: u32, out: &mut Vec<u8>) -> io::Result<bool> {
+        self.dictionary.ord_to_term(term_ord as u64, out)
+    }
+
+    /// Reads the template ids and postings of all terms in ordinal order.
+    pub fn load_all_term_infos(&self) -> io::Result<Vec<TermInfo>> {
+        let mut all_term_infos = Vec::new();
+        let mut streamer = self.dictionary.stream()?;
+        while streamer.advance() {
+            all_term_infos.push(streamer.value().clone());
+        }
+        Ok(all_term_infos)
+    }
+
+    /// Reads all terms in ordinal order.
+    ///
+    /// Much faster than calling [`Dict::term_bytes_for_ord`] for every term id when
+    /// reconstructing many documents.
+    pub fn load_all_terms(&self) -> io::Result<AllTerms> {
+        let mut all_terms = AllTerms {
+            bytes: Vec::new(),
+            offsets: vec![0],
+        };
+        let mut streamer = self.dictionary.stream()?;
+        while streamer.advance() {
+            all_terms.bytes.extend_from_slice(streamer.key());
+            all_terms.offsets.push(all_terms.bytes.len());
+        }
+        Ok(all_terms)
     }
 }
@@ -1,11 +1,20 @@
-use std::io::{self};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::columns::read::{Columns, decompress_column};
+use crate::columns::read::{
+    ColumnFile, Columns, decompress_column, read_column_stats, read_doc_ids, read_timestamps,
+};
+use crate::columns::{COLUMN_BLOCK_SIZE, ColumnStats};
 use crate::dict::Dict;
+use crate::json::{JsonDocs, LeafColumn, read_json_columns};
+use crate::schema::{SchemaTree, read_schema_tree};
 use crate::search::Searcher;
+use crate::segment::read_segment_metas;
+use crate::settings::{Codec, Compression, read_settings};
 use crate::templates::{TemplateWithId, read_templates};
 use crate::{Doc, TemplateId};
 
@@ -36,11 +45,11 @@ impl Deref for Index {
         &self.inner
     }
 }
-/// The inner structure of the index, containing the dictionary and templates.
+/// The inner structure of the index, containing its segments.
 pub struct IndexInner {
-    folder: PathBuf,
-    pub(crate) dictionary: Arc<Dict>,
-    pub(crate) templates: Templates,
+    pub(crate) segments: Vec<Segment>,
+    /// The schema tree of an index of JSON documents.
+    schema_tree: Option<SchemaTree>,
 }
 pub(crate) struct Templates {
     templates: Vec<TemplateWithId>,
@@ -57,80 +66,511 @@ impl Templates {
 
 impl IndexInner {
     pub(crate) fn new(folder: &str) -> io::Result<Self> {
-        let dictionary = Dict::new(folder)?;
-        let folder = PathBuf::from(folder);
-        let templates = read_templates(&folder)?;
-        for (idx, template) in templates.iter().enumerate() {
-            assert_eq!(
-                idx, template.template_id.0 as usize,
-                "Template ID mismatch at index {idx}",
-            );
+        let folder = Path::new(folder);
+        let codec = read_settings(folder)?.codec;
+        let schema_tree = read_schema_tree(folder)?;
+        let mut segments = Vec::new();
+        let mut doc_id_offset = 0;
+        let mut json_doc_offset = 0;
+        for meta in read_segment_metas(folder)? {
+            let mut segment =
+                Segment::open(meta.folder(folder), doc_id_offset, meta.num_docs, codec)?;
+            segment.last_line_unterminated = meta.last_line_unterminated;
+            if let Some(tree) = &schema_tree {
+                let json_docs = segment.open_json_docs(tree)?;
+                segment.json_doc_offset = json_doc_offset;
+                json_doc_offset += json_docs.num_docs();
+                segment.json_docs = Some(json_docs);
+            }
+            segments.push(segment);
+            doc_id_offset += meta.num_docs;
         }
-        let templates = Templates { templates };
         Ok(IndexInner {
-            dictionary: Arc::new(dictionary),
-            templates,
-            folder,
+            segments,
+            schema_tree,
         })
     }
 
+    /// Returns the schema tree if the index contains JSON documents, see
+    /// [`IndexWriter::index_json`](crate::indexing::IndexWriter::index_json).
+    pub fn schema_tree(&self) -> Option<&SchemaTree> {
+        self.schema_tree.as_ref()
+    }
+
+    /// Returns the segment containing the doc id.
+    fn segment_for_doc_id(&self, doc_id: u32) -> Option<&Segment> {
+        let idx = self
+            .segments
+            .partition_point(|segment| segment.end_doc_id() <= doc_id);
+        self.segments.get(idx)
+    }
+
+    /// Returns the segment containing the JSON document.
+    fn segment_for_json_doc_id(&self, doc_id: u32) -> Option<&Segment> {
+        let idx = self
+            .segments
+            .partition_point(|segment| segment.end_json_doc_id() <= doc_id);
+        self.segments.get(idx)
+    }
+
     /// Retrieve documents based on the provided `Doc` (template ID and term IDs).
     pub fn retrieve_doc(&self, docs: &[Doc]) -> io::Result<Vec<String>> {
         // Retrieve the documents for the term ID and template IDs.
         let mut documents = Vec::new();
         for doc in docs {
-            let reconstructed = self
+            let segment = self.segment_for_doc_id(doc.doc_id).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Doc ID {} out of bounds", doc.doc_id),
+                )
+            })?;
+            let reconstructed = segment
                 .templates
                 .get_template(doc.template_id)
                 .template
-                .reconstruct(&doc.term_ids, &self.dictionary)?;
+                .reconstruct(&doc.term_ids, &segment.dictionary)?;
             documents.push(reconstructed);
         }
 
         Ok(documents)
     }
 
-    /// Returns the term ids of each document
+    /// Retrieve JSON documents by their doc id, i.e. their position in the indexed input.
+    ///
+    /// The documents are reconstructed from the lines of their string leaves and their typed
+    /// columns. Keys keep their order in the document, so compact JSON is reproduced byte for
+    /// byte. Whitespace between tokens is dropped and numbers are reserialized, so other input
+    /// is equal but not necessarily identical.
+    pub fn retrieve_json(&self, doc_ids: &[u32]) -> io::Result<Vec<String>> {
+        let tree = self.schema_tree.as_ref().ok_or_else(not_json)?;
+        let segment_offset = |doc_id: u32| {
+            self.segment_for_json_doc_id(doc_id)
+                .map(|segment| segment.json_doc_offset)
+        };
+        let mut documents = Vec::with_capacity(doc_ids.len());
+        // The lines of consecutive documents of a segment are reconstructed together.
+        for docs in doc_ids.chunk_by(|a, b| segment_offset(*a) == segment_offset(*b)) {
+            let segment = self.segment_for_json_doc_id(docs[0]).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Doc ID {} out of bounds", docs[0]),
+                )
+            })?;
+            let json_docs = segment.json_docs.as_ref().ok_or_else(not_json)?;
+            let columns = segment.open_json_columns()?;
+            let line_ids: Vec<u32> = docs
+                .iter()
+                .flat_map(|doc_id| json_docs.lines(doc_id - segment.json_doc_offset))
+                .collect();
+            let mut lines = segment.retrieve_lines(&line_ids)?.into_iter();
+            for doc_id in docs {
+                let doc = doc_id - segment.json_doc_offset;
+                let doc_lines: Vec<String> =
+                    lines.by_ref().take(json_docs.lines(doc).len()).collect();
+                documents.push(json_docs.reconstruct(tree, doc, &doc_lines, &columns)?);
+            }
+        }
+        Ok(documents)
+    }
+
+    /// Returns the term ids of each document of a template in the segment at `segment_ord` that
+    /// contains `term_id` in any column.
+    ///
+    /// Only the columns of the blocks that may contain the term id are decompressed, the other
+    /// columns only for blocks with hits.
     pub fn search_in_zstd_column(
         &self,
-        match_fn: impl Fn(u32) -> bool,
+        term_id: u32,
+        segment_ord: usize,
         template_id: TemplateId,
         max_hits: Option<usize>,
     ) -> io::Result<Vec<Vec<u32>>> {
-        // The number of variables with num_docs will used to retrieve the other terms
-        // of a document.
-        let num_docs = self.templates.get_template(template_id).num_docs();
-        let columns: Columns = decompress_column(&self.folder, template_id, num_docs)?;
-
-        let mut documents_ids_hit = Vec::new();
-        for doc_id in columns.get_doc_ids(&match_fn) {
-            documents_ids_hit.push(doc_id);
-            if let Some(max) = max_hits
-                && documents_ids_hit.len() >= max
-            {
+        let segment = self.segments.get(segment_ord).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Segment {segment_ord} out of bounds"),
+            )
+        })?;
+        let column_file = segment.open_column_file(template_id)?;
+
+        let mut all_documents = Vec::new();
+        for block in 0..column_file.num_blocks() {
+            if max_hits.is_some_and(|max| all_documents.len() >= max) {
                 break;
             }
+            let may_contain = |column| column_file.block_may_contain(column, block, term_id);
+            if !(0..column_file.num_columns()).any(may_contain) {
+                continue;
+            }
+            let mut columns = column_file.read_block(block, may_contain)?;
+            let doc_ids = columns.get_doc_ids(term_id);
+            if doc_ids.is_empty() {
+                continue;
+            }
+            // Now we have the document IDs that contain the term ID.
+            // We need to retrieve the other termids of the documents.
+            column_file.read_missing_columns(&mut columns)?;
+            for doc_id in doc_ids {
+                if max_hits.is_some_and(|max| all_documents.len() >= max) {
+                    break;
+                }
+                all_documents.push(columns.get_term_ids(doc_id).collect());
+            }
         }
+        Ok(all_documents)
+    }
 
-        let mut all_documents = Vec::new();
-        // Now we have the document IDs that contain the term ID.
-        // We need to retrieve the other termids of the documents.
-        for doc_id in documents_ids_hit.iter() {
-            let document_terms = columns.get_term_ids(*doc_id).collect();
-            all_documents.push(document_terms);
+    /// Writes every indexed line in its original order to `writer`, each followed by `\n`.
+    ///
+    /// The output is identical to the indexed input. The last line has no `\n` if it was indexed
+    /// with [`IndexWriter::with_unterminated_last_line`].
+    ///
+    /// [`IndexWriter::with_unterminated_last_line`]: crate::indexing::IndexWriter::with_unterminated_last_line
+    ///
+    /// An index of JSON documents writes each document as one line of JSON instead, see
+    /// [`IndexInner::retrieve_json`].
+    pub fn decompress_all(&self, mut writer: impl Write) -> io::Result<()> {
+        for (segment_ord, segment) in self.segments.iter().enumerate() {
+            match (&self.schema_tree, &segment.json_docs) {
+                (Some(tree), Some(json_docs)) => {
+                    segment.decompress_json(tree, json_docs, &mut writer)?
+                }
+                _ => {
+                    // Only the end of the index is the end of the input.
+                    let is_last = segment_ord + 1 == self.segments.len();
+                    let last_line_unterminated = is_last && segment.last_line_unterminated;
+                    segment.decompress_all(&mut writer, last_line_unterminated)?
+                }
+            }
+        }
+        writer.flush()
+    }
+
+    /// Retrieve the document at the given line number of the indexed input.
+    ///
+    /// Returns `None` if the line number is out of range. In an index of JSON documents the
+    /// line number is the doc id of the document, blank lines of the input are not counted.
+    pub fn retrieve_by_line(&self, line: u32) -> io::Result<Option<String>> {
+        if self.schema_tree.is_some() {
+            if self.segment_for_json_doc_id(line).is_none() {
+                return Ok(None);
+            }
+            return Ok(self.retrieve_json(&[line])?.pop());
+        }
+        let Some(segment) = self.segment_for_doc_id(line) else {
+            return Ok(None);
+        };
+        match segment.doc_by_id(line)? {
+            Some(doc) => Ok(self.retrieve_doc(&[doc])?.pop()),
+            None => Ok(None),
         }
-        Ok(all_documents)
     }
 
     /// Compute total uncompressed bytes of all column files by reusing column
     /// opening via `decompress_column` and summing the decompressed values.
     pub fn uncompressed_columns_size(&self) -> io::Result<u64> {
         let mut total: u64 = 0;
-        for tpl in self.templates.iter() {
-            let num_docs = tpl.num_docs();
-            let columns: Columns = decompress_column(&self.folder, tpl.template_id, num_docs)?;
-            total += columns.size_in_bytes();
+        for segment in &self.segments {
+            for tpl in segment.templates.iter() {
+                total += segment.open_columns(tpl.template_id)?.size_in_bytes();
+            }
         }
         Ok(total)
     }
 }
+
+fn not_json() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the index doesn't contain JSON documents",
+    )
+}
+
+/// A segment opened for searching.
+pub(crate) struct Segment {
+    folder: PathBuf,
+    /// The global doc id of the first document in the segment.
+    pub(crate) doc_id_offset: u32,
+    pub(crate) num_docs: u32,
+    pub(crate) dictionary: Arc<Dict>,
+    pub(crate) templates: Templates,
+    /// How the files of the templates are compressed.
+    pub(crate) compression: Compression,
+    /// The JSON documents of the segment, in an index of JSON documents.
+    ///
+    /// Then the lines of the segment are the string leaves of the documents.
+    pub(crate) json_docs: Option<JsonDocs>,
+    /// The global doc id of the first JSON document in the segment.
+    pub(crate) json_doc_offset: u32,
+    /// The last line of the segment is written without `\n`.
+    pub(crate) last_line_unterminated: bool,
+}
+
+impl Segment {
+    pub(crate) fn open(
+        folder: PathBuf,
+        doc_id_offset: u32,
+        num_docs: u32,
+        codec: Codec,
+    ) -> io::Result<Self> {
+        let dictionary = Dict::new(&folder)?;
+        let compression = Compression::open(&folder, codec)?;
+        let templates = read_templates(&folder)?;
+        for (idx, template) in templates.iter().enumerate() {
+            assert_eq!(
+                idx, template.template_id.0 as usize,
+                "Template ID mismatch at index {idx}",
+            );
+        }
+        let templates = Templates { templates };
+        Ok(Segment {
+            folder,
+            doc_id_offset,
+            num_docs,
+            dictionary: Arc::new(dictionary),
+            templates,
+            compression,
+            json_docs: None,
+            json_doc_offset: 0,
+            last_line_unterminated: false,
+        })
+    }
+
+    /// The global doc id after the last document in the segment.
+    pub(crate) fn end_doc_id(&self) -> u32 {
+        self.doc_id_offset + self.num_docs
+    }
+
+    /// The global doc id after the last JSON document in the segment.
+    pub(crate) fn end_json_doc_id(&self) -> u32 {
+        let num_docs = self.json_docs.as_ref().map_or(0, JsonDocs::num_docs);
+        self.json_doc_offset + num_docs
+    }
+
+    /// Writes the lines of the segment in their original order, each followed by `\n` unless
+    /// it is the last line and `last_line_unterminated` is set.
+    fn decompress_all(
+        &self,
+        writer: &mut impl Write,
+        last_line_unterminated: bool,
+    ) -> io::Result<()> {
+        let mut remaining = self.num_docs;
+        self.for_each_line(|line| {
+            remaining -= 1;
+            if remaining > 0 || !last_line_unterminated {
+                line.push(b'\n');
+            }
+            writer.write_all(line)
+        })
+    }
+
+    /// Writes the JSON documents of the segment in their original order, one per line.
+    fn decompress_json(
+        &self,
+        tree: &SchemaTree,
+        json_docs: &JsonDocs,
+        writer: &mut impl Write,
+    ) -> io::Result<()> {
+        let columns = self.open_json_columns()?;
+        let mut doc = 0;
+        let mut lines = Vec::new();
+        // Writes the documents whose lines are complete, documents without string leaves have
+        // no lines.
+        let mut write_docs = |lines: &mut Vec<String>| -> io::Result<()> {
+            while doc < json_docs.num_docs() && json_docs.lines(doc).len() == lines.len() {
+                let json = json_docs.reconstruct(tree, doc, lines, &columns)?;
+                writer.write_all(json.as_bytes())?;
+                writer.write_all(b"\n")?;
+                lines.clear();
+                doc += 1;
+            }
+            Ok(())
+        };
+        write_docs(&mut lines)?;
+        self.for_each_line(|line| {
+            let line = String::from_utf8(line.clone())
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            lines.push(line);
+            write_docs(&mut lines)
+        })
+    }
+
+    /// Reconstructs the lines of the segment in their original order.
+    fn for_each_line(
+        &self,
+        mut on_line: impl FnMut(&mut Vec<u8>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        struct TemplateCursor {
+            template_id: TemplateId,
+            doc_ids: Vec<u32>,
+            columns: Columns,
+            row: usize,
+        }
+
+        let all_terms = self.dictionary.load_all_terms()?;
+        let mut cursors = Vec::new();
+        // Merge the templates by doc id, the rows of each template are sorted by doc id.
+        let mut heap = BinaryHeap::new();
+        for template in self.templates.iter() {
+            let doc_ids = self.open_doc_ids(template.template_id)?;
+            let Some(first_doc_id) = doc_ids.first() else {
+                continue;
+            };
+            heap.push(Reverse((*first_doc_id, cursors.len())));
+            cursors.push(TemplateCursor {
+                template_id: template.template_id,
+                columns: self.open_columns(template.template_id)?,
+                doc_ids,
+                row: 0,
+            });
+        }
+
+        let mut line = Vec::new();
+        let mut term_ids = Vec::new();
+        while let Some(Reverse((_, cursor_idx))) = heap.pop() {
+            let cursor = &mut cursors[cursor_idx];
+            term_ids.clear();
+            term_ids.extend(cursor.columns.get_term_ids(cursor.row as u32));
+            line.clear();
+            self.templates
+                .get_template(cursor.template_id)
+                .template
+                .reconstruct_into(
+                    &term_ids,
+                    |term_id, out| {
+                        let term = all_terms.get(term_id).ok_or_else(|| {
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("Term ID {term_id} out of bounds"),
+                            )
+                        })?;
+                        out.extend_from_slice(term);
+                        Ok(())
+                    },
+                    &mut line,
+                )?;
+            on_line(&mut line)?;
+
+            cursor.row += 1;
+            if let Some(next_doc_id) = cursor.doc_ids.get(cursor.row) {
+                heap.push(Reverse((*next_doc_id, cursor_idx)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reconstructs the lines with the given local doc ids, in the given order.
+    ///
+    /// The doc ids of each template that may contain a line are read once, and each block of
+    /// its columns at most once.
+    fn retrieve_lines(&self, doc_ids: &[u32]) -> io::Result<Vec<String>> {
+        // `(doc_id, position)` sorted by doc id, so the rows of each template are ascending.
+        let mut sorted: Vec<(u32, usize)> = doc_ids.iter().copied().zip(0..).collect();
+        sorted.sort_unstable();
+        let mut lines = vec![None; doc_ids.len()];
+        for template in self.templates.iter() {
+            let mut wanted = sorted
+                .iter()
+                .filter(|(doc_id, _)| template.may_contain_doc_id(*doc_id))
+                .peekable();
+            if wanted.peek().is_none() {
+                continue;
+            }
+            let template_doc_ids = self.open_doc_ids(template.template_id)?;
+            let column_file = self.open_column_file(template.template_id)?;
+            let mut block: Option<(usize, Columns)> = None;
+            for (doc_id, position) in wanted {
+                let Ok(row) = template_doc_ids.binary_search(doc_id) else {
+                    continue;
+                };
+                let block_idx = row / COLUMN_BLOCK_SIZE;
+                if block.as_ref().is_none_or(|(idx, _)| *idx != block_idx) {
+                    block = Some((block_idx, column_file.read_block(block_idx, |_| true)?));
+                }
+                let (_, columns) = block.as_ref().unwrap();
+                let term_ids: Vec<u32> = columns.get_term_ids(row as u32).collect();
+                lines[*position] =
+                    Some(template.template.reconstruct(&term_ids, &self.dictionary)?);
+            }
+        }
+        lines
+            .into_iter()
+            .zip(doc_ids)
+            .map(|(line, doc_id)| {
+                line.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Line {} not found", self.doc_id_offset + doc_id),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Find the template and term ids of a document by its global doc id.
+    fn doc_by_id(&self, doc_id: u32) -> io::Result<Option<Doc>> {
+        let local_doc_id = doc_id - self.doc_id_offset;
+        for template in self.templates.iter() {
+            if !template.may_contain_doc_id(local_doc_id) {
+                continue;
+            }
+            let doc_ids = self.open_doc_ids(template.template_id)?;
+            if let Ok(row) = doc_ids.binary_search(&local_doc_id) {
+                let columns = self
+                    .open_column_file(template.template_id)?
+                    .read_block(row / COLUMN_BLOCK_SIZE, |_| true)?;
+                return Ok(Some(Doc {
+                    doc_id,
+                    template_id: template.template_id,
+                    term_ids: columns.get_term_ids(row as u32).collect(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read the doc ids of the rows of a template, sorted ascending.
+    ///
+    /// The doc ids are local to the segment, add `doc_id_offset` to get the global doc id.
+    pub(crate) fn open_doc_ids(&self, template_id: TemplateId) -> io::Result<Vec<u32>> {
+        let num_docs = self.templates.get_template(template_id).num_docs();
+        read_doc_ids(&self.folder, template_id, num_docs, &self.compression)
+    }
+
+    /// Read the number stats of each column of a template.
+    pub(crate) fn open_column_stats(
+        &self,
+        template_id: TemplateId,
+    ) -> io::Result<Vec<Option<ColumnStats>>> {
+        read_column_stats(&self.folder, template_id)
+    }
+
+    /// Read the timestamp of each row of a template.
+    ///
+    /// Only templates with a `time_range` have a timestamp file.
+    pub(crate) fn open_timestamps(&self, template_id: TemplateId) -> io::Result<Vec<Option<i64>>> {
+        read_timestamps(&self.folder, template_id, &self.compression)
+    }
+
+    /// Decompress all columns of a template.
+    pub(crate) fn open_columns(&self, template_id: TemplateId) -> io::Result<Columns> {
+        let num_docs = self.templates.get_template(template_id).num_docs();
+        decompress_column(&self.folder, template_id, num_docs, &self.compression)
+    }
+
+    /// Read the schemas of the JSON documents of the segment.
+    pub(crate) fn open_json_docs(&self, tree: &SchemaTree) -> io::Result<JsonDocs> {
+        JsonDocs::open(&self.folder, tree, &self.compression)
+    }
+
+    /// Read the typed columns of the JSON documents of the segment, indexed by schema.
+    pub(crate) fn open_json_columns(&self) -> io::Result<Vec<Vec<LeafColumn>>> {
+        read_json_columns(&self.folder, &self.compression)
+    }
+
+    /// Read the column file of a template, to decompress only some of its columns.
+    pub(crate) fn open_column_file(&self, template_id: TemplateId) -> io::Result<ColumnFile> {
+        let num_docs = self.templates.get_template(template_id).num_docs();
+        ColumnFile::open(&self.folder, template_id, num_docs, &self.compression)
+    }
+}
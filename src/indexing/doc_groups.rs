@@ -32,11 +32,17 @@ impl DocGroupsByLen {
     /// Inserts a document
     ///
     /// * All documents with identical token types end up in the same group.
-    pub fn insert(&mut self, tokens: &[Token], line: &str, term_hash_map: &mut IndexingTermmap) {
+    pub fn insert(
+        &mut self,
+        tokens: &[Token],
+        line: &str,
+        doc_id: u32,
+        term_hash_map: &mut IndexingTermmap,
+    ) {
         let len = tokens.len();
         self.ensure_bucket(len);
         let entry = &mut self.group_by_token_len[len];
-        entry.insert(tokens, line, term_hash_map);
+        entry.insert(tokens, line, doc_id, term_hash_map);
     }
 
     /// Total number of *groups*.
@@ -48,6 +54,11 @@ impl DocGroupsByLen {
             .sum()
     }
 
+    /// Estimated heap memory of all groups in bytes.
+    pub fn mem_usage(&self) -> usize {
+        self.values().map(DocGroup::mem_usage).sum()
+    }
+
     /// Immutable iterator over *(GroupId, &PrelimDocGroup)*.
     pub(crate) fn iter(&self) -> impl Iterator<Item = (GroupId, &DocGroup)> {
         self.group_by_token_len
@@ -104,6 +115,35 @@ impl DocGroupsByLen {
             .flat_map(|b| b.values_mut())
     }
 
+    /// Removes all groups, keeping their ids.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (GroupId, DocGroup)> {
+        std::mem::take(&mut self.group_by_token_len)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(num_tokens, bucket)| {
+                bucket.into_groups().map(move |(id, group)| {
+                    (
+                        GroupId {
+                            num_tokens: num_tokens as u32,
+                            id,
+                        },
+                        group,
+                    )
+                })
+            })
+    }
+
+    /// Adds the rows of `group` to the group with the same id, or inserts it.
+    pub(crate) fn merge_group(
+        &mut self,
+        id: GroupId,
+        group: DocGroup,
+        term_hash_map: &mut IndexingTermmap,
+    ) {
+        self.ensure_bucket(id.num_tokens as usize);
+        self.group_by_token_len[id.num_tokens as usize].merge_group(id.id, group, term_hash_map);
+    }
+
     pub(crate) fn insert_new_group(&mut self, group: DocGroup) {
         let len = group.template.tokens.len();
         self.ensure_bucket(len);
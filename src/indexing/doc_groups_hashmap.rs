@@ -26,15 +26,21 @@ impl DocGroups {
     /// Inserts a document.
     ///
     /// * Every distinct **fingerprint** gets its own group.
-    pub fn insert(&mut self, tokens: &[Token], line: &str, term_hash_map: &mut IndexingTermmap) {
+    pub fn insert(
+        &mut self,
+        tokens: &[Token],
+        line: &str,
+        doc_id: u32,
+        term_hash_map: &mut IndexingTermmap,
+    ) {
         let id = fingerprint_tokens(tokens);
 
         match self.groups.get_mut(&id) {
             Some(entry) => {
-                entry.push(tokens, line, term_hash_map);
+                entry.push(tokens, line, doc_id, term_hash_map);
             }
             None => {
-                let group = DocGroup::new(tokens, line);
+                let group = DocGroup::new(tokens, line, doc_id);
                 self.groups.insert(id, group);
             }
         }
@@ -59,6 +65,26 @@ impl DocGroups {
         self.groups.insert(id, group);
     }
 
+    /// Adds the rows of `group` to the group with the fingerprint, or inserts it.
+    pub fn merge_group(
+        &mut self,
+        id: Fingerprint,
+        group: DocGroup,
+        term_hash_map: &mut IndexingTermmap,
+    ) {
+        match self.groups.get_mut(&id) {
+            Some(entry) => entry.merge(group, term_hash_map),
+            None => {
+                self.groups.insert(id, group);
+            }
+        }
+    }
+
+    /// Consuming iterator over *(GroupId, PrelimDocGroup)*.
+    pub fn into_groups(self) -> impl Iterator<Item = (Fingerprint, DocGroup)> {
+        self.groups.into_iter()
+    }
+
     /// Immutable iterator over *(GroupId, &PrelimDocGroup)*.
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (Fingerprint, &DocGroup)> {
@@ -1,39 +1,298 @@
 use std::{
-    io,
+    fs, io,
     path::{Path, PathBuf},
 };
 
+use fxhash::FxHashMap;
+
 use super::{
+    DocGroup,
     patterns::{assign_template_ids, merge_templates},
-    preliminary_index::preliminary_index,
+    preliminary_index::{preliminary_index_bounded, preliminary_index_parallel},
     term_id_idx_to_template_ids,
+    termmap::TermStore,
     write_dict::write_dictionary_and_generate_mapping,
 };
 use crate::{
-    columns::write::write_column_and_remap, constants::DICTIONARY_NAME,
-    indexing::patterns::split_templates, templates::write_templates,
+    columns::COLUMN_BLOCK_SIZE,
+    columns::write::{
+        group_column_samples, group_column_stats, template_postings, write_column_and_remap,
+        write_column_stats, write_doc_ids, write_timestamps,
+    },
+    constants::{DICTIONARY_NAME, SCHEMA_TREE_FILE_NAME, SEGMENTS_FILE_NAME},
+    indexing::merge::merge_segments,
+    indexing::patterns::split_templates,
+    json::JsonDocsWriter,
+    parallel::map_parallel,
+    schema::{SchemaTree, read_schema_tree, write_schema_tree},
+    segment::{SegmentMeta, read_segment_metas, write_new_segment, write_segment_metas},
+    settings::{Compression, IndexSettings, read_settings, write_settings},
+    templates::write_templates,
+    timestamp::{TimestampFormat, extract_timestamp},
+    tokenizer::Number,
 };
 
 /// IndexWriter is responsible for indxing log lines and writing the index to disk.
 pub struct IndexWriter {
     output_folder: PathBuf,
+    timestamp_formats: Vec<TimestampFormat>,
+    memory_budget: Option<usize>,
+    num_threads: usize,
+    postings_threshold: Option<usize>,
+    settings: Option<IndexSettings>,
+    last_line_unterminated: bool,
 }
 
 impl IndexWriter {
     /// Creates a new IndexWriter with the specified output folder.
+    ///
+    /// If the folder already contains an index, new documents are appended to it.
     pub fn new(output_folder: String) -> Self {
         IndexWriter {
             output_folder: output_folder.into(),
+            timestamp_formats: Vec::new(),
+            memory_budget: None,
+            num_threads: 1,
+            postings_threshold: None,
+            settings: None,
+            last_line_unterminated: false,
         }
     }
 
-    /// Indexes the provided lines and writes the index to disk.
+    /// Extract a timestamp from the start of each line, trying the formats in order.
+    ///
+    /// Without formats no timestamps are stored and time range queries match nothing.
+    pub fn with_timestamp_formats(mut self, formats: Vec<TimestampFormat>) -> Self {
+        self.timestamp_formats = formats;
+        self
+    }
+
+    /// Flush a segment whenever the in-memory index grows above `bytes`.
+    ///
+    /// The budget covers the term map and the columns, which hold nearly all memory used while
+    /// indexing. Without a budget all lines passed to [`IndexWriter::index`] become one segment.
+    ///
+    /// With several threads the budget is only checked after each round of one shard of 16384
+    /// lines per thread, so a segment may exceed it by the memory of one round.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Tokenize, group and compress on `num_threads` threads, 1 by default.
+    ///
+    /// The input is split into shards which are indexed independently and then merged, so the
+    /// doc ids don't depend on the number of threads.
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
+    }
+
+    /// Store the rows of each term that is in at most `max_rows` rows of a template.
+    ///
+    /// The rows are stored in the dictionary next to the templates of the term. Searching such a
+    /// term then only reads the blocks of its rows instead of checking every row of the
+    /// template. Without a threshold no postings are written.
+    ///
+    /// The threshold is stored with the index like [`IndexWriter::with_settings`], so appends
+    /// and merges keep writing postings.
+    pub fn with_postings_threshold(mut self, max_rows: usize) -> Self {
+        self.postings_threshold = Some(max_rows);
+        self
+    }
+
+    /// Whether the last line passed to [`IndexWriter::index`] had no trailing `\n` in the input.
+    ///
+    /// [`IndexInner::decompress_all`](crate::index::IndexInner::decompress_all) then writes it
+    /// without one, so an input without a final newline is restored byte for byte.
+    pub fn with_unterminated_last_line(mut self, unterminated: bool) -> Self {
+        self.last_line_unterminated = unterminated;
+        self
+    }
+
+    /// Compress the index with the given settings instead of the defaults.
+    ///
+    /// The settings are stored when the index is created. Appending to an existing index uses
+    /// its stored settings, and fails if they differ from the given ones.
+    pub fn with_settings(mut self, settings: IndexSettings) -> Self {
+        self.settings = Some(settings);
+        self
+    }
+
+    /// Indexes the provided lines as new segments and adds them to the index.
+    ///
+    /// The doc ids of the lines continue after the last document of the index. Each segment is
+    /// added to the index once it is written. Nothing is added if there are no lines.
     pub fn index<T: Into<String>>(
         &self,
         lines: impl Iterator<Item = T>,
         _report: bool,
     ) -> io::Result<()> {
-        let mut preliminary_index = preliminary_index(lines);
+        fs::create_dir_all(&self.output_folder)?;
+        if self.output_folder.join(SCHEMA_TREE_FILE_NAME).exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the index contains JSON documents, use index_json",
+            ));
+        }
+        let mut segment_metas = self.read_segment_metas()?;
+        let settings = self.open_settings(!segment_metas.is_empty())?;
+        let mut lines = lines.peekable();
+        while lines.peek().is_some() {
+            let mut meta = SegmentMeta::create(&self.output_folder, &segment_metas)?;
+            let segment_folder = meta.folder(&self.output_folder);
+            write_new_segment(&segment_folder, || {
+                (meta.num_docs, _) =
+                    self.write_segment(&segment_folder, &settings, &mut lines, self.memory_budget)?;
+                meta.last_line_unterminated = self.last_line_unterminated && lines.peek().is_none();
+                segment_metas.push(meta);
+                write_segment_metas(&self.output_folder, &segment_metas)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Indexes the provided NDJSON lines, each a JSON object, as new segments.
+    ///
+    /// Each document is split into its leaves with the schema tree of the index. The string
+    /// leaves are indexed like log lines, while the schema and the other leaves of each document
+    /// are stored in typed columns. Search the documents with
+    /// [`Searcher::search_json`](crate::search::Searcher::search_json) and reconstruct them
+    /// with [`IndexInner::retrieve_json`](crate::index::IndexInner::retrieve_json).
+    ///
+    /// Blank lines are skipped, the doc ids of the documents continue after the last document of
+    /// the index. With a memory budget a segment is written whenever the buffered string leaves
+    /// and the typed columns of its documents exceed the budget. Fails if a line is not a JSON
+    /// object, or if the index contains log lines.
+    pub fn index_json<T: AsRef<str>>(&self, lines: impl Iterator<Item = T>) -> io::Result<()> {
+        fs::create_dir_all(&self.output_folder)?;
+        let mut segment_metas = self.read_segment_metas()?;
+        let mut tree = match read_schema_tree(&self.output_folder)? {
+            Some(tree) => tree,
+            None if segment_metas.is_empty() => SchemaTree::new(),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the index contains log lines, use index",
+                ));
+            }
+        };
+        let settings = self.open_settings(!segment_metas.is_empty())?;
+        let mut lines = lines
+            .filter(|line| !line.as_ref().trim().is_empty())
+            .peekable();
+        while lines.peek().is_some() {
+            let mut json_docs = JsonDocsWriter::default();
+            let mut string_leaves = Vec::new();
+            let mut string_leaves_size = 0;
+            for line in lines.by_ref() {
+                let num_string_leaves = string_leaves.len();
+                json_docs.add(&mut tree, line.as_ref(), &mut string_leaves)?;
+                string_leaves_size += string_leaves[num_string_leaves..]
+                    .iter()
+                    .map(|text| size_of::<String>() + text.len())
+                    .sum::<usize>();
+                if self
+                    .memory_budget
+                    .is_some_and(|budget| string_leaves_size + json_docs.mem_usage() > budget)
+                {
+                    break;
+                }
+            }
+
+            let mut meta = SegmentMeta::create(&self.output_folder, &segment_metas)?;
+            let segment_folder = meta.folder(&self.output_folder);
+            write_new_segment(&segment_folder, || {
+                // A document must not be split across segments, so the budget is applied above.
+                let (num_lines, compression) = self.write_segment(
+                    &segment_folder,
+                    &settings,
+                    &mut string_leaves.into_iter(),
+                    None,
+                )?;
+                meta.num_docs = num_lines;
+                json_docs.write(&segment_folder, &compression)?;
+                // The tree only grows, so it can be written before the segment is added.
+                write_schema_tree(&self.output_folder, &tree)?;
+                segment_metas.push(meta);
+                write_segment_metas(&self.output_folder, &segment_metas)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Merges all segments of the index into one.
+    ///
+    /// Searching a single segment is faster, as every query is resolved once per segment.
+    pub fn merge_segments(&self) -> io::Result<()> {
+        merge_segments(&self.output_folder)
+    }
+
+    fn read_segment_metas(&self) -> io::Result<Vec<SegmentMeta>> {
+        if self.output_folder.join(SEGMENTS_FILE_NAME).exists() {
+            read_segment_metas(&self.output_folder)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Returns the stored settings of an existing index, or stores the settings of the writer
+    /// for a new index.
+    ///
+    /// Indexes created before settings were stored use the default settings.
+    fn open_settings(&self, index_exists: bool) -> io::Result<IndexSettings> {
+        let settings = self.settings.map(|settings| IndexSettings {
+            postings_threshold: self.postings_threshold.or(settings.postings_threshold),
+            ..settings
+        });
+        if !index_exists {
+            let settings = settings.unwrap_or(IndexSettings {
+                postings_threshold: self.postings_threshold,
+                ..IndexSettings::default()
+            });
+            write_settings(&self.output_folder, &settings)?;
+            return Ok(settings);
+        }
+        let stored = read_settings(&self.output_folder)?;
+        if settings.is_some_and(|settings| settings != stored)
+            || self
+                .postings_threshold
+                .is_some_and(|max_rows| stored.postings_threshold != Some(max_rows))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the index was created with different settings: {stored:?}"),
+            ));
+        }
+        Ok(stored)
+    }
+
+    /// Writes the dictionary, templates and columns of the lines and returns the number of lines
+    /// and the compression of the segment.
+    ///
+    /// Stops taking lines once the memory budget is exceeded.
+    fn write_segment<T: Into<String>>(
+        &self,
+        segment_folder: &Path,
+        settings: &IndexSettings,
+        lines: &mut impl Iterator<Item = T>,
+        memory_budget: Option<usize>,
+    ) -> io::Result<(u32, Compression)> {
+        let mut num_docs = 0;
+        let mut timestamps: Vec<Option<i64>> = Vec::new();
+        let lines = lines.map(|line| {
+            let line: String = line.into();
+            num_docs += 1;
+            if !self.timestamp_formats.is_empty() {
+                timestamps.push(extract_timestamp(&line, &self.timestamp_formats));
+            }
+            line
+        });
+        let mut preliminary_index = if self.num_threads > 1 {
+            preliminary_index_parallel(lines, memory_budget, self.num_threads)
+        } else {
+            preliminary_index_bounded(lines, memory_budget)
+        };
         // More templates
         if std::env::var("ST").is_ok() {
             split_templates(&mut preliminary_index);
@@ -45,20 +304,109 @@ impl IndexWriter {
             preliminary_index.print_stats();
         }
         assign_template_ids(&mut preliminary_index);
+        if !timestamps.is_empty() {
+            for group in preliminary_index.doc_groups.values_mut() {
+                group.template.time_range = time_range(&group.doc_ids, &timestamps);
+            }
+        }
         let term_id_idx = term_id_idx_to_template_ids(&preliminary_index);
+        let groups: Vec<&DocGroup> = preliminary_index.doc_groups.values().collect();
+        let postings = match settings.postings_threshold {
+            Some(max_rows) => self.postings(&groups, max_rows),
+            None => FxHashMap::default(),
+        };
 
         // Write the dictionary and generate a mapping from old term IDs to new term IDs.
         let old_to_new_id_map = write_dictionary_and_generate_mapping(
-            &self.output_folder.join(DICTIONARY_NAME),
+            &segment_folder.join(DICTIONARY_NAME),
             &preliminary_index.term_hash_map.regular,
             &term_id_idx,
+            &postings,
+            settings.postings_threshold,
         )?;
+        drop(postings);
 
-        write_templates(&preliminary_index, Path::new(&self.output_folder))?;
+        write_templates(&preliminary_index, segment_folder)?;
 
-        for group in preliminary_index.doc_groups.values() {
-            write_column_and_remap(&self.output_folder, group, &old_to_new_id_map)?;
+        let number_values = number_values(&preliminary_index.term_hash_map.regular);
+        let mut compression = Compression::new(settings.codec);
+        if let Some(max_size) = settings.zstd_dictionary_size {
+            let mut samples = Vec::new();
+            for group in groups.iter().filter(|group| is_small(group.doc_ids.len())) {
+                group_column_samples(group, &old_to_new_id_map, &mut samples)?;
+            }
+            compression = compression.train(segment_folder, &samples, max_size)?;
         }
-        Ok(())
+        let write_group = |group: &&DocGroup| -> io::Result<()> {
+            write_column_and_remap(segment_folder, group, &old_to_new_id_map, &compression)?;
+            let template_id = group.template.template_id;
+            write_doc_ids(segment_folder, template_id, &group.doc_ids, &compression)?;
+            let stats = group_column_stats(group, &number_values);
+            write_column_stats(segment_folder, template_id, &stats)?;
+            if group.template.time_range.is_some() {
+                let group_timestamps = group
+                    .doc_ids
+                    .iter()
+                    .map(|doc_id| timestamps[*doc_id as usize]);
+                write_timestamps(segment_folder, template_id, group_timestamps, &compression)?;
+            }
+            Ok(())
+        };
+        map_parallel(&groups, self.num_threads, write_group)
+            .into_iter()
+            .collect::<io::Result<()>>()?;
+        Ok((num_docs, compression))
+    }
+
+    /// Returns the template ids and rows of the templates where each term is in at most
+    /// `max_rows` rows, by the term IDs of the groups.
+    fn postings(
+        &self,
+        groups: &[&DocGroup],
+        max_rows: usize,
+    ) -> FxHashMap<u32, Vec<(u32, Vec<u32>)>> {
+        let group_postings = map_parallel(groups, self.num_threads, |group| {
+            let columns: Vec<&[u32]> = group.iter_columns().collect();
+            template_postings(&columns, max_rows, |term_id| term_id)
+        });
+        let mut postings: FxHashMap<u32, Vec<(u32, Vec<u32>)>> = FxHashMap::default();
+        for (group, group_postings) in groups.iter().zip(group_postings) {
+            let template_id = group.template.template_id.0;
+            for (term_id, rows) in group_postings {
+                postings
+                    .entry(term_id)
+                    .or_default()
+                    .push((template_id, rows));
+            }
+        }
+        postings
+    }
+}
+
+/// Returns true if a template with `num_docs` rows fits into a single column block.
+///
+/// The zstd dictionary of a segment is trained on the blocks of the small templates only, as
+/// larger blocks compress well on their own.
+pub(crate) fn is_small(num_docs: usize) -> bool {
+    num_docs <= COLUMN_BLOCK_SIZE
+}
+
+/// Returns the min and max timestamp of the docs.
+fn time_range(doc_ids: &[u32], timestamps: &[Option<i64>]) -> Option<(i64, i64)> {
+    doc_ids
+        .iter()
+        .filter_map(|doc_id| timestamps[*doc_id as usize])
+        .fold(None, |range, timestamp| match range {
+            Some((min, max)) => Some((timestamp.min(min), timestamp.max(max))),
+            None => Some((timestamp, timestamp)),
+        })
+}
+
+/// Returns the number value of each term, indexed by term ID.
+fn number_values(term_store: impl TermStore) -> Vec<Option<u64>> {
+    let mut values = vec![None; term_store.num_terms()];
+    for (term, term_id) in term_store.iter() {
+        values[term_id as usize] = Number::term_value(term);
     }
+    values
 }
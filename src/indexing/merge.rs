@@ -0,0 +1,358 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use fxhash::FxHashMap;
+
+use super::write_dict::{PostingsMerger, TermInfo, TermInfoSSTable};
+use crate::TemplateId;
+use crate::columns::ColumnStats;
+use crate::columns::write::{
+    column_samples, write_column_stats, write_columns, write_doc_ids, write_timestamps,
+};
+use crate::constants::DICTIONARY_NAME;
+use crate::dict::AllTerms;
+use crate::index::Segment;
+use crate::indexing::index_writer::is_small;
+use crate::json::JsonDocsWriter;
+use crate::schema::read_schema_tree;
+use crate::segment::{SegmentMeta, read_segment_metas, write_new_segment, write_segment_metas};
+use crate::settings::{Compression, IndexSettings, read_settings};
+use crate::templates::{Template, TemplateWithId, write_template_list};
+
+/// A template of the merged segment and the templates of the source segments it combines.
+struct MergedTemplate<'a> {
+    template: &'a Template,
+    /// `(segment_ord, template_id)` of each source, in doc id order.
+    sources: Vec<(usize, TemplateId)>,
+}
+
+/// Merges all segments of the index into a single segment.
+///
+/// Identical templates of different segments become one template, and the dictionaries are
+/// combined with the term ids of the columns remapped. Doc ids are unchanged.
+///
+/// The new segment replaces the old ones in the segment list before they are deleted, so
+/// readers opening the index see either the old or the new segments.
+///
+/// The merged segment uses the settings of the index, with a zstd dictionary trained for it. The
+/// postings of the terms are merged too if the index has a postings threshold.
+///
+/// In an index of JSON documents the schemas and typed columns of the documents are merged
+/// too, their lines are merged like any other lines.
+pub(crate) fn merge_segments(index_folder: &Path) -> io::Result<()> {
+    let segment_metas = read_segment_metas(index_folder)?;
+    if segment_metas.len() <= 1 {
+        return Ok(());
+    }
+    let settings = read_settings(index_folder)?;
+    let mut segments = Vec::new();
+    let mut doc_id_offset = 0;
+    for meta in &segment_metas {
+        let folder = meta.folder(index_folder);
+        segments.push(Segment::open(
+            folder,
+            doc_id_offset,
+            meta.num_docs,
+            settings.codec,
+        )?);
+        doc_id_offset += meta.num_docs;
+    }
+
+    // Unify the templates, `template_id_maps[segment_ord][old_id]` is the new id.
+    let mut merged_templates: Vec<MergedTemplate> = Vec::new();
+    let mut template_ids: FxHashMap<&Template, TemplateId> = FxHashMap::default();
+    let mut template_id_maps: Vec<Vec<TemplateId>> = Vec::new();
+    for (segment_ord, segment) in segments.iter().enumerate() {
+        let mut template_id_map = Vec::new();
+        for template in segment.templates.iter() {
+            let new_id = *template_ids.entry(&template.template).or_insert_with(|| {
+                merged_templates.push(MergedTemplate {
+                    template: &template.template,
+                    sources: Vec::new(),
+                });
+                TemplateId(merged_templates.len() as u32 - 1)
+            });
+            merged_templates[new_id.0 as usize]
+                .sources
+                .push((segment_ord, template.template_id));
+            template_id_map.push(new_id);
+        }
+        template_id_maps.push(template_id_map);
+    }
+
+    let mut meta = SegmentMeta::create(index_folder, &segment_metas)?;
+    meta.num_docs = doc_id_offset;
+    meta.last_line_unterminated = segment_metas
+        .last()
+        .is_some_and(|meta| meta.last_line_unterminated);
+    let folder = meta.folder(index_folder);
+    write_new_segment(&folder, || {
+        write_merged_segment(
+            index_folder,
+            &folder,
+            &segments,
+            &merged_templates,
+            &template_id_maps,
+            &settings,
+        )?;
+        write_segment_metas(index_folder, &[meta])
+    })?;
+    for meta in &segment_metas {
+        fs::remove_dir_all(meta.folder(index_folder))?;
+    }
+    Ok(())
+}
+
+/// Writes the dictionary, templates, columns and JSON documents of the merged segment.
+fn write_merged_segment(
+    index_folder: &Path,
+    folder: &Path,
+    segments: &[Segment],
+    merged_templates: &[MergedTemplate],
+    template_id_maps: &[Vec<TemplateId>],
+    settings: &IndexSettings,
+) -> io::Result<()> {
+    let term_id_maps = merge_dictionaries(
+        folder,
+        segments,
+        merged_templates,
+        template_id_maps,
+        settings.postings_threshold,
+    )?;
+
+    let mut compression = Compression::new(settings.codec);
+    // The columns of the small templates, kept from sampling so they are decompressed once.
+    let mut small_columns: Vec<Option<Vec<Vec<u32>>>> = vec![None; merged_templates.len()];
+    if let Some(max_size) = settings.zstd_dictionary_size {
+        let mut samples = Vec::new();
+        for (merged, small_columns) in merged_templates.iter().zip(&mut small_columns) {
+            let num_docs: usize = merged
+                .sources
+                .iter()
+                .map(|(segment_ord, template_id)| {
+                    segments[*segment_ord]
+                        .templates
+                        .get_template(*template_id)
+                        .num_docs()
+                })
+                .sum();
+            if is_small(num_docs) {
+                let columns = merged_columns(merged, segments, &term_id_maps)?;
+                column_samples(
+                    columns.iter().map(|column| column.iter().copied()),
+                    &mut samples,
+                )?;
+                *small_columns = Some(columns);
+            }
+        }
+        compression = compression.train(folder, &samples, max_size)?;
+    }
+
+    let mut templates = Vec::with_capacity(merged_templates.len());
+    for (new_id, merged) in merged_templates.iter().enumerate() {
+        let template_id = TemplateId(new_id as u32);
+        let sources: Vec<(&Segment, &TemplateWithId)> = merged
+            .sources
+            .iter()
+            .map(|(segment_ord, template_id)| {
+                let segment = &segments[*segment_ord];
+                let template = segment.templates.get_template(*template_id);
+                (segment, template)
+            })
+            .collect();
+
+        let columns = match small_columns[new_id].take() {
+            Some(columns) => columns,
+            None => merged_columns(merged, segments, &term_id_maps)?,
+        };
+        let mut doc_ids = Vec::new();
+        let mut stats: Option<Vec<Option<ColumnStats>>> = None;
+        for (segment, template) in &sources {
+            doc_ids.extend(
+                segment
+                    .open_doc_ids(template.template_id)?
+                    .into_iter()
+                    .map(|doc_id| segment.doc_id_offset + doc_id),
+            );
+            let source_stats = segment.open_column_stats(template.template_id)?;
+            stats = Some(match stats {
+                Some(stats) => stats
+                    .into_iter()
+                    .zip(source_stats)
+                    .map(|(stats, source_stats)| ColumnStats::merge(stats, source_stats))
+                    .collect(),
+                None => source_stats,
+            });
+        }
+        let (Some(&first_doc_id), Some(&last_doc_id)) = (doc_ids.first(), doc_ids.last()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("template {new_id} of the merged segment has no documents"),
+            ));
+        };
+        write_columns(folder, template_id, &columns, &compression)?;
+        write_doc_ids(folder, template_id, &doc_ids, &compression)?;
+        write_column_stats(folder, template_id, &stats.unwrap_or_default())?;
+
+        let time_range = sources
+            .iter()
+            .filter_map(|(_, template)| template.time_range)
+            .reduce(|(min1, max1), (min2, max2)| (min1.min(min2), max1.max(max2)));
+        if time_range.is_some() {
+            let mut timestamps = Vec::with_capacity(doc_ids.len());
+            for (segment, template) in &sources {
+                if template.time_range.is_some() {
+                    timestamps.extend(segment.open_timestamps(template.template_id)?);
+                } else {
+                    timestamps.extend(std::iter::repeat_n(None, template.num_docs()));
+                }
+            }
+            write_timestamps(folder, template_id, timestamps, &compression)?;
+        }
+
+        templates.push(TemplateWithId {
+            num_docs: doc_ids.len(),
+            doc_id_range: (first_doc_id, last_doc_id),
+            time_range,
+            template_id,
+            template: merged.template.clone(),
+        });
+    }
+    write_template_list(&templates, folder)?;
+
+    if let Some(tree) = read_schema_tree(index_folder)? {
+        let mut json_docs = JsonDocsWriter::default();
+        for segment in segments {
+            json_docs.append(
+                &segment.open_json_docs(&tree)?,
+                segment.open_json_columns()?,
+            );
+        }
+        json_docs.write(folder, &compression)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the columns of a merged template, with the rows of the sources in order and the term
+/// ids remapped to the merged dictionary.
+fn merged_columns(
+    merged: &MergedTemplate,
+    segments: &[Segment],
+    term_id_maps: &[Vec<u32>],
+) -> io::Result<Vec<Vec<u32>>> {
+    let num_columns = merged
+        .template
+        .column_indices()
+        .into_iter()
+        .flatten()
+        .count();
+    let mut columns = vec![Vec::new(); num_columns];
+    for (segment_ord, template_id) in &merged.sources {
+        let term_id_map = &term_id_maps[*segment_ord];
+        let source_columns = segments[*segment_ord].open_columns(*template_id)?;
+        for (column, source_column) in columns.iter_mut().zip(source_columns.iter_columns()) {
+            column.extend(
+                source_column
+                    .iter()
+                    .map(|term_id| term_id_map[term_id as usize]),
+            );
+        }
+    }
+    Ok(columns)
+}
+
+/// Writes the union of the dictionaries of the segments, with the template ids remapped.
+///
+/// The rows of the sources of a merged template follow each other, so the postings of a term in
+/// a merged template are the postings of its sources with their rows shifted. They are kept if
+/// every source containing the term has postings and there are at most `max_rows` rows.
+///
+/// Returns for each segment the mapping from its term ids to the new term ids.
+fn merge_dictionaries(
+    folder: &Path,
+    segments: &[Segment],
+    merged_templates: &[MergedTemplate],
+    template_id_maps: &[Vec<TemplateId>],
+    max_rows: Option<usize>,
+) -> io::Result<Vec<Vec<u32>>> {
+    let mut all_terms: Vec<AllTerms> = Vec::new();
+    let mut all_term_infos: Vec<Vec<TermInfo>> = Vec::new();
+    for segment in segments {
+        all_terms.push(segment.dictionary.load_all_terms()?);
+        all_term_infos.push(segment.dictionary.load_all_term_infos()?);
+    }
+    // `row_offsets[segment_ord][old_id]` is the first row of the source in its merged template.
+    let mut row_offsets: Vec<Vec<u32>> = segments
+        .iter()
+        .map(|segment| vec![0; segment.templates.iter().count()])
+        .collect();
+    for merged in merged_templates {
+        let mut num_rows = 0;
+        for (segment_ord, template_id) in &merged.sources {
+            row_offsets[*segment_ord][template_id.0 as usize] = num_rows;
+            num_rows += segments[*segment_ord]
+                .templates
+                .get_template(*template_id)
+                .num_docs() as u32;
+        }
+    }
+    let mut term_id_maps: Vec<Vec<u32>> = all_terms
+        .iter()
+        .map(|terms| Vec::with_capacity(terms.len()))
+        .collect();
+
+    let wtr = BufWriter::new(File::create(folder.join(DICTIONARY_NAME))?);
+    let mut builder = tantivy_sstable::Dictionary::<TermInfoSSTable>::builder(wtr)?;
+
+    // The terms of each dictionary are sorted, so a k-way merge yields all terms in order.
+    let mut heap = BinaryHeap::new();
+    for (segment_ord, terms) in all_terms.iter().enumerate() {
+        if let Some(term) = terms.get(0) {
+            heap.push(Reverse((term, segment_ord)));
+        }
+    }
+    let mut new_term_id = 0;
+    let mut term_info = TermInfo::default();
+    let mut postings_merger = PostingsMerger::default();
+    while let Some(Reverse((term, _))) = heap.peek().copied() {
+        term_info.template_ids.clear();
+        term_info.postings.clear();
+        while let Some(Reverse((next_term, segment_ord))) = heap.peek().copied()
+            && next_term == term
+        {
+            heap.pop();
+            let term_id_map = &mut term_id_maps[segment_ord];
+            let old_term_id = term_id_map.len();
+            term_id_map.push(new_term_id);
+            let source = &all_term_infos[segment_ord][old_term_id];
+            for template_id in &source.template_ids {
+                let new_template_id = template_id_maps[segment_ord][*template_id as usize].0;
+                term_info.template_ids.push(new_template_id);
+                if max_rows.is_none() {
+                    continue;
+                }
+                let offset = row_offsets[segment_ord][*template_id as usize];
+                let rows = source
+                    .postings
+                    .binary_search_by_key(template_id, |(template_id, _)| *template_id)
+                    .ok()
+                    .map(|index| source.postings[index].1.iter().map(|row| offset + row));
+                postings_merger.add(new_template_id, rows);
+            }
+            if let Some(next_term) = all_terms[segment_ord].get(old_term_id as u32 + 1) {
+                heap.push(Reverse((next_term, segment_ord)));
+            }
+        }
+        term_info.template_ids.sort_unstable();
+        term_info.template_ids.dedup();
+        postings_merger.finish(max_rows, &mut term_info.postings);
+        builder.insert(term, &term_info)?;
+        new_term_id += 1;
+    }
+    builder.finish().map_err(io::Error::other)?;
+    Ok(term_id_maps)
+}
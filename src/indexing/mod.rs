@@ -2,6 +2,7 @@ pub(crate) mod doc_groups;
 pub(crate) mod doc_groups_hashmap;
 pub(crate) mod fingerprint;
 pub(crate) mod index_writer;
+pub(crate) mod merge;
 pub(crate) mod patterns;
 /// Indexes the input lines into a preliminary index structure.
 pub(crate) mod preliminary_index;
@@ -3,6 +3,7 @@ use fxhash::{FxHashMap, FxHashSet};
 use crate::indexing::{
     ConstTemplateToken, DocGroup, GroupId, IndexingTemplateToken, PreliminaryIndex,
 };
+use crate::tokenizer::TokenType;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 // Token groups that can be merged in a template
@@ -11,6 +12,10 @@ enum MergeableTokenGroup {
     Constant(Vec<u8>),
     // Can always be merged currently
     Variable,
+    // Without `number_as_string` numbers are stored as u64 bytes. They can only be turned back
+    // into text if the column is known to hold numbers, so they are not merged with other types.
+    #[cfg(not(feature = "number_as_string"))]
+    NumberVariable,
 }
 
 impl MergeableTokenGroup {
@@ -18,14 +23,26 @@ impl MergeableTokenGroup {
         match token {
             IndexingTemplateToken::Constant(constant_token) => {
                 if num_docs < 1000 {
-                    MergeableTokenGroup::Variable
+                    Self::variable(constant_token.token_type)
                 } else {
                     MergeableTokenGroup::Constant(constant_token.text.to_vec())
                 }
             }
-            IndexingTemplateToken::Variable { .. } => MergeableTokenGroup::Variable,
+            IndexingTemplateToken::Variable { token_type, .. } => Self::variable(*token_type),
         }
     }
+
+    fn variable(_token_type: TokenType) -> Self {
+        #[cfg(not(feature = "number_as_string"))]
+        if _token_type == TokenType::Number {
+            return MergeableTokenGroup::NumberVariable;
+        }
+        MergeableTokenGroup::Variable
+    }
+
+    fn is_variable(&self) -> bool {
+        !matches!(self, MergeableTokenGroup::Constant(_))
+    }
 }
 
 /// NOTE: This does not improve compression.
@@ -188,7 +205,7 @@ pub fn merge_templates(index: &mut PreliminaryIndex) {
         }
         // At first convert const tokens to variable ones
         for (token_idx, token_group_type) in token_group.iter().enumerate() {
-            if let MergeableTokenGroup::Variable = token_group_type {
+            if token_group_type.is_variable() {
                 // Iterate over the indices and convert the constant tokens to variable tokens
                 for &idx in &group_id {
                     let group = &mut index.doc_groups.get_mut(idx).unwrap();
@@ -211,7 +228,12 @@ pub fn merge_templates(index: &mut PreliminaryIndex) {
 
 pub fn assign_template_ids(index: &mut PreliminaryIndex) {
     for (template_id, group) in index.doc_groups.values_mut().enumerate() {
+        group.sort_rows_by_doc_id();
         group.template.template_id = (template_id as u32).into();
         group.template.num_docs = group.num_docs;
+        group.template.doc_id_range = (
+            group.doc_ids.first().copied().unwrap_or_default(),
+            group.doc_ids.last().copied().unwrap_or_default(),
+        );
     }
 }
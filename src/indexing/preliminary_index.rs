@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 use crate::TemplateId;
 use crate::indexing::DocGroupsByLen;
 use crate::indexing::termmap::TermStore;
+use crate::parallel::map_parallel;
 use crate::tokenizer::{Token, TokenType, TokenTypeTrait, Tokenizer};
 use stacker::fastcmp::fast_short_slice_compare;
 
@@ -14,6 +15,10 @@ use super::termmap::IndexingTermmap;
 pub(crate) struct IndexingTemplate {
     pub template_id: TemplateId,
     pub num_docs: usize,
+    /// The first and last doc id (line number) in this template.
+    pub doc_id_range: (u32, u32),
+    /// The min and max timestamp of the documents, if timestamps are extracted.
+    pub time_range: Option<(i64, i64)>,
     pub tokens: Vec<TemplateTokenWithPos>,
 }
 
@@ -37,7 +42,7 @@ pub(crate) enum IndexingTemplateToken {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Hash, Eq)]
 pub(crate) struct ConstTemplateToken {
     pub(crate) token_type: TokenType,
-    // u64 LE bytes for numbers (with feature_flag `number_as_string`)
+    // The binary term of numbers without the feature flag `number_as_string`, see `Number`
     // String for: words
     pub(crate) text: Vec<u8>,
 }
@@ -74,12 +79,42 @@ impl IndexingTemplateToken {
 }
 
 /// A preliminary index that contains the term hash map and document groups.
+#[derive(Default)]
 pub struct PreliminaryIndex {
     pub(crate) term_hash_map: IndexingTermmap,
     /// Document groups, keyed by the token length.
     pub doc_groups: DocGroupsByLen,
 }
 impl PreliminaryIndex {
+    /// Estimated memory of the term map and the columns in bytes.
+    pub(crate) fn mem_usage(&self) -> usize {
+        self.term_hash_map.mem_usage() + self.doc_groups.mem_usage()
+    }
+
+    /// Appends the documents of `other`, which must have higher doc ids.
+    ///
+    /// The terms of `other` are added to the term map and its columns remapped, groups with the
+    /// same token types are merged.
+    pub(crate) fn append(&mut self, mut other: PreliminaryIndex) {
+        if self.doc_groups.num_groups() == 0 {
+            *self = other;
+            return;
+        }
+        let old_to_new_id_map = self
+            .term_hash_map
+            .regular
+            .append_terms(&other.term_hash_map.regular);
+        for (id, mut group) in other.doc_groups.drain() {
+            for column in group.columns.iter_mut() {
+                for term_id in column.iter_mut() {
+                    *term_id = old_to_new_id_map[*term_id as usize];
+                }
+            }
+            self.doc_groups
+                .merge_group(id, group, &mut self.term_hash_map);
+        }
+    }
+
     pub(crate) fn iter_templates(&self) -> impl Iterator<Item = &IndexingTemplate> {
         self.doc_groups.values().map(|group| &group.template)
     }
@@ -197,6 +232,8 @@ pub(crate) struct DocGroup {
     /// Tokens of the first document in this group. We use it to compare token types
     //pub tokens: Vec<Token>,
     pub(crate) columns: Vec<Vec<u32>>,
+    /// The doc id (line number in the input) of each row.
+    pub(crate) doc_ids: Vec<u32>,
     pub(crate) num_docs: usize,
 }
 
@@ -205,6 +242,13 @@ impl DocGroup {
         self.columns.iter().map(|c| c.len()).sum()
     }
 
+    /// Estimated heap memory of the group in bytes.
+    pub(crate) fn mem_usage(&self) -> usize {
+        let columns: usize = self.columns.iter().map(|c| c.capacity()).sum();
+        let tokens = self.template.tokens.capacity() * std::mem::size_of::<TemplateTokenWithPos>();
+        (columns + self.doc_ids.capacity()) * std::mem::size_of::<u32>() + tokens
+    }
+
     #[inline]
     pub(crate) fn remove_rows<F>(&mut self, mut keep: F)
     where
@@ -218,6 +262,30 @@ impl DocGroup {
                 keep
             });
         }
+        let mut row = 0;
+        self.doc_ids.retain(|_| {
+            let keep = keep(&row);
+            row += 1;
+            keep
+        });
+    }
+
+    /// Reorder the rows so the doc ids are ascending.
+    ///
+    /// Rows are appended in input order, only merging groups mixes them up.
+    pub(crate) fn sort_rows_by_doc_id(&mut self) {
+        if self.doc_ids.is_sorted() {
+            return;
+        }
+        let mut order: Vec<u32> = (0..self.doc_ids.len() as u32).collect();
+        order.sort_unstable_by_key(|row| self.doc_ids[*row as usize]);
+        let permute = |values: &[u32]| -> Vec<u32> {
+            order.iter().map(|row| values[*row as usize]).collect()
+        };
+        for column in self.columns.iter_mut() {
+            *column = permute(column);
+        }
+        self.doc_ids = permute(&self.doc_ids);
     }
 
     /// Return an iterator over the columns, yielding (&[u32])
@@ -233,8 +301,32 @@ impl DocGroup {
         })
     }
 
+    /// Appends the rows of `other`, a group with the same token types and higher doc ids.
+    ///
+    /// Positions where the groups don't share the same constant become variable.
+    pub(crate) fn merge(&mut self, mut other: DocGroup, term_hash_map: &mut IndexingTermmap) {
+        for token_idx in 0..self.template.tokens.len() {
+            let same_constant = match (
+                &self.template.tokens[token_idx].token,
+                &other.template.tokens[token_idx].token,
+            ) {
+                (
+                    IndexingTemplateToken::Constant(ct),
+                    IndexingTemplateToken::Constant(other_ct),
+                ) => ct.text == other_ct.text,
+                _ => false,
+            };
+            if !same_constant {
+                self.convert_to_variable(token_idx, term_hash_map);
+                other.convert_to_variable(token_idx, term_hash_map);
+            }
+        }
+        self.append(&other);
+    }
+
     pub fn append(&mut self, other: &DocGroup) {
         self.num_docs += other.num_docs;
+        self.doc_ids.extend_from_slice(&other.doc_ids);
         // Merge only variable columns
         for (target_token, source_token) in self
             .template
@@ -277,7 +369,7 @@ impl DocGroup {
         }
     }
 
-    pub fn new(tokens: &[Token], line: &str) -> Self {
+    pub fn new(tokens: &[Token], line: &str, doc_id: u32) -> Self {
         let template_tokens = tokens
             .iter()
             .enumerate()
@@ -303,9 +395,12 @@ impl DocGroup {
             template: IndexingTemplate {
                 template_id: 0.into(), // This will be set later
                 num_docs: 0,           // This will be set later
+                doc_id_range: (0, 0),  // This will be set later
+                time_range: None,      // This will be set later
                 tokens: template_tokens,
             },
             columns: Vec::new(),
+            doc_ids: vec![doc_id],
             num_docs: 1,
         }
     }
@@ -315,6 +410,7 @@ impl DocGroup {
         &mut self,
         tokens: &[Token],
         line: &str,
+        doc_id: u32,
         term_hash_map: &mut IndexingTermmap,
     ) {
         // Compare with template and update if necessary
@@ -349,6 +445,7 @@ impl DocGroup {
                 }
             }
         }
+        self.doc_ids.push(doc_id);
         self.num_docs += 1;
     }
 }
@@ -370,24 +467,98 @@ pub fn check_is_id_like(column: &[u32]) -> bool {
 
 /// Create a preliminary index from log lines
 pub fn preliminary_index<T: Into<String>>(lines: impl Iterator<Item = T>) -> PreliminaryIndex {
+    preliminary_index_bounded(lines, None)
+}
+
+/// The memory usage is estimated every `MEMORY_CHECK_INTERVAL` lines.
+const MEMORY_CHECK_INTERVAL: usize = 1024;
+
+/// Like [`preliminary_index`], but stops taking lines once the term map and the columns use more
+/// than `memory_budget` bytes. Pass the lines with `by_ref` to index the rest later.
+pub(crate) fn preliminary_index_bounded<T: Into<String>>(
+    lines: impl Iterator<Item = T>,
+    memory_budget: Option<usize>,
+) -> PreliminaryIndex {
+    index_lines(lines.map(Into::into), 0, memory_budget)
+}
+
+/// Number of lines tokenized and grouped by one thread at once.
+const SHARD_SIZE: usize = 16 * MEMORY_CHECK_INTERVAL;
+
+/// Like [`preliminary_index_bounded`], but tokenizes and groups shards of the lines on
+/// `num_threads` threads. The shards are appended in input order, so doc ids are the same as
+/// with a single thread.
+///
+/// The memory budget is checked after each round of shards, so the index may exceed it by the
+/// memory of `num_threads * SHARD_SIZE` lines. The lines of a round are taken from the input
+/// before indexing them, so the round can't stop early.
+pub(crate) fn preliminary_index_parallel<T: Into<String>>(
+    mut lines: impl Iterator<Item = T>,
+    memory_budget: Option<usize>,
+    num_threads: usize,
+) -> PreliminaryIndex {
+    let mut index = PreliminaryIndex::default();
+    let mut num_docs = 0;
+    loop {
+        let mut shards: Vec<(u32, Vec<String>)> = Vec::new();
+        for _ in 0..num_threads {
+            let shard: Vec<String> = lines.by_ref().take(SHARD_SIZE).map(Into::into).collect();
+            if shard.is_empty() {
+                break;
+            }
+            let first_doc_id = num_docs;
+            num_docs += shard.len() as u32;
+            shards.push((first_doc_id, shard));
+        }
+        let is_last_round = shards.len() < num_threads
+            || shards
+                .last()
+                .is_some_and(|(_, shard)| shard.len() < SHARD_SIZE);
+
+        let shard_indexes = map_parallel(&shards, num_threads, |(first_doc_id, shard)| {
+            index_lines(shard.iter(), *first_doc_id, None)
+        });
+        for shard_index in shard_indexes {
+            index.append(shard_index);
+        }
+        if is_last_round || memory_budget.is_some_and(|budget| index.mem_usage() > budget) {
+            return index;
+        }
+    }
+}
+
+/// Index the lines with doc ids starting at `first_doc_id`.
+fn index_lines<T: AsRef<str>>(
+    lines: impl Iterator<Item = T>,
+    first_doc_id: u32,
+    memory_budget: Option<usize>,
+) -> PreliminaryIndex {
     let mut term_hash_map = IndexingTermmap::default();
     let mut preliminary_docs = DocGroupsByLen::default();
 
     let mut tokens = Vec::new();
-    for line in lines {
-        let line: String = line.into();
-        let tokenizer = Tokenizer::new(&line);
+    for (num_lines, line) in lines.enumerate() {
+        let doc_id = first_doc_id as usize + num_lines;
+        let line = line.as_ref();
+        let tokenizer = Tokenizer::new(line);
         tokens.extend(tokenizer);
         if tokens.len() == 2318 {
             println!("Line: {line:?}");
             println!(
                 "{:?}",
-                crate::tokenizer::tokens_as_string(&line, tokens.iter().cloned())
+                crate::tokenizer::tokens_as_string(line, tokens.iter().cloned())
             );
         }
 
-        preliminary_docs.insert(&tokens, &line, &mut term_hash_map);
+        preliminary_docs.insert(&tokens, line, doc_id as u32, &mut term_hash_map);
         tokens.clear();
+
+        if let Some(memory_budget) = memory_budget
+            && (num_lines + 1) % MEMORY_CHECK_INTERVAL == 0
+            && term_hash_map.mem_usage() + preliminary_docs.mem_usage() > memory_budget
+        {
+            break;
+        }
     }
 
     PreliminaryIndex {
@@ -103,6 +103,25 @@ impl RegularTermMap {
     }
 }
 
+impl RegularTermMap {
+    /// Memory used by the hash map, its arena and the id-like terms in bytes.
+    pub fn mem_usage(&self) -> usize {
+        self.map.mem_usage() + self.unique_terms.capacity()
+    }
+
+    /// Adds all terms of `other` and returns the mapping from its term IDs to the IDs in `self`.
+    pub fn append_terms(&mut self, other: &RegularTermMap) -> Vec<u32> {
+        let mut old_to_new_id_map = vec![0; other.num_terms()];
+        for (bytes, id) in other.map.iter() {
+            old_to_new_id_map[id as usize] = self.mutate_or_create(bytes, false);
+        }
+        for (bytes, id) in other.iter_unique() {
+            old_to_new_id_map[id as usize] = self.mutate_or_create(bytes, true);
+        }
+        old_to_new_id_map
+    }
+}
+
 impl TermStore for RegularTermMap {
     fn num_terms(&self) -> usize {
         self.next_term_id as usize
@@ -124,6 +143,11 @@ impl IndexingTermmap {
         self.regular.mutate_or_create(key, is_id_like)
     }
 
+    /// Memory used by the term map in bytes.
+    pub fn mem_usage(&self) -> usize {
+        self.regular.mem_usage()
+    }
+
     #[inline]
     /// This is VERY expensive, so use it only when necessary.
     /// We scan the dict.
@@ -4,16 +4,24 @@ use std::{
     path::Path,
 };
 
+use fxhash::FxHashMap;
+
 use super::{TemplateIdSet, termmap::TermStore};
 use tantivy_sstable::{
     SSTable,
     value::{ValueReader, ValueWriter},
 };
 
+/// Writes the dictionary, the value of each term is its [`TermInfo`].
+///
+/// `postings` maps old term IDs to the template ids and rows of the templates where the term is
+/// in at most `max_rows` rows, see [`template_postings`](crate::columns::write::template_postings).
 pub fn write_dictionary_and_generate_mapping(
     path: &Path,
     term_hash_map: impl TermStore,
     term_id_to_template_id: &[TemplateIdSet],
+    postings: &FxHashMap<u32, Vec<(u32, Vec<u32>)>>,
+    max_rows: Option<usize>,
 ) -> io::Result<Vec<u32>> {
     let len = term_hash_map.num_terms();
     let mut sorted_terms: Vec<(&[u8], u32)> = Vec::with_capacity(len);
@@ -28,60 +36,138 @@ pub fn write_dictionary_and_generate_mapping(
     let dictionary_path = path;
     let wtr = BufWriter::new(File::create(dictionary_path)?);
 
-    let mut builder = tantivy_sstable::Dictionary::<VecU32ValueSSTable>::builder(wtr)?;
+    let mut builder = tantivy_sstable::Dictionary::<TermInfoSSTable>::builder(wtr)?;
 
     // Note: We may have duplicate terms due to the unique term hashmap, so we need to ensure that we assign the same new ID to the
     // same term and not insert it multiple times.
     let mut new_id: u32 = 0;
-    let mut template_ids = Vec::new();
+    let mut term_info = TermInfo::default();
+    let mut postings_merger = PostingsMerger::default();
+    // Adds the template ids and postings of an old term ID.
+    let add_old_id =
+        |term_info: &mut TermInfo, postings_merger: &mut PostingsMerger, old_id: u32| {
+            let num_template_ids = term_info.template_ids.len();
+            term_id_to_template_id[old_id as usize].copy_into_vec(&mut term_info.template_ids);
+            if max_rows.is_none() {
+                return;
+            }
+            let term_postings = postings.get(&old_id).map_or(&[][..], Vec::as_slice);
+            for template_id in &term_info.template_ids[num_template_ids..] {
+                let rows = term_postings
+                    .iter()
+                    .find(|(posting_template_id, _)| posting_template_id == template_id)
+                    .map(|(_, rows)| rows.iter().copied());
+                postings_merger.add(*template_id, rows);
+            }
+        };
 
     let mut iter = sorted_terms.into_iter().peekable();
     while let Some((term_bytes, old_id)) = iter.next() {
         old_to_new_id_map[old_id as usize] = new_id;
-        term_id_to_template_id[old_id as usize].copy_into_vec(&mut template_ids);
+        add_old_id(&mut term_info, &mut postings_merger, old_id);
         while let Some((next_term_bytes, _)) = iter.peek()
             && *next_term_bytes == term_bytes
         {
             let (_, old_id) = iter.next().unwrap();
             old_to_new_id_map[old_id as usize] = new_id;
-            term_id_to_template_id[old_id as usize].copy_into_vec(&mut template_ids);
-            if template_ids.len() > 1 {
-                template_ids.sort_unstable();
-                template_ids.dedup();
+            add_old_id(&mut term_info, &mut postings_merger, old_id);
+            if term_info.template_ids.len() > 1 {
+                term_info.template_ids.sort_unstable();
+                term_info.template_ids.dedup();
             }
         }
-        if template_ids.len() > 1 {
-            template_ids.sort_unstable();
+        if term_info.template_ids.len() > 1 {
+            term_info.template_ids.sort_unstable();
         }
-        if template_ids.is_empty() {
+        if term_info.template_ids.is_empty() {
             // If there are no template IDs, we can skip inserting this term.
             // This can happen if the term is only in a constant
             continue;
         }
+        postings_merger.finish(max_rows, &mut term_info.postings);
 
-        builder.insert(term_bytes, &template_ids)?;
-        template_ids.clear();
+        builder.insert(term_bytes, &term_info)?;
+        term_info.template_ids.clear();
+        term_info.postings.clear();
         new_id += 1;
     }
     builder.finish().map_err(io::Error::other)?;
     Ok(old_to_new_id_map)
 }
 
-pub struct VecU32ValueSSTable;
+/// The value of a term in the dictionary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TermInfo {
+    /// The sorted ids of the templates using the term.
+    pub template_ids: Vec<u32>,
+    /// The sorted rows of the term in each template where it is in at most
+    /// `postings_threshold` rows, by template id. Empty without a postings threshold.
+    pub postings: Vec<(u32, Vec<u32>)>,
+}
+
+/// Combines the postings of a term from several sources, e.g. the term IDs of a term or the
+/// segments of a merge.
+///
+/// A template only keeps postings if every source of the term in the template has postings,
+/// and the term is in at most `max_rows` rows of the template.
+#[derive(Default)]
+pub(crate) struct PostingsMerger {
+    /// The rows of the term in each template, `None` if a source has no postings.
+    rows: FxHashMap<u32, Option<Vec<u32>>>,
+}
+
+impl PostingsMerger {
+    /// Adds the rows of the term in a template, `None` if the source has no postings for it.
+    pub(crate) fn add(&mut self, template_id: u32, rows: Option<impl Iterator<Item = u32>>) {
+        let merged_rows = self
+            .rows
+            .entry(template_id)
+            .or_insert_with(|| Some(Vec::new()));
+        match (merged_rows.as_mut(), rows) {
+            (Some(merged_rows), Some(rows)) => merged_rows.extend(rows),
+            _ => *merged_rows = None,
+        }
+    }
+
+    /// Moves the postings of the templates into `postings`, sorted by template id.
+    pub(crate) fn finish(&mut self, max_rows: Option<usize>, postings: &mut Vec<(u32, Vec<u32>)>) {
+        postings.extend(self.rows.drain().filter_map(|(template_id, rows)| {
+            let mut rows = rows?;
+            rows.sort_unstable();
+            rows.dedup();
+            (rows.len() <= max_rows?).then_some((template_id, rows))
+        }));
+        postings.sort_unstable_by_key(|(template_id, _)| *template_id);
+    }
+}
+
+pub struct TermInfoSSTable;
 
-impl SSTable for VecU32ValueSSTable {
-    type Value = Vec<u32>;
-    type ValueReader = VecU32ValueReader;
-    type ValueWriter = VecU32ValueWriter;
+impl SSTable for TermInfoSSTable {
+    type Value = TermInfo;
+    type ValueReader = TermInfoReader;
+    type ValueWriter = TermInfoWriter;
 }
 
 #[derive(Default)]
-pub struct VecU32ValueReader {
-    vals: Vec<Vec<u32>>,
+pub struct TermInfoReader {
+    vals: Vec<TermInfo>,
 }
 
-impl ValueReader for VecU32ValueReader {
-    type Value = Vec<u32>;
+/// Reads a length prefixed list of u32 values.
+fn read_u32s(data: &mut &[u8]) -> Vec<u32> {
+    let len = read_u32(data) as usize;
+    (0..len).map(|_| read_u32(data)).collect()
+}
+
+fn read_u32(data: &mut &[u8]) -> u32 {
+    let value = u32::from_le_bytes(data[..4].try_into().unwrap());
+    *data = &data[4..];
+    value
+}
+
+impl ValueReader for TermInfoReader {
+    type Value = TermInfo;
 
     #[inline(always)]
     fn value(&self, idx: usize) -> &Self::Value {
@@ -92,23 +178,19 @@ impl ValueReader for VecU32ValueReader {
         let original_num_bytes = data.len();
         self.vals.clear();
 
-        // The first 4 bytes are the number of blocks
-        let num_blocks = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
-        data = &data[4..];
-
-        for _ in 0..num_blocks {
-            // Each block starts with a 4-byte length
-            let segment_len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
-            data = &data[4..];
-
-            // Read the segment IDs for this block
-            let mut segment_ids = Vec::with_capacity(segment_len);
-            for _ in 0..segment_len {
-                let segment_id = u32::from_le_bytes(data[..4].try_into().unwrap());
-                segment_ids.push(segment_id);
-                data = &data[4..];
-            }
-            self.vals.push(segment_ids);
+        // The first 4 bytes are the number of values
+        let num_values = read_u32(&mut data) as usize;
+        for _ in 0..num_values {
+            // The template ids, followed by the postings
+            let template_ids = read_u32s(&mut data);
+            let num_postings = read_u32(&mut data) as usize;
+            let postings = (0..num_postings)
+                .map(|_| (read_u32(&mut data), read_u32s(&mut data)))
+                .collect();
+            self.vals.push(TermInfo {
+                template_ids,
+                postings,
+            });
         }
 
         // Return the number of bytes consumed
@@ -117,25 +199,34 @@ impl ValueReader for VecU32ValueReader {
 }
 
 #[derive(Default)]
-pub struct VecU32ValueWriter {
-    vals: Vec<Vec<u32>>,
+pub struct TermInfoWriter {
+    vals: Vec<TermInfo>,
+}
+
+/// Writes a length prefixed list of u32 values, see [`read_u32s`].
+fn write_u32s(values: &[u32], output: &mut Vec<u8>) {
+    output.extend_from_slice(&(values.len() as u32).to_le_bytes());
+    for value in values {
+        output.extend_from_slice(&value.to_le_bytes());
+    }
 }
 
-impl ValueWriter for VecU32ValueWriter {
-    type Value = Vec<u32>;
+impl ValueWriter for TermInfoWriter {
+    type Value = TermInfo;
 
     fn write(&mut self, val: &Self::Value) {
-        self.vals.push(val.to_vec());
+        self.vals.push(val.clone());
     }
 
     fn serialize_block(&self, output: &mut Vec<u8>) {
-        let num_blocks = self.vals.len() as u32;
-        output.extend_from_slice(&num_blocks.to_le_bytes());
-        for vals in &self.vals {
-            let len = vals.len() as u32;
-            output.extend_from_slice(&len.to_le_bytes());
-            for &segment_id in vals.iter() {
-                output.extend_from_slice(&segment_id.to_le_bytes());
+        let num_values = self.vals.len() as u32;
+        output.extend_from_slice(&num_values.to_le_bytes());
+        for val in &self.vals {
+            write_u32s(&val.template_ids, output);
+            output.extend_from_slice(&(val.postings.len() as u32).to_le_bytes());
+            for (template_id, rows) in &val.postings {
+                output.extend_from_slice(&template_id.to_le_bytes());
+                write_u32s(rows, output);
             }
         }
     }
@@ -0,0 +1,567 @@
+//! JSON documents indexed with their structure.
+//!
+//! Each document is split into its leaves with the [`SchemaTree`] of the index. The string
+//! leaves are indexed like log lines, one line per string leaf, so they get templates and can be
+//! searched. An array is a single leaf, the strings anywhere inside it are indexed as one line
+//! each, in the order they appear in the array. The other leaves and the schema of each document
+//! are stored per segment:
+//!
+//! - `json_docs`: the distinct schema ids of the segment, the schema of each document and the
+//!   number of strings of each array leaf of each document.
+//! - `json_columns`: per schema a typed column for each bool, number and array leaf, with one
+//!   row per document of the schema. Null leaves need no column, the schema id has them. Arrays
+//!   are stored with their strings replaced by empty strings, the lines fill them in again.
+//!
+//! The lines of a document are consecutive and in the order of its keys in the document, so the
+//! lines of each document follow from the schemas and array lengths of the documents before it.
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use fxhash::FxHashMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use serde_json_borrow::Value;
+
+use crate::constants::{JSON_COLUMNS_NAME, JSON_DOCS_NAME};
+use crate::schema::{LeafId, LeafKind, SchemaId, SchemaTree};
+use crate::settings::Compression;
+
+/// A JSON number with the type it was parsed with, so it is reconstructed unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) enum NumberValue {
+    U64(u64),
+    I64(i64),
+    F64(f64),
+}
+
+impl NumberValue {
+    fn to_json(self) -> JsonValue {
+        match self {
+            NumberValue::U64(value) => value.into(),
+            NumberValue::I64(value) => value.into(),
+            NumberValue::F64(value) => value.into(),
+        }
+    }
+}
+
+/// The value of a leaf of a document while it is indexed.
+enum LeafValue {
+    Null,
+    Bool(bool),
+    Number(NumberValue),
+    String(String),
+    /// An array serialized as JSON with empty strings, and its strings.
+    Array(String, Vec<String>),
+}
+
+impl LeafValue {
+    fn new(value: &Value) -> LeafValue {
+        match value {
+            Value::Null => LeafValue::Null,
+            Value::Bool(value) => LeafValue::Bool(*value),
+            Value::Number(number) => LeafValue::Number(if let Some(value) = number.as_u64() {
+                NumberValue::U64(value)
+            } else if let Some(value) = number.as_i64() {
+                NumberValue::I64(value)
+            } else {
+                NumberValue::F64(number.as_f64().unwrap_or_default())
+            }),
+            Value::Str(text) => LeafValue::String(text.to_string()),
+            Value::Array(_) | Value::Object(_) => {
+                let mut array = JsonValue::from(value);
+                let mut strings = Vec::new();
+                for_each_string(&mut array, &mut |text| strings.push(std::mem::take(text)));
+                LeafValue::Array(array.to_string(), strings)
+            }
+        }
+    }
+}
+
+/// Calls `f` for each string in the value, in the order they are serialized.
+fn for_each_string(value: &mut JsonValue, f: &mut impl FnMut(&mut String)) {
+    match value {
+        JsonValue::String(text) => f(text),
+        JsonValue::Array(values) => values
+            .iter_mut()
+            .for_each(|value| for_each_string(value, f)),
+        JsonValue::Object(map) => map.values_mut().for_each(|value| for_each_string(value, f)),
+        JsonValue::Null | JsonValue::Bool(_) | JsonValue::Number(_) => {}
+    }
+}
+
+/// The values of a bool, number or array leaf for the documents of a schema, in doc id order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum LeafColumn {
+    Bool(Vec<bool>),
+    Number(Vec<NumberValue>),
+    /// Arrays serialized as JSON, with empty strings in place of their strings.
+    Array(Vec<String>),
+}
+
+impl LeafColumn {
+    /// An empty column for a leaf of the kind, `None` for kinds without a column.
+    fn new(kind: LeafKind) -> Option<LeafColumn> {
+        match kind {
+            LeafKind::Bool => Some(LeafColumn::Bool(Vec::new())),
+            LeafKind::Number => Some(LeafColumn::Number(Vec::new())),
+            LeafKind::Array => Some(LeafColumn::Array(Vec::new())),
+            LeafKind::Null | LeafKind::String => None,
+        }
+    }
+
+    /// Returns the estimated heap memory of the value in bytes.
+    fn push(&mut self, value: LeafValue) -> usize {
+        match (self, value) {
+            (LeafColumn::Bool(values), LeafValue::Bool(value)) => {
+                values.push(value);
+                size_of::<bool>()
+            }
+            (LeafColumn::Number(values), LeafValue::Number(value)) => {
+                values.push(value);
+                size_of::<NumberValue>()
+            }
+            (LeafColumn::Array(values), LeafValue::Array(value, _)) => {
+                let mem_usage = size_of::<String>() + value.len();
+                values.push(value);
+                mem_usage
+            }
+            _ => unreachable!("the leaf id determines the kind of the value"),
+        }
+    }
+
+    fn append(&mut self, other: LeafColumn) {
+        match (self, other) {
+            (LeafColumn::Bool(values), LeafColumn::Bool(other)) => values.extend(other),
+            (LeafColumn::Number(values), LeafColumn::Number(other)) => values.extend(other),
+            (LeafColumn::Array(values), LeafColumn::Array(other)) => values.extend(other),
+            _ => unreachable!("the columns of a schema have the same kinds in every segment"),
+        }
+    }
+
+    fn get(&self, row: usize) -> Option<io::Result<JsonValue>> {
+        Some(match self {
+            LeafColumn::Bool(values) => Ok(JsonValue::Bool(*values.get(row)?)),
+            LeafColumn::Number(values) => Ok(values.get(row)?.to_json()),
+            LeafColumn::Array(values) => serde_json::from_str(values.get(row)?)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        })
+    }
+}
+
+/// Collects the schemas and typed columns of the JSON documents of a segment.
+#[derive(Default)]
+pub(crate) struct JsonDocsWriter {
+    schemas: Vec<SchemaId>,
+    schema_ords: FxHashMap<SchemaId, u32>,
+    doc_schemas: Vec<u32>,
+    /// The number of strings of each array leaf of each document, in key order.
+    array_lines: Vec<u32>,
+    /// The typed columns of each schema, one per bool, number and array leaf in key order.
+    columns: Vec<Vec<LeafColumn>>,
+    /// Estimated heap memory of the documents added so far in bytes.
+    mem_usage: usize,
+}
+
+impl JsonDocsWriter {
+    /// Adds a document and appends its string leaves to `lines`, in key order. The strings of
+    /// an array leaf are appended in array order.
+    ///
+    /// If a key occurs several times in an object, its last value is kept.
+    pub(crate) fn add(
+        &mut self,
+        tree: &mut SchemaTree,
+        json: &str,
+        lines: &mut Vec<String>,
+    ) -> io::Result<()> {
+        let mut leaves: FxHashMap<LeafId, LeafValue> = FxHashMap::default();
+        let schema_id = tree
+            .ingest_json_with(json, |leaf_id, value| {
+                leaves.insert(leaf_id, LeafValue::new(value));
+            })
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let schema_ord = match self.schema_ords.get(&schema_id) {
+            Some(schema_ord) => *schema_ord,
+            None => {
+                let columns = schema_id
+                    .leaf_ids()
+                    .iter()
+                    .filter_map(|leaf_id| LeafColumn::new(tree.leaf_info(*leaf_id).kind))
+                    .collect();
+                self.add_schema(schema_id.clone(), columns)
+            }
+        };
+        let mut columns = self.columns[schema_ord as usize].iter_mut();
+        for leaf_id in schema_id.leaf_ids() {
+            match leaves.remove(leaf_id).expect("a value for each leaf") {
+                LeafValue::Null => {}
+                LeafValue::String(text) => lines.push(text),
+                LeafValue::Array(array, strings) => {
+                    self.array_lines.push(strings.len() as u32);
+                    lines.extend(strings);
+                    self.mem_usage += size_of::<u32>()
+                        + columns
+                            .next()
+                            .expect("a column for each typed leaf")
+                            .push(LeafValue::Array(array, Vec::new()));
+                }
+                value => {
+                    self.mem_usage += columns
+                        .next()
+                        .expect("a column for each typed leaf")
+                        .push(value)
+                }
+            }
+        }
+        self.doc_schemas.push(schema_ord);
+        self.mem_usage += size_of::<u32>();
+        Ok(())
+    }
+
+    /// Estimated heap memory of the schemas and typed columns of the added documents in bytes.
+    pub(crate) fn mem_usage(&self) -> usize {
+        self.mem_usage
+    }
+
+    /// Appends the documents of a segment, e.g. to merge segments.
+    pub(crate) fn append(&mut self, docs: &JsonDocs, columns: Vec<Vec<LeafColumn>>) {
+        let schema_ord_map: Vec<u32> = docs
+            .schemas
+            .iter()
+            .zip(columns)
+            .map(
+                |(schema_id, columns)| match self.schema_ords.get(schema_id) {
+                    Some(schema_ord) => {
+                        let merged_columns = &mut self.columns[*schema_ord as usize];
+                        for (merged_column, column) in merged_columns.iter_mut().zip(columns) {
+                            merged_column.append(column);
+                        }
+                        *schema_ord
+                    }
+                    None => self.add_schema(schema_id.clone(), columns),
+                },
+            )
+            .collect();
+        self.doc_schemas.extend(
+            docs.doc_schemas
+                .iter()
+                .map(|schema_ord| schema_ord_map[*schema_ord as usize]),
+        );
+        self.array_lines.extend(&docs.array_lines);
+    }
+
+    fn add_schema(&mut self, schema_id: SchemaId, columns: Vec<LeafColumn>) -> u32 {
+        let schema_ord = self.schemas.len() as u32;
+        self.mem_usage +=
+            size_of_val(schema_id.leaf_ids()) + columns.len() * size_of::<LeafColumn>();
+        self.schema_ords.insert(schema_id.clone(), schema_ord);
+        self.schemas.push(schema_id);
+        self.columns.push(columns);
+        schema_ord
+    }
+
+    /// Writes the schemas and typed columns to the segment folder.
+    pub(crate) fn write(&self, segment_folder: &Path, compression: &Compression) -> io::Result<()> {
+        write_compressed(
+            &segment_folder.join(JSON_DOCS_NAME),
+            &(&self.schemas, &self.doc_schemas, &self.array_lines),
+            compression,
+        )?;
+        write_compressed(
+            &segment_folder.join(JSON_COLUMNS_NAME),
+            &self.columns,
+            compression,
+        )
+    }
+}
+
+/// The JSON documents of a segment, see the [module docs](self).
+pub(crate) struct JsonDocs {
+    /// The distinct schema ids of the segment.
+    schemas: Vec<SchemaId>,
+    /// The index in `schemas` of the schema of each document.
+    doc_schemas: Vec<u32>,
+    /// The first line of each document, followed by the number of lines.
+    line_offsets: Vec<u32>,
+    /// The row of each document in the typed columns of its schema.
+    schema_rows: Vec<u32>,
+    /// The number of strings of each array leaf of each document, in key order.
+    array_lines: Vec<u32>,
+    /// The index in `array_lines` of the first array leaf of each document.
+    array_offsets: Vec<u32>,
+    /// The string and array leaves of each schema, in the order of the lines of its documents,
+    /// with whether the leaf is an array.
+    line_leaves: Vec<Vec<(LeafId, bool)>>,
+}
+
+impl JsonDocs {
+    /// Reads the schemas of the documents of a segment.
+    pub(crate) fn open(
+        segment_folder: &Path,
+        tree: &SchemaTree,
+        compression: &Compression,
+    ) -> io::Result<Self> {
+        let (schemas, doc_schemas, array_lines): (Vec<SchemaId>, Vec<u32>, Vec<u32>) =
+            read_compressed(&segment_folder.join(JSON_DOCS_NAME), compression)?;
+        let line_leaves: Vec<Vec<(LeafId, bool)>> = schemas
+            .iter()
+            .map(|schema_id| {
+                schema_id
+                    .leaf_ids()
+                    .iter()
+                    .filter_map(|leaf_id| match tree.leaf_info(*leaf_id).kind {
+                        LeafKind::String => Some((*leaf_id, false)),
+                        LeafKind::Array => Some((*leaf_id, true)),
+                        LeafKind::Null | LeafKind::Bool | LeafKind::Number => None,
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let invalid_data = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut line_offsets = Vec::with_capacity(doc_schemas.len() + 1);
+        let mut schema_rows = Vec::with_capacity(doc_schemas.len());
+        let mut array_offsets = Vec::with_capacity(doc_schemas.len());
+        let mut num_rows = vec![0u32; schemas.len()];
+        let mut num_lines = 0;
+        let mut num_arrays = 0;
+        for schema_ord in &doc_schemas {
+            let schema_ord = *schema_ord as usize;
+            let num_rows = num_rows
+                .get_mut(schema_ord)
+                .ok_or_else(|| invalid_data(format!("schema {schema_ord} out of bounds")))?;
+            schema_rows.push(*num_rows);
+            *num_rows += 1;
+            line_offsets.push(num_lines);
+            array_offsets.push(num_arrays);
+            for (_, is_array) in &line_leaves[schema_ord] {
+                num_lines += if *is_array {
+                    num_arrays += 1;
+                    *array_lines
+                        .get(num_arrays as usize - 1)
+                        .ok_or_else(|| invalid_data(format!("array {num_arrays} out of bounds")))?
+                } else {
+                    1
+                };
+            }
+        }
+        line_offsets.push(num_lines);
+
+        Ok(JsonDocs {
+            schemas,
+            doc_schemas,
+            line_offsets,
+            schema_rows,
+            array_lines,
+            array_offsets,
+            line_leaves,
+        })
+    }
+
+    /// The number of documents in the segment.
+    pub(crate) fn num_docs(&self) -> u32 {
+        self.doc_schemas.len() as u32
+    }
+
+    /// The lines of the string leaves of a document, local to the segment.
+    pub(crate) fn lines(&self, doc: u32) -> Range<u32> {
+        self.line_offsets[doc as usize]..self.line_offsets[doc as usize + 1]
+    }
+
+    /// Returns the documents of the sorted lines, sorted and without duplicates.
+    pub(crate) fn docs_of_lines(&self, lines: &[u32]) -> Vec<u32> {
+        let mut docs: Vec<u32> = lines.iter().map(|line| self.doc_of_line(*line)).collect();
+        docs.dedup();
+        docs
+    }
+
+    fn doc_of_line(&self, line: u32) -> u32 {
+        // Documents without lines have the same offset as the next document.
+        self.line_offsets.partition_point(|offset| *offset <= line) as u32 - 1
+    }
+
+    /// Returns the lines that are the value of one of the leaves, or a string in it.
+    pub(crate) fn lines_of_leaves(&self, lines: &[u32], leaf_ids: &[LeafId]) -> Vec<u32> {
+        lines
+            .iter()
+            .copied()
+            .filter(|line| leaf_ids.contains(&self.leaf_of_line(*line)))
+            .collect()
+    }
+
+    fn leaf_of_line(&self, line: u32) -> LeafId {
+        let doc = self.doc_of_line(line) as usize;
+        let schema_ord = self.doc_schemas[doc] as usize;
+        let mut position = line - self.line_offsets[doc];
+        let mut array_lines = self.array_lines[self.array_offsets[doc] as usize..].iter();
+        for (leaf_id, is_array) in &self.line_leaves[schema_ord] {
+            let num_lines = if *is_array {
+                *array_lines.next().expect("array lines checked on open")
+            } else {
+                1
+            };
+            if position < num_lines {
+                return *leaf_id;
+            }
+            position -= num_lines;
+        }
+        unreachable!("the lines of a document are checked on open")
+    }
+
+    /// Returns the documents whose schema contains one of the leaves.
+    pub(crate) fn docs_with_leaves(&self, leaf_ids: &[LeafId]) -> Vec<u32> {
+        let has_leaf: Vec<bool> = self
+            .schemas
+            .iter()
+            .map(|schema_id| {
+                leaf_ids
+                    .iter()
+                    .any(|leaf_id| schema_id.contains_leaf_id(*leaf_id))
+            })
+            .collect();
+        (0..self.num_docs())
+            .filter(|doc| has_leaf[self.doc_schemas[*doc as usize] as usize])
+            .collect()
+    }
+
+    /// Returns the documents whose value of a null, bool, number or array leaf matches.
+    ///
+    /// Arrays are passed with empty strings in place of their strings.
+    pub(crate) fn docs_with_leaf_value(
+        &self,
+        tree: &SchemaTree,
+        columns: &[Vec<LeafColumn>],
+        leaf_id: LeafId,
+        matches: impl Fn(&JsonValue) -> bool,
+    ) -> io::Result<Vec<u32>> {
+        let kind = tree.leaf_info(leaf_id).kind;
+        // The position of the leaf among the typed columns of each schema.
+        let column_positions: Vec<Option<usize>> = self
+            .schemas
+            .iter()
+            .map(|schema_id| {
+                if !schema_id.contains_leaf_id(leaf_id) {
+                    return None;
+                }
+                Some(
+                    schema_id
+                        .leaf_ids()
+                        .iter()
+                        .take_while(|other| **other != leaf_id)
+                        .filter(|other| LeafColumn::new(tree.leaf_info(**other).kind).is_some())
+                        .count(),
+                )
+            })
+            .collect();
+        let mut docs = Vec::new();
+        for doc in 0..self.num_docs() {
+            let schema_ord = self.doc_schemas[doc as usize] as usize;
+            let Some(position) = column_positions[schema_ord] else {
+                continue;
+            };
+            let is_match = if kind == LeafKind::Null {
+                matches(&JsonValue::Null)
+            } else {
+                let row = self.schema_rows[doc as usize] as usize;
+                let value = columns
+                    .get(schema_ord)
+                    .and_then(|columns| columns.get(position))
+                    .and_then(|column| column.get(row))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("no value for leaf {leaf_id:?} of doc {doc}"),
+                        )
+                    })??;
+                matches(&value)
+            };
+            if is_match {
+                docs.push(doc);
+            }
+        }
+        Ok(docs)
+    }
+
+    /// Reconstructs a document from the lines of its string leaves and the typed columns of the
+    /// segment.
+    pub(crate) fn reconstruct(
+        &self,
+        tree: &SchemaTree,
+        doc: u32,
+        lines: &[String],
+        columns: &[Vec<LeafColumn>],
+    ) -> io::Result<String> {
+        let schema_ord = self.doc_schemas[doc as usize] as usize;
+        let schema_id = &self.schemas[schema_ord];
+        let row = self.schema_rows[doc as usize] as usize;
+        let missing_value = |leaf_id: LeafId| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no value for leaf {leaf_id:?} of doc {doc}"),
+            )
+        };
+
+        let mut lines = lines.iter();
+        let mut columns = columns.get(schema_ord).into_iter().flatten();
+        let mut values = FxHashMap::default();
+        for leaf_id in schema_id.leaf_ids() {
+            let value = match tree.leaf_info(*leaf_id).kind {
+                LeafKind::Null => JsonValue::Null,
+                LeafKind::String => {
+                    let line = lines.next().ok_or_else(|| missing_value(*leaf_id))?;
+                    JsonValue::String(line.clone())
+                }
+                LeafKind::Bool | LeafKind::Number => columns
+                    .next()
+                    .and_then(|column| column.get(row))
+                    .ok_or_else(|| missing_value(*leaf_id))??,
+                LeafKind::Array => {
+                    let mut array = columns
+                        .next()
+                        .and_then(|column| column.get(row))
+                        .ok_or_else(|| missing_value(*leaf_id))??;
+                    let mut missing = false;
+                    for_each_string(&mut array, &mut |text| match lines.next() {
+                        Some(line) => text.clone_from(line),
+                        None => missing = true,
+                    });
+                    if missing {
+                        return Err(missing_value(*leaf_id));
+                    }
+                    array
+                }
+            };
+            values.insert(*leaf_id, value);
+        }
+        Ok(schema_id.reconstruct_json_with(tree, |leaf_id, _| {
+            values.remove(&leaf_id).unwrap_or_default()
+        }))
+    }
+}
+
+/// Reads the typed columns of the JSON documents of a segment, indexed by schema.
+pub(crate) fn read_json_columns(
+    segment_folder: &Path,
+    compression: &Compression,
+) -> io::Result<Vec<Vec<LeafColumn>>> {
+    read_compressed(&segment_folder.join(JSON_COLUMNS_NAME), compression)
+}
+
+fn write_compressed(
+    path: &Path,
+    value: &impl Serialize,
+    compression: &Compression,
+) -> io::Result<()> {
+    let bytes = postcard::to_allocvec(value).map_err(io::Error::other)?;
+    let mut compressed = Vec::new();
+    compression.compress(&bytes, &mut compressed)?;
+    fs::write(path, compressed)
+}
+
+fn read_compressed<T: DeserializeOwned>(path: &Path, compression: &Compression) -> io::Result<T> {
+    let bytes = compression.decompress(&fs::read(path)?)?;
+    postcard::from_bytes(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
@@ -2,6 +2,7 @@
 //! Moshiki is CLP like search engine for unstructured logs.
 //! It provides functionality for indexing, searching, and managing log data.
 
+pub(crate) mod automaton;
 /// For handling columns of data
 pub mod columns;
 pub mod constants;
@@ -13,12 +14,24 @@ pub mod indexing;
 
 /// The main entry point for the index and searcher
 pub mod index;
+/// For JSON documents split into their leaves
+pub(crate) mod json;
+/// For running work on several threads
+pub(crate) mod parallel;
+/// Query language for the searcher
+pub mod query;
 /// For JSON schema de-duplication
 pub mod schema;
 /// For searching the index
 pub mod search;
+/// For the segments of an index
+pub(crate) mod segment;
+/// Compression settings of an index
+pub mod settings;
 /// For handling templates
 pub(crate) mod templates;
+/// Timestamp recognition at index time
+pub mod timestamp;
 /// Tokenizer and token types
 pub mod tokenizer;
 
@@ -37,7 +50,9 @@ impl From<u32> for TemplateId {
 /// A document in the index, containing a template ID and a list of term IDs.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Doc {
-    /// The ID of the template that this document matches.
+    /// The line number of the document in the indexed input, starting at 0.
+    pub doc_id: u32,
+    /// The ID of the template that this document matches, within the segment of the document.
     pub template_id: TemplateId,
     /// The list of term IDs that are present in this document.
     pub term_ids: Vec<u32>,
@@ -50,6 +65,7 @@ mod tests {
 
     use crate::index::Index;
     use crate::indexing::IndexWriter;
+    use crate::search::Pagination;
 
     pub fn index<T: Into<String>>(output_folder: &str, lines: impl Iterator<Item = T>) {
         let writer = IndexWriter::new(output_folder.to_string());
@@ -89,4 +105,1335 @@ mod tests {
         assert_eq!(results[0], "hello world");
         assert_eq!(results[1], "hello there");
     }
+
+    #[test]
+    fn integration_test_boolean_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "ERROR block blk_1 failed",
+                "INFO block blk_1 served",
+                "ERROR block blk_2 failed",
+                "INFO block blk_3 served",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve("ERROR AND blk_1").unwrap();
+        assert_eq!(results, vec!["ERROR block blk_1 failed"]);
+
+        let results = searcher.search_and_retrieve("blk_2 OR blk_3").unwrap();
+        assert_eq!(
+            results,
+            vec!["ERROR block blk_2 failed", "INFO block blk_3 served"]
+        );
+
+        let results = searcher.search_and_retrieve("block NOT ERROR").unwrap();
+        assert_eq!(
+            results,
+            vec!["INFO block blk_1 served", "INFO block blk_3 served"]
+        );
+
+        let results = searcher
+            .search_and_retrieve("(blk_1 OR blk_2) AND NOT served")
+            .unwrap();
+        assert_eq!(
+            results,
+            vec!["ERROR block blk_1 failed", "ERROR block blk_2 failed"]
+        );
+
+        let results = searcher.search_and_retrieve("ERROR AND missing").unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn integration_test_pagination() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines: Vec<String> = (0..25)
+            .map(|i| format!("request id_{i} done"))
+            .chain((0..5).map(|i| format!("request {i} {i} failed badly")))
+            .collect();
+        index(output_folder, lines.iter().cloned());
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+        assert_eq!(searcher.search("request").unwrap().len(), 30);
+        assert_eq!(searcher.count("request").unwrap(), 30);
+        assert_eq!(searcher.count("failed").unwrap(), 5);
+        assert_eq!(searcher.count("id_3 OR failed").unwrap(), 6);
+
+        // Page through all hits with a cursor.
+        let mut pagination = Pagination {
+            limit: Some(7),
+            ..Default::default()
+        };
+        let mut all_docs = Vec::new();
+        loop {
+            let page = searcher.search_page("request", &pagination).unwrap();
+            assert!(page.docs.len() <= 7);
+            all_docs.extend(page.docs);
+            match page.next_cursor {
+                Some(cursor) => pagination.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(all_docs, searcher.search("request").unwrap());
+
+        // Offsets skip hits in the same order.
+        let page = searcher
+            .search_page(
+                "request",
+                &Pagination {
+                    limit: Some(3),
+                    offset: 24,
+                    cursor: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(page.docs, all_docs[24..27]);
+        assert!(page.next_cursor.is_some());
+    }
+
+    #[test]
+    fn integration_test_results_keep_line_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        // "start job N" and "job N done" have the same number of tokens, so their groups are
+        // merged and the rows interleave.
+        let lines = [
+            "start job 1",
+            "job 1 done",
+            "start job 2",
+            "job 2 failed with code 8",
+            "start job 3",
+            "job 3 done",
+        ];
+        index(output_folder, lines.into_iter());
+
+        let index = Index::new(output_folder).unwrap();
+        let searcher = index.searcher();
+
+        let docs = searcher.search("job").unwrap();
+        let doc_ids: Vec<u32> = docs.iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(doc_ids, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(index.retrieve_doc(&docs).unwrap(), lines);
+
+        for (line_number, line) in lines.iter().enumerate() {
+            let retrieved = index.retrieve_by_line(line_number as u32).unwrap();
+            assert_eq!(retrieved.as_deref(), Some(*line));
+        }
+        assert_eq!(index.retrieve_by_line(lines.len() as u32).unwrap(), None);
+    }
+
+    #[test]
+    fn integration_test_decompress_round_trip() {
+        let mut files: Vec<std::path::PathBuf> = std::fs::read_dir("bench_data")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.push("hdfs-logs".into());
+        for file in files {
+            let content = std::fs::read(&file).unwrap();
+            let lines = content
+                .strip_suffix(b"\n")
+                .unwrap_or(&content)
+                .split(|byte| *byte == b'\n')
+                .map(|line| String::from_utf8(line.to_vec()).unwrap());
+
+            let temp_dir = TempDir::new().unwrap();
+            let output_folder = temp_dir.path().to_str().unwrap();
+            index(output_folder, lines);
+
+            let mut decompressed = Vec::new();
+            Index::new(output_folder)
+                .unwrap()
+                .decompress_all(&mut decompressed)
+                .unwrap();
+            assert!(
+                decompressed == content,
+                "round trip of {file:?} is not lossless"
+            );
+        }
+
+        // An input without a final newline, appended in two parts.
+        let content = "first line\nsecond 007 line\nno newline 3.5";
+        let lines: Vec<&str> = content.split('\n').collect();
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let writer = IndexWriter::new(output_folder.to_string()).with_unterminated_last_line(true);
+        writer.index(lines[..1].iter().copied(), false).unwrap();
+        writer.index(lines[1..].iter().copied(), false).unwrap();
+        let mut decompressed = Vec::new();
+        Index::new(output_folder)
+            .unwrap()
+            .decompress_all(&mut decompressed)
+            .unwrap();
+        assert_eq!(String::from_utf8(decompressed).unwrap(), content);
+    }
+
+    #[test]
+    fn integration_test_decompress_keeps_special_lines() {
+        let lines = [
+            "",
+            "trailing space ",
+            "carriage return\r",
+            "\ttabs\tand  spaces",
+            "",
+            "unicode: grüße",
+        ];
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(output_folder, lines.into_iter());
+
+        let mut decompressed = Vec::new();
+        Index::new(output_folder)
+            .unwrap()
+            .decompress_all(&mut decompressed)
+            .unwrap();
+        let expected: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        assert_eq!(String::from_utf8(decompressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn integration_test_phrase_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "disk failed now",
+                "failed disk now",
+                "network failed now",
+                "PacketResponder 1 terminating",
+                "PacketResponder terminating",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve(r#""disk failed""#).unwrap();
+        assert_eq!(results, vec!["disk failed now"]);
+
+        let results = searcher.search_and_retrieve(r#""failed disk""#).unwrap();
+        assert_eq!(results, vec!["failed disk now"]);
+
+        let results = searcher.search_and_retrieve(r#""failed now""#).unwrap();
+        assert_eq!(results, vec!["disk failed now", "network failed now"]);
+
+        let results = searcher
+            .search_and_retrieve(r#""PacketResponder terminating""#)
+            .unwrap();
+        assert_eq!(results, vec!["PacketResponder terminating"]);
+    }
+
+    #[test]
+    fn integration_test_wildcard_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "served block blk_1074 to host",
+                "served block blk_1075 to host",
+                "served block blk_2001 to host",
+                "got java.io.IOException from host",
+                "got java.io.EOFException from host",
+                "got java.io.IOError from host",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve("blk_107*").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                "served block blk_1074 to host",
+                "served block blk_1075 to host"
+            ]
+        );
+
+        let results = searcher.search_and_retrieve("*Exception").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                "got java.io.IOException from host",
+                "got java.io.EOFException from host"
+            ]
+        );
+
+        // Matches a constant of the template.
+        let results = searcher.search_and_retrieve("serv*").unwrap();
+        assert_eq!(results.len(), 3);
+
+        let results = searcher.search_and_retrieve("h*t NOT blk_*").unwrap();
+        assert_eq!(results.len(), 3);
+
+        let results = searcher.search_and_retrieve("*missing*").unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn integration_test_regex_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "Receiving block blk_1074 src",
+                "Receiving block blk_x75 src",
+                "Deleting block blk_2001 file",
+                "Verification succeeded for blk_3",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve(r"/blk_\d+/").unwrap();
+        assert_eq!(
+            results,
+            vec![
+                "Receiving block blk_1074 src",
+                "Deleting block blk_2001 file",
+                "Verification succeeded for blk_3"
+            ]
+        );
+
+        // Matches template constants.
+        let results = searcher
+            .search_and_retrieve("/(Receiving|Deleting)/ AND /blk_[0-9]{4}/")
+            .unwrap();
+        assert_eq!(
+            results,
+            vec![
+                "Receiving block blk_1074 src",
+                "Deleting block blk_2001 file"
+            ]
+        );
+
+        // The regex has to match the whole token.
+        let results = searcher.search_and_retrieve("/lk_1074/").unwrap();
+        assert!(results.is_empty());
+
+        assert!(searcher.search_and_retrieve("/blk_(/").is_err());
+    }
+
+    #[test]
+    fn integration_test_substring_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "Receiving block src: /10.10.34.11:50010 dest: /10.10.34.12:50010",
+                "Receiving block src: /10.10.34.13:50010 dest: /10.10.34.11:50011",
+                "PacketResponder type=HAS_DOWNSTREAM_IN_PIPELINE terminating",
+                "PacketResponder type=LAST_IN_PIPELINE terminating",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve("10.10.34.11:50010").unwrap();
+        assert_eq!(
+            results,
+            vec!["Receiving block src: /10.10.34.11:50010 dest: /10.10.34.12:50010"]
+        );
+
+        let results = searcher.search_and_retrieve("type=HAS_DOWNSTREAM").unwrap();
+        assert_eq!(
+            results,
+            vec!["PacketResponder type=HAS_DOWNSTREAM_IN_PIPELINE terminating"]
+        );
+
+        // The edges may start and end inside a token, e.g. `: /`.
+        let results = searcher.search_and_retrieve("src:").unwrap();
+        assert_eq!(results.len(), 2);
+
+        let results = searcher.search_and_retrieve("type=MISSING").unwrap();
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn integration_test_range_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            [
+                "request took 120 ms",
+                "request took 480 ms",
+                "cache size 700 entries",
+                "request took 950 ms",
+                "error code 42",
+                "no numbers here",
+            ]
+            .into_iter(),
+        );
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+
+        let results = searcher.search_and_retrieve(">500").unwrap();
+        assert_eq!(
+            results,
+            vec!["cache size 700 entries", "request took 950 ms"]
+        );
+
+        let results = searcher.search_and_retrieve("took > 400").unwrap();
+        assert_eq!(results, vec!["request took 480 ms", "request took 950 ms"]);
+
+        let results = searcher.search_and_retrieve("[120 TO 480]").unwrap();
+        assert_eq!(results, vec!["request took 120 ms", "request took 480 ms"]);
+
+        let results = searcher.search_and_retrieve("<100").unwrap();
+        assert_eq!(results, vec!["error code 42"]);
+
+        assert_eq!(searcher.count(">=0").unwrap(), 5);
+        assert_eq!(searcher.count(">950").unwrap(), 0);
+    }
+
+    #[test]
+    fn integration_test_key_range_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let statuses = [200, 404, 503];
+        let mut lines: Vec<String> = (0..3000)
+            .map(|i| {
+                let status = statuses[i % 3];
+                format!(
+                    "status={status} latency={} bytes: {}",
+                    i % 1000,
+                    (i * 7) % 3000
+                )
+            })
+            .collect();
+        // Small templates, where the number after the key is another one than above.
+        lines.push("latency = 950 status=200".to_string());
+        lines.push("status=950 latency=12".to_string());
+        index(output_folder, lines.iter().cloned());
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+        let expected = |matches: &dyn Fn(usize) -> bool| -> Vec<u32> {
+            (0..3000)
+                .filter(|i| matches(*i))
+                .map(|i| i as u32)
+                .collect()
+        };
+        let doc_ids = |query: &str| -> Vec<u32> {
+            let docs = searcher.search(query).unwrap();
+            docs.iter().map(|doc| doc.doc_id).collect()
+        };
+        let mut latency_above_900 = expected(&|i| i % 1000 > 900);
+        latency_above_900.push(3000);
+        assert_eq!(doc_ids("latency > 900"), latency_above_900);
+        assert_eq!(searcher.count("latency > 900").unwrap(), 298);
+        let mut status_503 = expected(&|i| i % 3 == 2);
+        status_503.push(3001);
+        assert_eq!(doc_ids("status >=500"), status_503);
+        assert_eq!(
+            doc_ids("bytes [100 TO 200]"),
+            expected(&|i| (100..=200).contains(&((i * 7) % 3000)))
+        );
+        assert_eq!(
+            doc_ids("status <300 AND latency <5"),
+            expected(&|i| i % 3 == 0 && i % 1000 < 5)
+        );
+        // Without a key the range matches any number of the line.
+        assert_eq!(searcher.count(">2990").unwrap(), 9);
+    }
+
+    #[test]
+    fn integration_test_time_range_search() {
+        use crate::query::Query;
+        use crate::timestamp::{TimeRange, TimestampFormat};
+
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines = [
+            "2016-04-13T06:46:53Z INFO block blk_1 served",
+            "2016-04-13T06:47:10Z ERROR block blk_2 failed",
+            "continuation line without timestamp",
+            "2016-04-13T07:00:00Z INFO block blk_3 served",
+            "2016-04-14T00:00:00Z INFO block blk_4 served",
+        ];
+        IndexWriter::new(output_folder.to_string())
+            .with_timestamp_formats(vec![TimestampFormat::Iso8601])
+            .index(lines.iter().copied(), false)
+            .unwrap();
+
+        let index = Index::new(output_folder).unwrap();
+        let searcher = index.searcher();
+        let time = |text: &str| TimestampFormat::Iso8601.parse_prefix(text).unwrap();
+        let in_range = |query: &str, from: &str, to: &str| {
+            let query = Query::And(vec![
+                Query::parse(query).unwrap(),
+                Query::TimeRange(TimeRange {
+                    from: time(from),
+                    to: time(to),
+                }),
+            ]);
+            let docs = searcher
+                .search_query(&query, &Pagination::default())
+                .unwrap()
+                .docs;
+            docs.iter().map(|doc| doc.doc_id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            in_range("block", "2016-04-13T06:47:00Z", "2016-04-13T07:00:00Z"),
+            vec![1]
+        );
+        assert_eq!(
+            in_range("served", "2016-04-13T00:00:00Z", "2016-04-14T00:00:00Z"),
+            vec![0, 3]
+        );
+        assert!(in_range("block", "2017-01-01T00:00:00Z", "2018-01-01T00:00:00Z").is_empty());
+
+        // The lines are restored unchanged.
+        let mut decompressed = Vec::new();
+        index.decompress_all(&mut decompressed).unwrap();
+        let expected: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        assert_eq!(String::from_utf8(decompressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn integration_test_append_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        index(
+            output_folder,
+            ["block blk_1 served", "block blk_2 failed"].into_iter(),
+        );
+        index(output_folder, std::iter::empty::<String>());
+        // The folder of a segment that was never added to the list is skipped.
+        std::fs::create_dir(temp_dir.path().join("segment_1")).unwrap();
+        index(
+            output_folder,
+            ["block blk_3 failed", "other line", "block blk_4 served"].into_iter(),
+        );
+        assert!(temp_dir.path().join("segment_2").exists());
+
+        let index = Index::new(output_folder).unwrap();
+        let searcher = index.searcher();
+        let doc_ids = |query: &str| {
+            let docs = searcher.search(query).unwrap();
+            docs.iter().map(|doc| doc.doc_id).collect::<Vec<_>>()
+        };
+        assert_eq!(doc_ids("failed"), vec![1, 2]);
+        assert_eq!(doc_ids("block AND NOT blk_1"), vec![1, 2, 4]);
+        assert_eq!(searcher.count("served").unwrap(), 2);
+        assert_eq!(
+            searcher.search_and_retrieve("blk_4").unwrap(),
+            vec!["block blk_4 served"]
+        );
+
+        // Pages continue across segments.
+        let pagination = Pagination {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = searcher.search_page("block", &pagination).unwrap();
+        assert_eq!(index.retrieve_doc(&page.docs).unwrap().len(), 2);
+        let page = searcher
+            .search_page(
+                "block",
+                &Pagination {
+                    cursor: page.next_cursor,
+                    ..pagination
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            index.retrieve_doc(&page.docs).unwrap(),
+            vec!["block blk_3 failed", "block blk_4 served"]
+        );
+        assert!(page.next_cursor.is_none());
+
+        assert_eq!(
+            index.retrieve_by_line(3).unwrap().as_deref(),
+            Some("other line")
+        );
+        assert_eq!(index.retrieve_by_line(5).unwrap(), None);
+
+        let mut decompressed = Vec::new();
+        index.decompress_all(&mut decompressed).unwrap();
+        assert_eq!(
+            String::from_utf8(decompressed).unwrap(),
+            "block blk_1 served\nblock blk_2 failed\nblock blk_3 failed\nother line\nblock blk_4 served\n"
+        );
+    }
+
+    #[test]
+    fn integration_test_merge_segments() {
+        use crate::timestamp::TimestampFormat;
+
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let batches = [
+            vec![
+                "2016-04-13T06:46:53Z block blk_1 served 10",
+                "2016-04-13T06:47:10Z block blk_2 failed 20",
+            ],
+            vec!["starting datanode", "block blk_3 served 30"],
+            vec![
+                "2016-04-14T00:00:00Z block blk_4 failed 40",
+                "block blk_5 served 50",
+                "stopping datanode",
+            ],
+        ];
+        let writer = IndexWriter::new(output_folder.to_string())
+            .with_timestamp_formats(vec![TimestampFormat::Iso8601]);
+        for batch in &batches {
+            writer.index(batch.iter().copied(), false).unwrap();
+        }
+
+        let queries = [
+            "block",
+            "failed",
+            "blk_3 OR datanode",
+            "served AND NOT blk_1",
+            "[20 TO 40]",
+            "blk_*",
+        ];
+        let search_all = || {
+            let index = Index::new(output_folder).unwrap();
+            let searcher = index.searcher();
+            let results: Vec<Vec<String>> = queries
+                .iter()
+                .map(|query| searcher.search_and_retrieve(query).unwrap())
+                .collect();
+            let mut decompressed = Vec::new();
+            index.decompress_all(&mut decompressed).unwrap();
+            (index.segments.len(), results, decompressed)
+        };
+        let (num_segments, before, decompressed_before) = search_all();
+        assert_eq!(num_segments, 3);
+
+        // The folder of a segment that was never added to the list is skipped.
+        let orphan = temp_dir.path().join("segment_3");
+        std::fs::create_dir(&orphan).unwrap();
+        writer.merge_segments().unwrap();
+        assert!(temp_dir.path().join("segment_4").exists());
+        std::fs::remove_dir(orphan).unwrap();
+        let (num_segments, after, decompressed_after) = search_all();
+        assert_eq!(num_segments, 1);
+        assert_eq!(after, before);
+        assert_eq!(after[1].len(), 2);
+        assert!(after[1][0].contains("blk_2") && after[1][1].contains("blk_4"));
+        assert_eq!(decompressed_after, decompressed_before);
+        // Only the merged segment is left on disk, next to the segment list and the settings.
+        assert_eq!(std::fs::read_dir(output_folder).unwrap().count(), 3);
+
+        // Templates with and without timestamps are merged.
+        let index = Index::new(output_folder).unwrap();
+        let query = crate::query::Query::And(vec![
+            crate::query::Query::parse("block").unwrap(),
+            crate::query::Query::TimeRange(crate::timestamp::TimeRange {
+                from: TimestampFormat::Iso8601
+                    .parse_prefix("2016-04-13T06:47:00Z")
+                    .unwrap(),
+                to: i64::MAX,
+            }),
+        ]);
+        let page = index
+            .searcher()
+            .search_query(&query, &Pagination::default())
+            .unwrap();
+        let doc_ids: Vec<u32> = page.docs.iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(doc_ids, vec![1, 4]);
+
+        // Appending after a merge continues the doc ids.
+        writer
+            .index(["block blk_6 failed 60"].into_iter(), false)
+            .unwrap();
+        let searcher = Index::new(output_folder).unwrap().searcher();
+        let docs = searcher.search("failed").unwrap();
+        assert_eq!(docs.last().unwrap().doc_id, 7);
+    }
+
+    #[test]
+    fn integration_test_memory_budget_flushes_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines: Vec<String> = (0..3000)
+            .map(|i| format!("request {i} from host_{} took {}ms", i % 7, i % 100))
+            .collect();
+        // A tiny budget flushes a segment at every memory check.
+        IndexWriter::new(output_folder.to_string())
+            .with_memory_budget(1)
+            .index(lines.iter().cloned(), false)
+            .unwrap();
+
+        let index = Index::new(output_folder).unwrap();
+        assert_eq!(index.segments.len(), 3);
+        let searcher = index.searcher();
+        assert_eq!(searcher.count("host_3").unwrap(), 429);
+        let docs = searcher.search("host_3").unwrap();
+        assert_eq!(docs.last().unwrap().doc_id, 2999);
+        assert_eq!(
+            index.retrieve_by_line(1500).unwrap().as_deref(),
+            Some(lines[1500].as_str())
+        );
+
+        let mut decompressed = Vec::new();
+        index.decompress_all(&mut decompressed).unwrap();
+        let expected: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        assert_eq!(String::from_utf8(decompressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn integration_test_json_memory_budget_counts_typed_columns() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines: Vec<String> = (0..100)
+            .map(|i| format!(r#"{{"msg": "ok", "count": {i}, "ratio": {i}.5, "retry": true}}"#))
+            .collect();
+        // The string leaves alone stay below the budget, the numbers and bools exceed it.
+        IndexWriter::new(output_folder.to_string())
+            .with_memory_budget(3000)
+            .index_json(lines.iter())
+            .unwrap();
+
+        let index = Index::new(output_folder).unwrap();
+        assert_eq!(index.segments.len(), 3);
+        let retrieved = index.retrieve_json(&(0..100).collect::<Vec<_>>()).unwrap();
+        for (json, line) in retrieved.iter().zip(&lines) {
+            let parse = |json: &str| -> serde_json::Value { serde_json::from_str(json).unwrap() };
+            assert_eq!(parse(json), parse(line));
+        }
+    }
+
+    #[test]
+    fn integration_test_multi_threaded_indexing() {
+        // Several shards, where `host_a` and `host_b` are constant in some shards only.
+        let lines: Vec<String> = (0..40_000)
+            .map(|i| {
+                let host = if i < 20_000 { "host_a" } else { "host_b" };
+                format!("{host} request {i} status {}", i % 5)
+            })
+            .collect();
+        let open_index = |num_threads: usize| {
+            let temp_dir = TempDir::new().unwrap();
+            let output_folder = temp_dir.path().to_str().unwrap();
+            IndexWriter::new(output_folder.to_string())
+                .with_num_threads(num_threads)
+                .index(lines.iter().cloned(), false)
+                .unwrap();
+            (Index::new(output_folder).unwrap(), temp_dir)
+        };
+        let (single_threaded, _dir) = open_index(1);
+        let (multi_threaded, _dir) = open_index(4);
+
+        for query in ["host_a", "host_b", "status", "host_b AND NOT request"] {
+            let doc_ids = |index: &Index| {
+                let docs = index.searcher().search(query).unwrap();
+                docs.iter().map(|doc| doc.doc_id).collect::<Vec<_>>()
+            };
+            assert_eq!(doc_ids(&multi_threaded), doc_ids(&single_threaded));
+        }
+        assert_eq!(multi_threaded.searcher().count("host_b").unwrap(), 20_000);
+
+        let decompress = |index: &Index| {
+            let mut decompressed = Vec::new();
+            index.decompress_all(&mut decompressed).unwrap();
+            decompressed
+        };
+        assert_eq!(decompress(&multi_threaded), decompress(&single_threaded));
+    }
+
+    #[test]
+    fn integration_test_parallel_search() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines: Vec<String> = (0..500)
+            .map(|i| match i % 4 {
+                0 => format!("connection from 10.0.0.{} accepted", i % 9),
+                1 => format!("request {i} took {}ms", i % 13),
+                2 => format!("user_{} logged in", i % 5),
+                _ => format!("error code {} in module_{}", i % 3, i % 7),
+            })
+            .collect();
+        index(output_folder, lines.iter().cloned());
+        let index = Index::new(output_folder).unwrap();
+        let serial = index.searcher().with_num_threads(1);
+        let parallel = index.searcher().with_num_threads(4);
+
+        for query in [
+            "accepted OR user_3",
+            "NOT error",
+            "module_2 OR logged",
+            "took",
+        ] {
+            assert_eq!(
+                parallel.search(query).unwrap(),
+                serial.search(query).unwrap()
+            );
+            assert_eq!(parallel.count(query).unwrap(), serial.count(query).unwrap());
+
+            // Paging through the hits gives the same pages.
+            let mut pagination = Pagination {
+                limit: Some(7),
+                ..Default::default()
+            };
+            loop {
+                let page = parallel.search_page(query, &pagination).unwrap();
+                let serial_page = serial.search_page(query, &pagination).unwrap();
+                assert_eq!(page.docs, serial_page.docs);
+                assert_eq!(page.next_cursor, serial_page.next_cursor);
+                match page.next_cursor {
+                    Some(cursor) => pagination.cursor = Some(cursor),
+                    None => break,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn integration_test_search_reads_needed_columns() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let lines: Vec<String> = (0..100)
+            .map(|i| format!("user user_{} from host_{} port {}", i % 10, i % 3, i))
+            .collect();
+        index(output_folder, lines.iter().cloned());
+
+        let index = Index::new(output_folder).unwrap();
+        let searcher = index.searcher();
+        // Only the host column is checked, but hits have the term ids of all columns.
+        let docs = searcher.search(r#""from host_2""#).unwrap();
+        assert_eq!(docs.len(), 33);
+        let expected: Vec<&str> = lines
+            .iter()
+            .filter(|line| line.contains("host_2"))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(index.retrieve_doc(&docs).unwrap(), expected);
+        assert_eq!(searcher.count(r#""from host_2""#).unwrap(), 33);
+        assert_eq!(
+            searcher
+                .search_and_retrieve(r#""user_4 from host_1""#)
+                .unwrap(),
+            vec![
+                "user user_4 from host_1 port 4",
+                "user user_4 from host_1 port 34",
+                "user user_4 from host_1 port 64",
+                "user user_4 from host_1 port 94"
+            ]
+        );
+    }
+
+    #[test]
+    fn integration_test_column_blocks() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        // Several blocks per column, each `session_N` is only in one block.
+        let lines: Vec<String> = (0..10_000)
+            .map(|i| format!("session_{} status {}", i / 100, i % 4))
+            .collect();
+        index(output_folder, lines.iter().cloned());
+
+        let index = Index::new(output_folder).unwrap();
+        let searcher = index.searcher();
+        let docs = searcher.search("session_57").unwrap();
+        let doc_ids: Vec<u32> = docs.iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(doc_ids, (5700..5800).collect::<Vec<_>>());
+        assert_eq!(index.retrieve_doc(&docs[..1]).unwrap(), lines[5700..5701]);
+        let session_term_id = docs[0].term_ids[0];
+        let term_ids = searcher
+            .search_in_zstd_column(session_term_id, 0, docs[0].template_id, None)
+            .unwrap();
+        let expected: Vec<Vec<u32>> = docs.iter().map(|doc| doc.term_ids.clone()).collect();
+        assert_eq!(term_ids, expected);
+        assert!(
+            searcher
+                .search_in_zstd_column(session_term_id, 1, docs[0].template_id, None)
+                .is_err()
+        );
+        assert_eq!(searcher.count("session_57 OR session_99").unwrap(), 200);
+        assert_eq!(searcher.count(r#""status 3""#).unwrap(), 2500);
+        assert_eq!(
+            searcher.count("session_9* AND NOT session_99").unwrap(),
+            1000
+        );
+
+        // Pages starting inside a block.
+        let page = searcher
+            .search_page(
+                r#""status 1""#,
+                &Pagination {
+                    limit: Some(2),
+                    offset: 1500,
+                    cursor: None,
+                },
+            )
+            .unwrap();
+        let doc_ids: Vec<u32> = page.docs.iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(doc_ids, vec![6001, 6005]);
+
+        assert_eq!(
+            index.retrieve_by_line(9999).unwrap().as_deref(),
+            Some(lines[9999].as_str())
+        );
+        let mut decompressed = Vec::new();
+        index.decompress_all(&mut decompressed).unwrap();
+        let expected: String = lines.iter().map(|line| format!("{line}\n")).collect();
+        assert_eq!(String::from_utf8(decompressed).unwrap(), expected);
+    }
+
+    #[test]
+    fn integration_test_postings() {
+        use crate::dict::Postings;
+
+        let lines: Vec<String> = (0..10_000)
+            .map(|i| {
+                let user = if i % 2500 == 7 { "admin" } else { "guest" };
+                format!("login user_{} as {user} status {}", i % 5000, i % 4)
+            })
+            .collect();
+        let open_index = |postings_threshold: Option<usize>| {
+            let temp_dir = TempDir::new().unwrap();
+            let output_folder = temp_dir.path().to_str().unwrap();
+            let mut writer = IndexWriter::new(output_folder.to_string());
+            if let Some(max_rows) = postings_threshold {
+                writer = writer.with_postings_threshold(max_rows);
+            }
+            writer.index(lines[..6000].iter().cloned(), false).unwrap();
+            writer.index(lines[6000..].iter().cloned(), false).unwrap();
+            (Index::new(output_folder).unwrap(), temp_dir)
+        };
+        let (without_postings, _dir) = open_index(None);
+        let (with_postings, dir) = open_index(Some(10));
+        let has_postings = |index: &Index| {
+            index.segments.iter().any(|segment| {
+                let term_infos = segment.dictionary.load_all_term_infos().unwrap();
+                term_infos
+                    .iter()
+                    .any(|term_info| !term_info.postings.is_empty())
+            })
+        };
+        assert!(has_postings(&with_postings));
+        assert!(!has_postings(&without_postings));
+
+        let queries = [
+            "admin",
+            "user_7",
+            "user_7 AND status",
+            "admin AND user_2507",
+            "user_1 OR user_4999",
+            "user_4* AND NOT guest",
+            r#""as admin""#,
+            "guest",
+        ];
+        let doc_ids = |index: &Index, query: &str| {
+            let docs = index.searcher().search(query).unwrap();
+            docs.iter().map(|doc| doc.doc_id).collect::<Vec<_>>()
+        };
+        for query in queries {
+            assert_eq!(
+                doc_ids(&with_postings, query),
+                doc_ids(&without_postings, query),
+                "{query}"
+            );
+            assert_eq!(
+                with_postings.searcher().count(query).unwrap(),
+                without_postings.searcher().count(query).unwrap(),
+                "{query}"
+            );
+        }
+        assert_eq!(doc_ids(&with_postings, "admin"), vec![7, 2507, 5007, 7507]);
+
+        let page = with_postings
+            .searcher()
+            .search_page(
+                "admin",
+                &Pagination {
+                    limit: Some(1),
+                    offset: 1,
+                    cursor: None,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            with_postings.retrieve_doc(&page.docs).unwrap(),
+            lines[2507..2508]
+        );
+
+        // The threshold is stored with the index, so merging without it keeps the postings.
+        let output_folder = dir.path().to_str().unwrap();
+        IndexWriter::new(output_folder.to_string())
+            .merge_segments()
+            .unwrap();
+        let merged = Index::new(output_folder).unwrap();
+        assert!(has_postings(&merged));
+        // The rows of the second segment are shifted behind the rows of the first one.
+        let segment = &merged.segments[0];
+        let admin = segment.dictionary.search_single_term(b"admin").unwrap();
+        let admin = admin.unwrap();
+        let mut postings = Postings::default();
+        postings.add(&admin);
+        let rows: Vec<u32> = admin
+            .template_ids()
+            .iter()
+            .flat_map(|template_id| postings.rows(*template_id, admin.term_id()).unwrap())
+            .copied()
+            .collect();
+        assert_eq!(rows, [7, 2507, 5007, 7507]);
+        for query in queries {
+            assert_eq!(
+                doc_ids(&merged, query),
+                doc_ids(&without_postings, query),
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn integration_test_postings_of_id_like_terms() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        // The column becomes id-like after 10000 rows, from then on each value gets a new term
+        // id. `req_dup` has rows before and after, which must end up in the same postings.
+        let lines: Vec<String> = (0..12_000)
+            .map(|i| match i {
+                5 | 11_000 | 11_500 => "handled req_dup".to_string(),
+                i => format!("handled req_{i}"),
+            })
+            .collect();
+        IndexWriter::new(output_folder.to_string())
+            .with_postings_threshold(10)
+            .index(lines.iter().cloned(), false)
+            .unwrap();
+
+        let searcher = Index::new(output_folder).unwrap().searcher();
+        let docs = searcher.search("req_dup").unwrap();
+        let doc_ids: Vec<u32> = docs.iter().map(|doc| doc.doc_id).collect();
+        assert_eq!(doc_ids, [5, 11_000, 11_500]);
+        assert_eq!(searcher.count("req_dup").unwrap(), 3);
+    }
+
+    #[test]
+    fn integration_test_index_settings() {
+        use crate::settings::{Codec, IndexSettings};
+
+        // Many small templates, which share a trained zstd dictionary.
+        let lines: Vec<String> = (0..3000)
+            .map(|i| format!("{} event {i} on host_{}", "word ".repeat(i % 40), i % 9))
+            .collect();
+        let all_settings = [
+            IndexSettings::default(),
+            IndexSettings {
+                codec: Codec::Zstd { level: 1 },
+                zstd_dictionary_size: Some(16 * 1024),
+                postings_threshold: None,
+            },
+            IndexSettings {
+                codec: Codec::Lz4,
+                zstd_dictionary_size: None,
+                postings_threshold: None,
+            },
+            IndexSettings {
+                codec: Codec::None,
+                zstd_dictionary_size: None,
+                postings_threshold: None,
+            },
+        ];
+        for settings in all_settings {
+            let temp_dir = TempDir::new().unwrap();
+            let output_folder = temp_dir.path().to_str().unwrap();
+            let writer = IndexWriter::new(output_folder.to_string())
+                .with_settings(settings)
+                .with_postings_threshold(5)
+                .with_timestamp_formats(Vec::new());
+            writer.index(lines[..2000].iter().cloned(), false).unwrap();
+            // Appending uses the stored settings.
+            IndexWriter::new(output_folder.to_string())
+                .index(lines[2000..].iter().cloned(), false)
+                .unwrap();
+            let other_settings = IndexSettings {
+                codec: Codec::Zstd { level: 22 },
+                zstd_dictionary_size: None,
+                postings_threshold: None,
+            };
+            let result = IndexWriter::new(output_folder.to_string())
+                .with_settings(other_settings)
+                .index(lines.iter().cloned(), false);
+            assert!(result.is_err());
+            let result = IndexWriter::new(output_folder.to_string())
+                .with_postings_threshold(6)
+                .index(lines.iter().cloned(), false);
+            assert!(result.is_err());
+
+            let check_index = || {
+                let index = Index::new(output_folder).unwrap();
+                let searcher = index.searcher();
+                assert_eq!(searcher.count("host_4").unwrap(), 333, "{settings:?}");
+                let docs = searcher.search("2999").unwrap();
+                assert_eq!(index.retrieve_doc(&docs).unwrap(), lines[2999..]);
+                let mut decompressed = Vec::new();
+                index.decompress_all(&mut decompressed).unwrap();
+                let expected: String = lines.iter().map(|line| format!("{line}\n")).collect();
+                assert_eq!(String::from_utf8(decompressed).unwrap(), expected);
+            };
+            check_index();
+            writer.merge_segments().unwrap();
+            check_index();
+            let has_dictionary = temp_dir
+                .path()
+                .read_dir()
+                .unwrap()
+                .any(|entry| entry.unwrap().path().join("zstd_dictionary").exists());
+            assert_eq!(has_dictionary, settings.zstd_dictionary_size.is_some());
+        }
+    }
+
+    #[test]
+    fn integration_test_json_documents() {
+        use crate::query::Query;
+
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let docs: Vec<String> = (0..300)
+            .map(|i| {
+                let severity = if i % 7 == 0 { "ERROR" } else { "INFO" };
+                serde_json::json!({
+                    "timestamp": 1_700_000_000_000u64 + i,
+                    "severity_text": severity,
+                    "body": format!("Receiving block blk_{i} src: /10.0.0.{} dest: /10.0.1.1", i % 5),
+                    "resource": { "service": format!("datanode/{:02}", i % 3) },
+                    "attributes": { "retry": i % 2 == 0, "offset": -(i as i64), "ratio": i as f64 / 8.0 },
+                    "tags": ["a", i],
+                    "parent": null,
+                })
+                .to_string()
+            })
+            .collect();
+        let mut lines: Vec<&str> = docs.iter().map(String::as_str).collect();
+        // Documents may have any schema, and blank lines are skipped.
+        lines.insert(10, r#"{"flag": true, "count": 18446744073709551615}"#);
+        lines.insert(11, "");
+        lines.push(r#"{"body": "ERROR in a different schema", "body2": "datanode"}"#);
+        let expected: Vec<serde_json::Value> = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        let writer = IndexWriter::new(output_folder.to_string());
+        writer.index_json(lines[..150].iter()).unwrap();
+        // Append with a small budget, to write several segments.
+        let writer = writer.with_memory_budget(4096);
+        writer.index_json(lines[150..].iter()).unwrap();
+        assert!(writer.index(["a log line"].into_iter(), false).is_err());
+
+        let check_index = || {
+            let index = Index::new(output_folder).unwrap();
+            let searcher = index.searcher();
+            let parse =
+                |json: &String| -> serde_json::Value { serde_json::from_str(json).unwrap() };
+            let retrieved = index
+                .retrieve_json(&(0..expected.len() as u32).collect::<Vec<_>>())
+                .unwrap();
+            assert_eq!(retrieved.iter().map(parse).collect::<Vec<_>>(), expected);
+            // Documents of several segments, in any order.
+            let doc_ids: Vec<u32> = (0..expected.len() as u32).rev().step_by(7).collect();
+            let retrieved = index.retrieve_json(&doc_ids).unwrap();
+            for (json, doc_id) in retrieved.iter().zip(&doc_ids) {
+                assert_eq!(parse(json), expected[*doc_id as usize]);
+            }
+            let mut decompressed = Vec::new();
+            index.decompress_all(&mut decompressed).unwrap();
+            let decompressed: Vec<serde_json::Value> = String::from_utf8(decompressed)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            assert_eq!(decompressed, expected);
+            // The schema tree lists the distinct schemas with their documents.
+            let schemas = index.schema_tree().unwrap().schemas();
+            let mut num_docs: Vec<u64> = schemas.iter().map(|(_, num_docs)| *num_docs).collect();
+            num_docs.sort_unstable();
+            assert_eq!(num_docs, [1, 1, 300]);
+
+            let hits = searcher.search_json("ERROR").unwrap();
+            let expected_hits: Vec<u32> = expected
+                .iter()
+                .enumerate()
+                .filter(|(_, doc)| doc.to_string().contains("ERROR"))
+                .map(|(doc_id, _)| doc_id as u32)
+                .collect();
+            assert_eq!(hits, expected_hits);
+            assert_eq!(hits.len(), 44);
+            // The clauses of AND may match different leaves of a document.
+            let hits = searcher.search_json("ERROR AND datanode/01").unwrap();
+            assert_eq!(index.retrieve_json(&hits).unwrap().len(), 14);
+            let hits = searcher
+                .search_json("ERROR AND NOT datanode/01 AND NOT datanode/02")
+                .unwrap();
+            assert_eq!(hits.len(), 16);
+            assert_eq!(
+                parse(&index.retrieve_by_line(301).unwrap().unwrap()),
+                expected[301]
+            );
+            assert_eq!(index.retrieve_by_line(302).unwrap(), None);
+
+            let page = searcher
+                .search_json_query(
+                    &Query::parse("blk_12*").unwrap(),
+                    &Pagination {
+                        limit: Some(5),
+                        offset: 2,
+                        cursor: None,
+                    },
+                )
+                .unwrap();
+            // blk_12 is doc 13 after the inserted document, blk_120 to blk_129 follow.
+            assert_eq!(page.doc_ids, [122, 123, 124, 125, 126]);
+            let page = searcher
+                .search_json_query(
+                    &Query::parse("blk_12*").unwrap(),
+                    &Pagination {
+                        limit: None,
+                        offset: 0,
+                        cursor: page.next_cursor,
+                    },
+                )
+                .unwrap();
+            assert_eq!(page.doc_ids, [127, 128, 129, 130]);
+        };
+        check_index();
+        assert!(Index::new(output_folder).unwrap().segments.len() > 2);
+        writer.merge_segments().unwrap();
+        assert_eq!(Index::new(output_folder).unwrap().segments.len(), 1);
+        check_index();
+
+        let log_dir = TempDir::new().unwrap();
+        let log_folder = log_dir.path().to_str().unwrap();
+        index(log_folder, ["a log line"].into_iter());
+        let writer = IndexWriter::new(log_folder.to_string());
+        assert!(writer.index_json(lines.iter()).is_err());
+        let searcher = Index::new(log_folder).unwrap().searcher();
+        assert!(searcher.search_json("line").is_err());
+    }
+
+    #[test]
+    fn integration_test_json_fields() {
+        let temp_dir = TempDir::new().unwrap();
+        let output_folder = temp_dir.path().to_str().unwrap();
+        let docs: Vec<serde_json::Value> = (0..100)
+            .map(|i| {
+                let severity = if i % 7 == 0 { "ERROR" } else { "INFO" };
+                serde_json::json!({
+                    "severity_text": severity,
+                    "body": format!("{severity} reading block blk_{i} from datanode/{:02}", i % 4),
+                    "resource": { "service": format!("datanode/{:02}", i % 3) },
+                    "attributes": { "retry": i % 2 == 0, "offset": -(i as i64), "ratio": i as f64 / 8.0 },
+                    "parent": null,
+                    "tags": if i % 10 == 0 {
+                        serde_json::json!([])
+                    } else {
+                        serde_json::json!([format!("host-{}", i % 5), i, [{ "span": format!("span_{i}"), "ok": true }, ""]])
+                    },
+                })
+            })
+            .chain([serde_json::json!({ "resource.service": "datanode/01", "retry": "true" })])
+            .collect();
+        let lines: Vec<String> = docs.iter().map(|doc| doc.to_string()).collect();
+        IndexWriter::new(output_folder.to_string())
+            .index_json(lines.iter())
+            .unwrap();
+        let json_index = Index::new(output_folder).unwrap();
+        let searcher = json_index.searcher();
+        let expected_hits = |matches: &dyn Fn(usize) -> bool| -> Vec<u32> {
+            (0..100).filter(|i| matches(*i)).map(|i| i as u32).collect()
+        };
+        // Compact documents are reconstructed byte for byte, with their keys in order.
+        let retrieved = json_index
+            .retrieve_json(&(0..docs.len() as u32).collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(retrieved, lines);
+
+        // Without a field, datanode/01 also matches the body.
+        let hits = searcher.search_json("ERROR AND datanode/01").unwrap();
+        assert_eq!(
+            hits,
+            expected_hits(&|i| i % 7 == 0 && (i % 3 == 1 || i % 4 == 1))
+        );
+        let hits = searcher
+            .search_json("severity_text:ERROR AND resource.service:datanode/01")
+            .unwrap();
+        assert_eq!(hits, expected_hits(&|i| i % 7 == 0 && i % 3 == 1));
+        // Keys may contain dots, and a path to an object has no leaves.
+        let hits = searcher
+            .search_json("resource.service:datanode/01")
+            .unwrap();
+        let mut expected = expected_hits(&|i| i % 3 == 1);
+        expected.push(100);
+        assert_eq!(hits, expected);
+        assert!(
+            searcher
+                .search_json("resource:datanode/01")
+                .unwrap()
+                .is_empty()
+        );
+        let hits = searcher
+            .search_json(r#"body:"reading block" AND NOT body:datanode/00"#)
+            .unwrap();
+        assert_eq!(hits, expected_hits(&|i| i % 4 != 0));
+        assert_eq!(
+            searcher.search_json("body:(blk_1 OR blk_2)").unwrap(),
+            [1, 2]
+        );
+
+        // Terms and ranges match typed leaves by value.
+        let hits = searcher.search_json("attributes.retry:true").unwrap();
+        assert_eq!(hits, expected_hits(&|i| i % 2 == 0));
+        assert_eq!(searcher.search_json("retry:true").unwrap(), [100]);
+        assert_eq!(searcher.search_json("attributes.offset:-42").unwrap(), [42]);
+        assert_eq!(searcher.search_json("attributes.ratio:1.5").unwrap(), [12]);
+        assert_eq!(searcher.search_json("attributes.ratio:2").unwrap(), [16]);
+        let hits = searcher.search_json("attributes.ratio:>=12").unwrap();
+        assert_eq!(hits, expected_hits(&|i| i >= 96));
+        let hits = searcher.search_json("parent:null").unwrap();
+        assert_eq!(hits, expected_hits(&|_| true));
+
+        // The strings in arrays are searchable, the other values in a field.
+        assert_eq!(searcher.search_json("span_17").unwrap(), [17]);
+        let hits = searcher.search_json("tags:host-3").unwrap();
+        assert_eq!(hits, expected_hits(&|i| i % 5 == 3));
+        assert_eq!(searcher.search_json("tags:17").unwrap(), [17]);
+        let hits = searcher.search_json("tags:>=95").unwrap();
+        assert_eq!(hits, expected_hits(&|i| i >= 95 && i % 10 != 0));
+        let hits = searcher.search_json("tags:true").unwrap();
+        assert_eq!(hits, expected_hits(&|i| i % 10 != 0));
+        assert!(searcher.search_json("body:span_17").unwrap().is_empty());
+
+        // Unknown paths and values in other leaves match nothing.
+        assert!(searcher.search_json("missing:ERROR").unwrap().is_empty());
+        assert!(
+            searcher
+                .search_json("body:INFO AND severity_text:ERROR")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            searcher
+                .search_json("severity_text:datanode/01")
+                .unwrap()
+                .is_empty()
+        );
+        // Field queries need JSON documents.
+        let log_dir = TempDir::new().unwrap();
+        let log_folder = log_dir.path().to_str().unwrap();
+        index(log_folder, ["level:ERROR"].into_iter());
+        let searcher = Index::new(log_folder).unwrap().searcher();
+        assert_eq!(searcher.search("level:ERROR").unwrap().len(), 1);
+    }
 }
@@ -1,12 +1,16 @@
 use std::cmp::max;
 use std::fs::File;
-use std::io::{BufRead, Write};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 use std::{fs, io};
 
 use moshiki::constants::DICTIONARY_NAME;
 use moshiki::index::Index;
 use moshiki::indexing::IndexWriter;
+use moshiki::query::Query;
+use moshiki::search::Pagination;
+use moshiki::settings::IndexSettings;
+use moshiki::timestamp::{TimeRange, TimestampFormat};
 
 use tikv_jemallocator::Jemalloc;
 
@@ -127,14 +131,18 @@ fn print_reports(reports: &[Report]) {
     }
 }
 
-/// Return the sum of `len()` for every regular file **directly inside** `dir`.
+/// Return the sum of `len()` for every regular file inside `dir` and its sub-directories
+/// for which `include` returns true.
 ///
-/// * Sub-directories, symlinks, sockets, etc. are ignored.
-pub fn folder_size<P: AsRef<Path>>(dir: P) -> io::Result<u64> {
+/// * Symlinks, sockets, etc. are ignored.
+pub fn folder_size<P: AsRef<Path>>(dir: P, include: &impl Fn(&Path) -> bool) -> io::Result<u64> {
     let mut total: u64 = 0;
     for entry in fs::read_dir(dir)? {
-        let md = entry?.metadata()?;
-        if md.is_file() {
+        let entry = entry?;
+        let md = entry.metadata()?;
+        if md.is_dir() {
+            total += folder_size(entry.path(), include)?;
+        } else if md.is_file() && include(&entry.path()) {
             total += md.len();
         }
     }
@@ -152,15 +160,18 @@ fn generate_report(ndjson_files: &[String], output_folder: &str) -> io::Result<(
             print!("{ndjson_file}, ");
         }
         let start_time = std::time::Instant::now();
-        index_file(ndjson_file, output_folder, false)?;
+        remove_index(output_folder)?;
+        index_file(
+            ndjson_file,
+            &IndexWriter::new(output_folder.to_string()),
+            false,
+        )?;
 
         let file_size = fs::metadata(ndjson_file)
             .expect("Failed to get file metadata")
             .len();
         let output_folder = Path::new(output_folder);
-        let dict_size = fs::metadata(output_folder.join(DICTIONARY_NAME))
-            .map(|md| md.len())
-            .unwrap_or(0);
+        let dict_size = folder_size(output_folder, &|path| path.ends_with(DICTIONARY_NAME))?;
 
         // Open the index to compute the uncompressed size of all columns.
         let index = Index::new(output_folder.to_str().unwrap())?;
@@ -176,7 +187,7 @@ fn generate_report(ndjson_files: &[String], output_folder: &str) -> io::Result<(
             input_size: file_size,
             uncompressed_cols_size,
             zstd_compressed_size: zstd_compressed_size(ndjson_file)?,
-            output_size: folder_size(output_folder)?,
+            output_size: folder_size(output_folder, &|_| true)?,
             dictionary_size: dict_size,
         });
     }
@@ -206,47 +217,230 @@ fn main() {
         return;
     }
     if args.get(1) == Some(&"search".to_string()) {
-        let search_term = args.get(2).expect("Search term is required");
-        let output_folder = args.get(3).expect("Output folder is required");
-        let searcher = Index::new(output_folder)
-            .expect("Failed to create searcher")
-            .searcher();
-        let res = searcher
-            .search_and_retrieve(search_term)
+        if args.len() < 4 {
+            eprintln!(
+                "Usage: {} search <query> <index_folder> [--limit <n>] [--offset <n>] [--count] [--from <time>] [--to <time>]\n\
+                 A range after a term, like `latency > 500`, matches lines where the number after \
+                 `latency` is above 500.",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+        let search_term = &args[2];
+        let output_folder = &args[3];
+        let index = Index::new(output_folder).expect("Failed to create searcher");
+        let searcher = index.searcher();
+
+        let mut pagination = Pagination::default();
+        let mut count_only = false;
+        let mut time_range: Option<TimeRange> = None;
+        let mut flags = args.get(4..).unwrap_or(&[]).iter();
+        while let Some(flag) = flags.next() {
+            let mut value = || {
+                flags
+                    .next()
+                    .and_then(|value| value.parse::<usize>().ok())
+                    .unwrap_or_else(|| panic!("{flag} requires a number"))
+            };
+            match flag.as_str() {
+                "--limit" => pagination.limit = Some(value()),
+                "--offset" => pagination.offset = value(),
+                "--count" => count_only = true,
+                "--from" | "--to" => {
+                    let time = flags
+                        .next()
+                        .and_then(|value| parse_time(value))
+                        .unwrap_or_else(|| panic!("{flag} requires a timestamp"));
+                    let range = time_range.get_or_insert(TimeRange {
+                        from: i64::MIN,
+                        to: i64::MAX,
+                    });
+                    if flag == "--from" {
+                        range.from = time;
+                    } else {
+                        range.to = time;
+                    }
+                }
+                _ => panic!("Unknown search option: {flag}"),
+            }
+        }
+
+        let mut query = if index.schema_tree().is_some() {
+            Query::parse_fields(search_term)
+        } else {
+            Query::parse(search_term)
+        }
+        .expect("Failed to parse query");
+        if let Some(time_range) = time_range {
+            query = Query::And(vec![query, Query::TimeRange(time_range)]);
+        }
+        if index.schema_tree().is_some() {
+            if count_only {
+                pagination = Pagination::default();
+            }
+            let page = searcher
+                .search_json_query(&query, &pagination)
+                .expect("Failed to search");
+            if count_only {
+                println!("{}", page.doc_ids.len());
+                return;
+            }
+            let res = index
+                .retrieve_json(&page.doc_ids)
+                .expect("Failed to retrieve");
+            for doc in res {
+                println!("{doc}");
+            }
+            return;
+        }
+        if count_only {
+            let count = searcher.count_query(&query).expect("Failed to search");
+            println!("{count}");
+            return;
+        }
+        let page = searcher
+            .search_query(&query, &pagination)
             .expect("Failed to search");
+        let res = index.retrieve_doc(&page.docs).expect("Failed to retrieve");
         for doc in res {
             println!("{doc}");
         }
         return;
     }
+    if args.get(1) == Some(&"decompress".to_string()) {
+        let index_folder = args.get(2).expect("Index folder is required");
+        let output_file = args.get(3).expect("Output file is required");
+        let index = Index::new(index_folder).expect("Failed to open index");
+        let writer = io::BufWriter::new(File::create(output_file).expect("Failed to create file"));
+        index
+            .decompress_all(writer)
+            .expect("Failed to decompress index");
+        return;
+    }
+    if args.get(1) == Some(&"merge".to_string()) {
+        let index_folder = args.get(2).expect("Index folder is required");
+        IndexWriter::new(index_folder.to_string())
+            .merge_segments()
+            .expect("Failed to merge segments");
+        return;
+    }
+    // `append` adds the file as a new segment instead of replacing the index.
+    let append = args.get(1) == Some(&"append".to_string());
+    let args = if append { &args[1..] } else { &args[..] };
     if args.len() < 3 {
-        eprintln!("Usage: {} <ndjson_file> <output_folder>", args[0]);
+        eprintln!(
+            "Usage: {} [append] <ndjson_file> <output_folder> [--timestamp-format <format>]... [--memory-budget <MB>] [--num-threads <n>] [--postings-threshold <rows>] [--codec <zstd[:level]|lz4|none>] [--zstd-dictionary <KB>] [--json]",
+            args[0]
+        );
         std::process::exit(1);
     }
     let ndjson_file = &args[1];
     let output_folder = &args[2];
+    let mut timestamp_formats = Vec::new();
+    let mut settings: Option<IndexSettings> = None;
+    let mut json = false;
+    let mut writer = IndexWriter::new(output_folder.to_string());
+    let mut flags = args[3..].iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--timestamp-format" => {
+                let format = flags.next().expect("--timestamp-format requires a format");
+                timestamp_formats.push(format.parse().expect("Invalid timestamp format"));
+            }
+            "--memory-budget" => {
+                let megabytes: usize = flags
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect("--memory-budget requires a number of MB");
+                writer = writer.with_memory_budget(megabytes * 1024 * 1024);
+            }
+            "--num-threads" => {
+                let num_threads: usize = flags
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect("--num-threads requires a number");
+                writer = writer.with_num_threads(num_threads);
+            }
+            "--postings-threshold" => {
+                let max_rows: usize = flags
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect("--postings-threshold requires a number of rows");
+                writer = writer.with_postings_threshold(max_rows);
+            }
+            "--codec" => {
+                let codec = flags.next().expect("--codec requires a codec");
+                settings.get_or_insert_default().codec = codec.parse().expect("Invalid codec");
+            }
+            "--zstd-dictionary" => {
+                let kilobytes: usize = flags
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .expect("--zstd-dictionary requires a number of KB");
+                settings.get_or_insert_default().zstd_dictionary_size = Some(kilobytes * 1024);
+            }
+            "--json" => json = true,
+            _ => panic!("Unknown index option: {flag}"),
+        }
+    }
+    let mut writer = writer.with_timestamp_formats(timestamp_formats);
+    if let Some(settings) = settings {
+        writer = writer.with_settings(settings);
+    }
+
+    if !append {
+        remove_index(output_folder).unwrap();
+    }
+    if json {
+        index_json_file(ndjson_file, &writer).unwrap();
+    } else {
+        let writer =
+            writer.with_unterminated_last_line(last_line_unterminated(ndjson_file).unwrap());
+        index_file(ndjson_file, &writer, true).unwrap();
+    }
+}
+
+/// Delete the index folder if it exists.
+fn remove_index(output_folder: &str) -> io::Result<()> {
+    if Path::new(output_folder).exists() {
+        fs::remove_dir_all(output_folder)?;
+    }
+    Ok(())
+}
+
+/// Returns true if the file doesn't end with `\n`, so decompressing restores it without one.
+fn last_line_unterminated(file: &str) -> io::Result<bool> {
+    let mut file = File::open(file)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last_byte = [0];
+    file.read_exact(&mut last_byte)?;
+    Ok(last_byte[0] != b'\n')
+}
 
-    index_file(ndjson_file, output_folder, true).unwrap();
+/// Parses a time as milliseconds since the epoch, or as ISO-8601.
+fn parse_time(value: &str) -> Option<i64> {
+    value
+        .parse()
+        .ok()
+        .or_else(|| TimestampFormat::Iso8601.parse_prefix(value))
 }
 
-pub fn index_file(ndjson_file: &str, output_folder: &str, report: bool) -> std::io::Result<()> {
+pub fn index_file(ndjson_file: &str, writer: &IndexWriter, report: bool) -> std::io::Result<()> {
     let file_size = fs::metadata(ndjson_file)
         .expect("Failed to get file metadata")
         .len();
     let start_time = std::time::Instant::now();
-    // Delete the output folder if it exists
-    if Path::new(output_folder).exists() {
-        fs::remove_dir_all(output_folder)?;
-    }
-    fs::create_dir_all(output_folder)?;
 
     let file = fs::File::open(ndjson_file)?;
     let reader = std::io::BufReader::new(file);
-    let lines = reader
-        .lines()
-        .map(|line| line.expect("Failed to read line"));
+    // Split on `\n` only, so a `\r` before it is kept and the input can be restored exactly.
+    let lines = reader.split(b'\n').map(|line| {
+        String::from_utf8(line.expect("Failed to read line")).expect("Line is not valid UTF-8")
+    });
 
-    let writer = IndexWriter::new(output_folder.to_string());
     writer.index(lines, report)?;
     if report {
         println!(
@@ -256,3 +450,20 @@ pub fn index_file(ndjson_file: &str, output_folder: &str, report: bool) -> std::
     }
     Ok(())
 }
+
+/// Index each line of the file as a JSON document.
+pub fn index_json_file(ndjson_file: &str, writer: &IndexWriter) -> std::io::Result<()> {
+    let file_size = fs::metadata(ndjson_file)?.len();
+    let start_time = std::time::Instant::now();
+
+    let reader = std::io::BufReader::new(fs::File::open(ndjson_file)?);
+    let lines = reader
+        .lines()
+        .map(|line| line.expect("Failed to read line"));
+    writer.index_json(lines)?;
+    println!(
+        "{ndjson_file}: Throughput: {:.2} MB/s",
+        (file_size as f64 / 1024.0 / 1024.0) / start_time.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Maps every item on up to `num_threads` threads and returns the results in the order of the
+/// items.
+///
+/// Threads take the next item when they are done, so items of uneven cost are balanced.
+pub(crate) fn map_parallel<T, R, F>(items: &[T], num_threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if num_threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next_item = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..num_threads.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut mapped = Vec::new();
+                    loop {
+                        let idx = next_item.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(idx) else {
+                            break;
+                        };
+                        mapped.push((idx, f(item)));
+                    }
+                    mapped
+                })
+            })
+            .collect();
+        for worker in workers {
+            for (idx, result) in worker.join().expect("worker thread panicked") {
+                results[idx] = Some(result);
+            }
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| result.expect("every item is mapped"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_item_order() {
+        let items: Vec<u32> = (0..100).collect();
+        let squares = map_parallel(&items, 4, |item| item * item);
+        assert_eq!(
+            squares,
+            items.iter().map(|item| item * item).collect::<Vec<_>>()
+        );
+    }
+}
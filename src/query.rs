@@ -0,0 +1,730 @@
+//! Query language for the searcher.
+//!
+//! Grammar (operators are case-sensitive):
+//! ```text
+//! or    := and ("OR" and)*
+//! and   := unary ("AND"? unary)*
+//! unary := "NOT" unary | "(" or ")" | phrase | regex | range | term
+//! ```
+//! Adjacent terms without an operator are combined with AND.
+//! A phrase is enclosed in double quotes, `\"` escapes a quote inside a phrase.
+//! A term containing `*` is a wildcard, `*` matches any sequence of characters.
+//! A regex is enclosed in slashes, e.g. `/blk_[0-9]+/`, and `\/` escapes a slash inside it.
+//! A term starting with a slash that doesn't end at the closing slash, like `/var/log/syslog`,
+//! stays a term.
+//! Wildcards and regexes have to match a whole token.
+//! A numeric range is written as `>500`, `>=500`, `<500`, `<=500` or `[100 TO 500]`, with `*`
+//! for an open end. Brackets that don't enclose a range, like `[INFO]`, are part of a term.
+//! A range directly after a term is bound to it as a key, e.g. `latency > 500` matches lines
+//! where the number after `latency` is above 500, like `latency=712` or `latency: 712`.
+//!
+//! Queries for JSON documents parsed with [`Query::parse_fields`] may restrict a term, phrase,
+//! regex, range or parenthesized query to a leaf path, e.g. `severity_text:ERROR`,
+//! `resource.service:datanode/01`, `body:"Receiving block"`, `status:>499` or
+//! `status:[400 TO 499]`.
+//!
+//! [`Query::parse_fields`]: crate::query::Query::parse_fields
+
+use std::io;
+use std::ops::Bound;
+
+use crate::timestamp::TimeRange;
+
+/// A parsed query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    /// A single term, matched against template constants and dictionary terms.
+    Term(String),
+    /// All sub-queries must match.
+    And(Vec<Query>),
+    /// At least one sub-query must match.
+    Or(Vec<Query>),
+    /// The sub-query must not match.
+    Not(Box<Query>),
+    /// A sequence of tokens that must occur at consecutive token positions.
+    ///
+    /// The phrase is split with the same tokenizer as the indexed lines.
+    Phrase(String),
+    /// A term where `*` matches any sequence of characters, e.g. `blk_10740*` or `*Exception`.
+    ///
+    /// The pattern has to match a whole token.
+    Wildcard(String),
+    /// A regex matched against template constants and dictionary terms.
+    ///
+    /// The regex is anchored, it has to match a whole token.
+    Regex(String),
+    /// Matches numbers in the range, in template constants and number columns.
+    ///
+    /// The range matches any number of the line, see [`Query::KeyRange`] for the number after a
+    /// key.
+    Range(NumberRange),
+    /// Matches lines where the number directly after the key is in the range, e.g.
+    /// `latency > 500`.
+    ///
+    /// The key and the number may be separated by `=`, `:` and spaces, like in `latency=712`
+    /// or `latency: 712`.
+    KeyRange {
+        /// The text before the number.
+        key: String,
+        /// The range of the number.
+        range: NumberRange,
+    },
+    /// Matches documents whose timestamp is in the range.
+    ///
+    /// Timestamps are extracted at index time, see
+    /// [`IndexWriter::with_timestamp_formats`](crate::indexing::IndexWriter::with_timestamp_formats).
+    /// This has no query syntax, combine it with a parsed query.
+    TimeRange(TimeRange),
+    /// The sub-query must match the value at a leaf path of a JSON document, e.g.
+    /// `severity_text:ERROR`.
+    ///
+    /// Only parsed by [`Query::parse_fields`]. Terms and ranges also match bool, number and null
+    /// leaves, e.g. `retry:true` or `status:>499`.
+    Field {
+        /// The keys of the path joined with `.`.
+        path: String,
+        /// The query for the value at the path.
+        query: Box<Query>,
+    },
+}
+
+/// A range of numbers, numbers are unsigned integers like in the tokenizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberRange {
+    /// The lower bound of the range.
+    pub lower: Bound<u64>,
+    /// The upper bound of the range.
+    pub upper: Bound<u64>,
+}
+
+impl NumberRange {
+    /// Returns true if the value is in the range.
+    pub fn contains(&self, value: u64) -> bool {
+        let above_lower = match self.lower {
+            Bound::Included(lower) => value >= lower,
+            Bound::Excluded(lower) => value > lower,
+            Bound::Unbounded => true,
+        };
+        let below_upper = match self.upper {
+            Bound::Included(upper) => value <= upper,
+            Bound::Excluded(upper) => value < upper,
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+
+    /// Returns true if the value is in the range, for negative and fractional numbers of JSON
+    /// documents.
+    pub fn contains_f64(&self, value: f64) -> bool {
+        let above_lower = match self.lower {
+            Bound::Included(lower) => value >= lower as f64,
+            Bound::Excluded(lower) => value > lower as f64,
+            Bound::Unbounded => true,
+        };
+        let below_upper = match self.upper {
+            Bound::Included(upper) => value <= upper as f64,
+            Bound::Excluded(upper) => value < upper as f64,
+            Bound::Unbounded => true,
+        };
+        above_lower && below_upper
+    }
+
+    /// Returns true if any value in `min..=max` is in the range.
+    pub fn overlaps(&self, min: u64, max: u64) -> bool {
+        let below_max = match self.lower {
+            Bound::Included(lower) => lower <= max,
+            Bound::Excluded(lower) => lower < max,
+            Bound::Unbounded => true,
+        };
+        let above_min = match self.upper {
+            Bound::Included(upper) => upper >= min,
+            Bound::Excluded(upper) => upper > min,
+            Bound::Unbounded => true,
+        };
+        below_max && above_min
+    }
+
+    /// Parses a comparison like `>=500`.
+    fn from_comparison(comparison: &str) -> Option<NumberRange> {
+        let (operator, number) =
+            comparison.split_at(comparison.find(|c: char| c.is_ascii_digit())?);
+        let number: u64 = number.parse().ok()?;
+        let (lower, upper) = match operator {
+            ">" => (Bound::Excluded(number), Bound::Unbounded),
+            ">=" => (Bound::Included(number), Bound::Unbounded),
+            "<" => (Bound::Unbounded, Bound::Excluded(number)),
+            "<=" => (Bound::Unbounded, Bound::Included(number)),
+            _ => return None,
+        };
+        Some(NumberRange { lower, upper })
+    }
+
+    /// Parses the inside of `[100 TO 500]`, both ends are inclusive.
+    fn from_brackets(range: &str) -> io::Result<NumberRange> {
+        let parse_bound = |bound: &str| -> io::Result<Bound<u64>> {
+            if bound == "*" {
+                return Ok(Bound::Unbounded);
+            }
+            bound
+                .parse()
+                .map(Bound::Included)
+                .map_err(|_| parse_error(format!("invalid range bound {bound:?}")))
+        };
+        match range.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [lower, "TO", upper] => Ok(NumberRange {
+                lower: parse_bound(lower)?,
+                upper: parse_bound(upper)?,
+            }),
+            _ => Err(parse_error(format!("invalid range [{range}]"))),
+        }
+    }
+}
+
+impl Query {
+    /// Parse a query string.
+    pub fn parse(query: &str) -> io::Result<Query> {
+        Self::parse_with(query, false)
+    }
+
+    /// Parse a query string for an index of JSON documents, where `path:query` is a
+    /// [`Query::Field`].
+    ///
+    /// The path ends at the first `:` of a term. Values containing `:` can be searched as a
+    /// phrase, e.g. `"10.10.34.11:50010"`, as can terms with `:` in an index of log lines.
+    pub fn parse_fields(query: &str) -> io::Result<Query> {
+        Self::parse_with(query, true)
+    }
+
+    fn parse_with(query: &str, fields: bool) -> io::Result<Query> {
+        let tokens = lex(query, fields)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            fields,
+        };
+        let query = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(parse_error(format!("unexpected token {token:?}")));
+        }
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum QueryToken {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(String),
+    Phrase(String),
+    Regex(String),
+    Range(NumberRange),
+}
+
+/// With `fields`, a regex or range may also follow the `:` of a path, e.g. `status:[400 TO 499]`.
+fn lex(query: &str, fields: bool) -> io::Result<Vec<QueryToken>> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let flush = |current: &mut String, tokens: &mut Vec<QueryToken>| {
+        if current.is_empty() {
+            return;
+        }
+        let token = match current.as_str() {
+            "AND" => QueryToken::And,
+            "OR" => QueryToken::Or,
+            "NOT" => QueryToken::Not,
+            _ => QueryToken::Term(current.clone()),
+        };
+        tokens.push(token);
+        current.clear();
+    };
+    let starts_value = |current: &String| {
+        current.is_empty() || (fields && current.len() > 1 && current.ends_with(':'))
+    };
+    let mut chars = query.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                flush(&mut current, &mut tokens);
+                tokens.push(QueryToken::Phrase(lex_phrase(&mut chars)?));
+            }
+            '/' if starts_value(&current) => match lex_regex(&chars) {
+                Some((regex, rest)) => {
+                    flush(&mut current, &mut tokens);
+                    tokens.push(QueryToken::Regex(regex));
+                    chars = rest;
+                }
+                None => current.push(c),
+            },
+            '[' if starts_value(&current) => match lex_range(&chars) {
+                Some((range, rest)) => {
+                    flush(&mut current, &mut tokens);
+                    tokens.push(QueryToken::Range(range));
+                    chars = rest;
+                }
+                None => current.push(c),
+            },
+            '(' | ')' => {
+                flush(&mut current, &mut tokens);
+                tokens.push(if c == '(' {
+                    QueryToken::Open
+                } else {
+                    QueryToken::Close
+                });
+            }
+            c if c.is_whitespace() => flush(&mut current, &mut tokens),
+            c => current.push(c),
+        }
+    }
+    flush(&mut current, &mut tokens);
+    Ok(tokens)
+}
+
+/// Reads a phrase up to the closing quote. The opening quote is already consumed.
+fn lex_phrase(chars: &mut std::str::Chars) -> io::Result<String> {
+    let mut phrase = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(phrase),
+            '\\' => match chars.next() {
+                Some(escaped) => phrase.push(escaped),
+                None => break,
+            },
+            c => phrase.push(c),
+        }
+    }
+    Err(parse_error("unterminated phrase".to_string()))
+}
+
+/// Reads a regex up to the closing slash and returns it with the chars after it. The opening
+/// slash is already consumed.
+///
+/// Returns `None` if the closing slash is missing or not followed by whitespace, `)` or the end
+/// of the query, e.g. for the path `/var/log/syslog`, which is a term then.
+///
+/// Only `\/` is unescaped, other escapes are kept for the regex parser.
+fn lex_regex<'a>(chars: &std::str::Chars<'a>) -> Option<(String, std::str::Chars<'a>)> {
+    let mut chars = chars.clone();
+    let mut regex = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' => {
+                return match chars.clone().next() {
+                    None | Some(')') => Some((regex, chars)),
+                    Some(next) if next.is_whitespace() => Some((regex, chars)),
+                    Some(_) => None,
+                };
+            }
+            '\\' => match chars.next() {
+                Some('/') => regex.push('/'),
+                Some(escaped) => {
+                    regex.push('\\');
+                    regex.push(escaped);
+                }
+                None => break,
+            },
+            c => regex.push(c),
+        }
+    }
+    None
+}
+
+/// Reads a range up to the closing bracket and returns it with the chars after it. The opening
+/// bracket is already consumed.
+///
+/// Returns `None` if the brackets don't enclose `<bound> TO <bound>`, e.g. for `[INFO]`, which
+/// is a term then.
+fn lex_range<'a>(chars: &std::str::Chars<'a>) -> Option<(NumberRange, std::str::Chars<'a>)> {
+    let mut chars = chars.clone();
+    let mut range = String::new();
+    for c in chars.by_ref() {
+        if c == ']' {
+            return Some((NumberRange::from_brackets(&range).ok()?, chars));
+        }
+        range.push(c);
+    }
+    None
+}
+
+fn parse_error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+struct Parser {
+    tokens: Vec<QueryToken>,
+    pos: usize,
+    /// Parse `path:query` as a field query.
+    fields: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&QueryToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<QueryToken> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> io::Result<Query> {
+        let mut clauses = vec![self.parse_and()?];
+        while self.peek() == Some(&QueryToken::Or) {
+            self.next();
+            clauses.push(self.parse_and()?);
+        }
+        Ok(flatten(clauses, Query::Or))
+    }
+
+    fn parse_and(&mut self) -> io::Result<Query> {
+        let mut clauses = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                Some(QueryToken::And) => {
+                    self.next();
+                }
+                Some(QueryToken::Or) | Some(QueryToken::Close) | None => break,
+                _ => {}
+            }
+            clauses.push(self.parse_unary()?);
+        }
+        Ok(flatten(clauses, Query::And))
+    }
+
+    fn parse_unary(&mut self) -> io::Result<Query> {
+        match self.next() {
+            Some(QueryToken::Not) => Ok(Query::Not(Box::new(self.parse_unary()?))),
+            Some(QueryToken::Open) => {
+                let query = self.parse_or()?;
+                if self.next() != Some(QueryToken::Close) {
+                    return Err(parse_error("missing closing parenthesis".to_string()));
+                }
+                Ok(query)
+            }
+            Some(QueryToken::Term(term)) => self.parse_term(term),
+            Some(QueryToken::Phrase(phrase)) => Ok(Query::Phrase(phrase)),
+            Some(QueryToken::Regex(regex)) => Ok(Query::Regex(regex)),
+            Some(QueryToken::Range(range)) => Ok(Query::Range(range)),
+            Some(token) => Err(parse_error(format!("unexpected token {token:?}"))),
+            None => Err(parse_error("unexpected end of query".to_string())),
+        }
+    }
+
+    fn parse_term(&mut self, term: String) -> io::Result<Query> {
+        if self.fields
+            && let Some((path, value)) = term.split_once(':')
+            && !path.is_empty()
+        {
+            // `path:` is followed by a phrase or a parenthesized query.
+            let query = if value.is_empty() {
+                self.parse_unary()?
+            } else {
+                self.parse_value(value.to_string())?
+            };
+            return Ok(Query::Field {
+                path: path.to_string(),
+                query: Box::new(query),
+            });
+        }
+        self.parse_value(term)
+    }
+
+    /// Parses a term that isn't split into a path and a value.
+    ///
+    /// Without fields a range after the term is bound to it as a key.
+    fn parse_value(&mut self, term: String) -> io::Result<Query> {
+        if let Some(range) = self.parse_comparison(&term)? {
+            return Ok(Query::Range(range));
+        }
+        if term.contains('*') {
+            return Ok(Query::Wildcard(term));
+        }
+        if !self.fields
+            && let Some(range) = self.parse_key_range()?
+        {
+            return Ok(Query::KeyRange { key: term, range });
+        }
+        Ok(Query::Term(term))
+    }
+
+    /// Parses a comparison like `>500`, reading the number from the next token if the term is
+    /// only the operator.
+    fn parse_comparison(&mut self, term: &str) -> io::Result<Option<NumberRange>> {
+        if is_comparison_operator(term) {
+            let range = match self.next() {
+                Some(QueryToken::Term(number)) => {
+                    NumberRange::from_comparison(&format!("{term}{number}"))
+                }
+                _ => None,
+            };
+            return range
+                .map(Some)
+                .ok_or_else(|| parse_error(format!("expected a number after {term}")));
+        }
+        Ok(NumberRange::from_comparison(term))
+    }
+
+    /// Parses the range after a key if the next token starts one, e.g. `> 500` or
+    /// `[100 TO 500]`.
+    fn parse_key_range(&mut self) -> io::Result<Option<NumberRange>> {
+        match self.peek() {
+            Some(QueryToken::Range(range)) => {
+                let range = *range;
+                self.next();
+                Ok(Some(range))
+            }
+            Some(QueryToken::Term(term))
+                if is_comparison_operator(term) || NumberRange::from_comparison(term).is_some() =>
+            {
+                let term = term.clone();
+                self.next();
+                self.parse_comparison(&term)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn is_comparison_operator(term: &str) -> bool {
+    matches!(term, ">" | ">=" | "<" | "<=")
+}
+
+fn flatten(mut clauses: Vec<Query>, combine: fn(Vec<Query>) -> Query) -> Query {
+    if clauses.len() == 1 {
+        clauses.pop().unwrap()
+    } else {
+        combine(clauses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(text: &str) -> Query {
+        Query::Term(text.to_string())
+    }
+
+    #[test]
+    fn parses_single_term() {
+        assert_eq!(Query::parse("blk_123").unwrap(), term("blk_123"));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let query = Query::parse("a OR b AND NOT c").unwrap();
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                term("a"),
+                Query::And(vec![term("b"), Query::Not(Box::new(term("c")))]),
+            ])
+        );
+    }
+
+    #[test]
+    fn implicit_and_and_parentheses() {
+        let query = Query::parse("(a OR b) c").unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![Query::Or(vec![term("a"), term("b")]), term("c")])
+        );
+    }
+
+    #[test]
+    fn parses_phrases() {
+        let query = Query::parse(r#""PacketResponder terminating" AND "say \"hi\"""#).unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![
+                Query::Phrase("PacketResponder terminating".to_string()),
+                Query::Phrase("say \"hi\"".to_string()),
+            ])
+        );
+        assert!(Query::parse(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn parses_wildcards() {
+        let query = Query::parse("blk_10740* AND NOT *Exception").unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![
+                Query::Wildcard("blk_10740*".to_string()),
+                Query::Not(Box::new(Query::Wildcard("*Exception".to_string()))),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_regexes() {
+        let query = Query::parse(r"/blk_\d+/ OR /a\/b/").unwrap();
+        assert_eq!(
+            query,
+            Query::Or(vec![
+                Query::Regex(r"blk_\d+".to_string()),
+                Query::Regex("a/b".to_string()),
+            ])
+        );
+        assert_eq!(
+            Query::parse("(/a/)").unwrap(),
+            Query::Regex("a".to_string())
+        );
+        // A slash only starts a regex if the closing slash ends the token.
+        assert_eq!(Query::parse("/tmp").unwrap(), term("/tmp"));
+        assert_eq!(
+            Query::parse("/var/log/syslog").unwrap(),
+            term("/var/log/syslog")
+        );
+        assert_eq!(
+            Query::parse("/var/ log").unwrap(),
+            Query::And(vec![Query::Regex("var".to_string()), term("log")])
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let range = |lower, upper| Query::Range(NumberRange { lower, upper });
+        assert_eq!(
+            Query::parse(">500").unwrap(),
+            range(Bound::Excluded(500), Bound::Unbounded)
+        );
+        let key_range = |key: &str, lower, upper| Query::KeyRange {
+            key: key.to_string(),
+            range: NumberRange { lower, upper },
+        };
+        assert_eq!(
+            Query::parse("latency <= 500").unwrap(),
+            key_range("latency", Bound::Unbounded, Bound::Included(500))
+        );
+        assert_eq!(
+            Query::parse("ERROR latency >500").unwrap(),
+            Query::And(vec![
+                term("ERROR"),
+                key_range("latency", Bound::Excluded(500), Bound::Unbounded)
+            ])
+        );
+        assert_eq!(
+            Query::parse("status [400 TO 499]").unwrap(),
+            key_range("status", Bound::Included(400), Bound::Included(499))
+        );
+        assert_eq!(
+            Query::parse("[100 TO *]").unwrap(),
+            range(Bound::Included(100), Bound::Unbounded)
+        );
+        // Brackets without a range are part of a term.
+        assert_eq!(Query::parse("[INFO]").unwrap(), term("[INFO]"));
+        assert_eq!(
+            Query::parse("[main] started").unwrap(),
+            Query::And(vec![term("[main]"), term("started")])
+        );
+        assert_eq!(
+            Query::parse("[100 500]").unwrap(),
+            Query::And(vec![term("[100"), term("500]")])
+        );
+        assert_eq!(
+            Query::parse("[100 TO 500").unwrap(),
+            Query::And(vec![term("[100"), term("TO"), term("500")])
+        );
+        assert!(Query::parse("latency >").is_err());
+        assert_eq!(Query::parse(">x").unwrap(), term(">x"));
+    }
+
+    #[test]
+    fn parses_fields() {
+        let field = |path: &str, query| Query::Field {
+            path: path.to_string(),
+            query: Box::new(query),
+        };
+        let query =
+            Query::parse_fields(r#"severity_text:ERROR AND resource.service:datanode/01"#).unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![
+                field("severity_text", term("ERROR")),
+                field("resource.service", term("datanode/01")),
+            ])
+        );
+        let query = Query::parse_fields(r#"body:"a b" host:10.0.0.1:80 status:>499"#).unwrap();
+        assert_eq!(
+            query,
+            Query::And(vec![
+                field("body", Query::Phrase("a b".to_string())),
+                field("host", term("10.0.0.1:80")),
+                field(
+                    "status",
+                    Query::Range(NumberRange {
+                        lower: Bound::Excluded(499),
+                        upper: Bound::Unbounded
+                    })
+                ),
+            ])
+        );
+        assert_eq!(
+            Query::parse_fields("tag:(a OR blk_*)").unwrap(),
+            field(
+                "tag",
+                Query::Or(vec![term("a"), Query::Wildcard("blk_*".to_string())])
+            )
+        );
+        assert_eq!(
+            Query::parse_fields("status:[400 TO 499] body:/blk_[0-9]+/").unwrap(),
+            Query::And(vec![
+                field(
+                    "status",
+                    Query::Range(NumberRange {
+                        lower: Bound::Included(400),
+                        upper: Bound::Included(499)
+                    })
+                ),
+                field("body", Query::Regex("blk_[0-9]+".to_string())),
+            ])
+        );
+        assert_eq!(
+            Query::parse_fields("tag:[INFO] path:/var/log").unwrap(),
+            Query::And(vec![
+                field("tag", term("[INFO]")),
+                field("path", term("/var/log"))
+            ])
+        );
+        // Without fields, `:` doesn't start a range.
+        assert_eq!(
+            Query::parse("status:[1 TO 2]").unwrap(),
+            Query::And(vec![term("status:[1"), term("TO"), term("2]")])
+        );
+        assert_eq!(Query::parse_fields(":a").unwrap(), term(":a"));
+        assert!(Query::parse_fields("body:").is_err());
+        // Without fields, `:` is part of the term.
+        assert_eq!(
+            Query::parse("10.10.34.11:50010").unwrap(),
+            term("10.10.34.11:50010")
+        );
+    }
+
+    #[test]
+    fn range_overlaps() {
+        let range = NumberRange {
+            lower: Bound::Excluded(10),
+            upper: Bound::Included(20),
+        };
+        assert!(!range.contains(10));
+        assert!(range.contains(20));
+        assert!(range.overlaps(0, 11));
+        assert!(!range.overlaps(0, 10));
+        assert!(range.overlaps(20, 30));
+        assert!(!range.overlaps(21, 30));
+        assert!(range.contains_f64(10.5));
+        assert!(!range.contains_f64(20.5));
+        assert!(!range.contains_f64(-1.0));
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses() {
+        assert!(Query::parse("(a OR b").is_err());
+        assert!(Query::parse("a)").is_err());
+        assert!(Query::parse("a AND").is_err());
+    }
+}
@@ -2,27 +2,36 @@
 
 use std::borrow::Cow;
 use std::fmt;
+use std::fs;
 use std::io;
+use std::path::Path;
 
-use fxhash::FxHashMap;
-use serde::Deserialize;
+use fxhash::{FxHashMap, FxHashSet};
 use serde::de::{DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
 use serde_json::{Map as JsonMap, Value as JsonValue};
 use serde_json_borrow::Value;
 
+use crate::constants::SCHEMA_TREE_FILE_NAME;
+
 /// A unique identifier for a leaf in the schema tree.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct LeafId(pub u32);
 
 /// A list of leaf ids that uniquely identifies a schema.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// The leaves are in the order of their keys in the document, so documents with the same keys
+/// in a different order have different schemas.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SchemaId(pub Vec<LeafId>);
 
 impl SchemaId {
-    /// Create a SchemaId from a list of leaf ids.
+    /// Create a SchemaId from a list of leaf ids in key order.
+    ///
+    /// A leaf that occurs several times keeps its first position, like a repeated key.
     pub fn new(mut ids: Vec<LeafId>) -> Self {
-        ids.sort_unstable();
-        ids.dedup();
+        let mut seen = FxHashSet::default();
+        ids.retain(|id| seen.insert(*id));
         Self(ids)
     }
 
@@ -59,7 +68,7 @@ impl SchemaId {
 
     /// Reconstruct this schema into a serialized JSON object string with caller-provided leaf values.
     ///
-    /// The callback is invoked once for each leaf present in this schema id.
+    /// The callback is invoked once for each leaf present in this schema id, in key order.
     pub fn reconstruct_json_with<F>(&self, tree: &SchemaTree, mut leaf_value_for: F) -> String
     where
         F: FnMut(LeafId, &LeafInfo) -> JsonValue,
@@ -69,15 +78,16 @@ impl SchemaId {
             .expect("serializing reconstructed JSON object should not fail")
     }
 
-    fn contains_leaf_id(&self, leaf_id: LeafId) -> bool {
-        self.0.binary_search(&leaf_id).is_ok()
+    /// Returns true if the schema has the leaf.
+    pub fn contains_leaf_id(&self, leaf_id: LeafId) -> bool {
+        self.0.contains(&leaf_id)
     }
 }
 
 /// The kind of a JSON leaf value.
 ///
-/// Arrays are treated as leaves; their contents are not traversed.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Arrays are leaves of the tree, the paths inside an array get no leaves of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum LeafKind {
     /// JSON null.
     Null,
@@ -87,7 +97,7 @@ pub enum LeafKind {
     Number,
     /// JSON string.
     String,
-    /// JSON array (arrays are treated as leaves).
+    /// JSON array (arrays are leaves).
     Array,
 }
 
@@ -116,7 +126,7 @@ impl LeafKind {
 }
 
 /// Information about a leaf in the schema tree.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct LeafInfo {
     /// The leaf key (last segment of the JSON path).
     pub key: String,
@@ -150,23 +160,32 @@ impl From<io::Error> for SchemaError {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 struct NodeId(u32);
 
 const ROOT_NODE_ID: NodeId = NodeId(0);
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct SchemaNode {
+    /// The key of the node in its parent, empty for the root.
+    key: String,
+    /// The parent of the node, the root is its own parent.
+    parent: NodeId,
     /// Normally, we have less than 10 children per node, so we could use a vector.
     children: FxHashMap<String, NodeId>,
     leaves: [Option<LeafId>; LeafKind::COUNT],
 }
 
 /// A schema tree that de-duplicates leaf paths and assigns leaf ids.
-#[derive(Debug)]
+///
+/// The tree also counts the documents of each distinct schema it ingested.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SchemaTree {
     nodes: Vec<SchemaNode>,
     leaves: Vec<LeafInfo>,
+    /// The node of each leaf.
+    leaf_nodes: Vec<NodeId>,
+    schema_doc_counts: FxHashMap<SchemaId, u64>,
 }
 
 impl Default for SchemaTree {
@@ -174,6 +193,8 @@ impl Default for SchemaTree {
         Self {
             nodes: vec![SchemaNode::default()],
             leaves: Vec::new(),
+            leaf_nodes: Vec::new(),
+            schema_doc_counts: FxHashMap::default(),
         }
     }
 }
@@ -190,6 +211,8 @@ impl SchemaTree {
     }
 
     /// Parse JSON and return its SchemaId, invoking a callback for each leaf.
+    ///
+    /// The document is counted for its schema, see [`SchemaTree::schemas`].
     pub fn ingest_json_with<F>(
         &mut self,
         json: &str,
@@ -209,7 +232,9 @@ impl SchemaTree {
         .deserialize(&mut deserializer);
         if object_parse_result.is_ok() {
             deserializer.end().map_err(Self::parse_error)?;
-            return Ok(SchemaId::new(leaf_ids));
+            let schema_id = SchemaId::new(leaf_ids);
+            *self.schema_doc_counts.entry(schema_id.clone()).or_default() += 1;
+            return Ok(schema_id);
         }
 
         let mut non_object_deserializer = serde_json::Deserializer::from_str(json);
@@ -228,84 +253,76 @@ impl SchemaTree {
         self.leaves.len()
     }
 
-    fn reconstruct_object_with<F>(&self, schema_id: &SchemaId, leaf_value_for: &mut F) -> JsonValue
-    where
-        F: FnMut(LeafId, &LeafInfo) -> JsonValue,
-    {
-        for leaf_id in schema_id.leaf_ids() {
-            let _ = self.leaf_info(*leaf_id);
-        }
+    /// Return the distinct schemas of the ingested documents with their number of documents,
+    /// ordered by schema id.
+    pub fn schemas(&self) -> Vec<(&SchemaId, u64)> {
+        let mut schemas: Vec<(&SchemaId, u64)> = self
+            .schema_doc_counts
+            .iter()
+            .map(|(schema_id, num_docs)| (schema_id, *num_docs))
+            .collect();
+        schemas.sort_unstable_by(|(a, _), (b, _)| a.leaf_ids().cmp(b.leaf_ids()));
+        schemas
+    }
 
-        let mut root_object = JsonMap::new();
-        for (child_key, child_id) in self.sorted_children(ROOT_NODE_ID) {
-            if let Some(child_value) =
-                self.reconstruct_node_value_with(schema_id, child_id, leaf_value_for)
-            {
-                root_object.insert(child_key.to_string(), child_value);
+    /// Lookup the leaves at a path of keys joined with `.`, one per kind of value at the path.
+    ///
+    /// Keys that contain `.` themselves match too, e.g. `resource.service.name` finds the key
+    /// `service.name` in the object `resource`.
+    pub fn leaf_ids_for_path(&self, path: &str) -> Vec<LeafId> {
+        let mut leaf_ids = Vec::new();
+        self.collect_leaf_ids_for_path(ROOT_NODE_ID, path, &mut leaf_ids);
+        leaf_ids.sort_unstable();
+        leaf_ids.dedup();
+        leaf_ids
+    }
+
+    fn collect_leaf_ids_for_path(&self, node_id: NodeId, path: &str, out: &mut Vec<LeafId>) {
+        for (key, child_id) in &self.nodes[node_id.0 as usize].children {
+            let Some(rest) = path.strip_prefix(key.as_str()) else {
+                continue;
+            };
+            if rest.is_empty() {
+                out.extend(self.nodes[child_id.0 as usize].leaves.iter().flatten());
+            } else if let Some(rest) = rest.strip_prefix('.') {
+                self.collect_leaf_ids_for_path(*child_id, rest, out);
             }
         }
-        JsonValue::Object(root_object)
     }
 
-    fn reconstruct_node_value_with<F>(
-        &self,
-        schema_id: &SchemaId,
-        node_id: NodeId,
-        leaf_value_for: &mut F,
-    ) -> Option<JsonValue>
+    /// Builds the object by inserting the leaves in key order, so each object is created at
+    /// the position of its first key.
+    ///
+    /// A key repeated with values of different kinds has several leaves, the later one wins.
+    fn reconstruct_object_with<F>(&self, schema_id: &SchemaId, leaf_value_for: &mut F) -> JsonValue
     where
         F: FnMut(LeafId, &LeafInfo) -> JsonValue,
     {
-        let node = &self.nodes[node_id.0 as usize];
-        let mut selected_leaf_id = None;
-        for leaf_id in node.leaves.iter().flatten().copied() {
-            if schema_id.contains_leaf_id(leaf_id) {
-                if let Some(previous_leaf_id) = selected_leaf_id {
-                    panic!(
-                        "schema id contains multiple leaf kinds for one path: {:?} and {:?}",
-                        previous_leaf_id, leaf_id
-                    );
-                }
-                selected_leaf_id = Some(leaf_id);
-            }
-        }
-
-        let mut child_object = JsonMap::new();
-        for (child_key, child_id) in self.sorted_children(node_id) {
-            if let Some(child_value) =
-                self.reconstruct_node_value_with(schema_id, child_id, leaf_value_for)
-            {
-                child_object.insert(child_key.to_string(), child_value);
+        let mut root_object = JsonMap::new();
+        let mut path = Vec::new();
+        for leaf_id in schema_id.leaf_ids() {
+            path.clear();
+            let mut node_id = self.leaf_nodes[leaf_id.0 as usize];
+            while node_id != ROOT_NODE_ID {
+                let node = &self.nodes[node_id.0 as usize];
+                path.push(node.key.as_str());
+                node_id = node.parent;
             }
-        }
-
-        if let Some(leaf_id) = selected_leaf_id {
-            if !child_object.is_empty() {
-                panic!(
-                    "schema id mixes leaf and object for one path at leaf id {:?}",
-                    leaf_id
-                );
+            let (key, parents) = path.split_first().expect("a leaf is below the root");
+            let mut object = &mut root_object;
+            for parent_key in parents.iter().rev() {
+                let value = object
+                    .entry(parent_key.to_string())
+                    .or_insert_with(|| JsonValue::Object(JsonMap::new()));
+                if !value.is_object() {
+                    *value = JsonValue::Object(JsonMap::new());
+                }
+                object = value.as_object_mut().expect("replaced by an object");
             }
-            let leaf_info = self.leaf_info(leaf_id);
-            return Some(leaf_value_for(leaf_id, leaf_info));
-        }
-
-        if child_object.is_empty() {
-            None
-        } else {
-            Some(JsonValue::Object(child_object))
+            let leaf_info = self.leaf_info(*leaf_id);
+            object.insert(key.to_string(), leaf_value_for(*leaf_id, leaf_info));
         }
-    }
-
-    fn sorted_children(&self, node_id: NodeId) -> Vec<(&str, NodeId)> {
-        let mut children: Vec<(&str, NodeId)> = self.nodes[node_id.0 as usize]
-            .children
-            .iter()
-            .map(|(key, child_id)| (key.as_str(), *child_id))
-            .collect();
-        children
-            .sort_unstable_by(|(child_key_1, _), (child_key_2, _)| child_key_1.cmp(child_key_2));
-        children
+        JsonValue::Object(root_object)
     }
 
     fn get_or_create_child(&mut self, parent_id: NodeId, key: &str) -> NodeId {
@@ -315,7 +332,11 @@ impl SchemaTree {
         }
 
         let child_id = NodeId(self.nodes.len() as u32);
-        self.nodes.push(SchemaNode::default());
+        self.nodes.push(SchemaNode {
+            key: key.to_string(),
+            parent: parent_id,
+            ..SchemaNode::default()
+        });
         self.nodes[parent_index]
             .children
             .insert(key.to_string(), child_id);
@@ -334,6 +355,7 @@ impl SchemaTree {
             key: key.to_string(),
             kind,
         });
+        self.leaf_nodes.push(node_id);
         id
     }
 
@@ -342,6 +364,39 @@ impl SchemaTree {
     }
 }
 
+/// The version of the schema tree file, written before the tree.
+const SCHEMA_TREE_VERSION: u32 = 2;
+
+/// Reads the schema tree of a JSON index, `None` if the index has no JSON documents.
+pub(crate) fn read_schema_tree(index_folder: &Path) -> io::Result<Option<SchemaTree>> {
+    let bytes = match fs::read(index_folder.join(SCHEMA_TREE_FILE_NAME)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let (version, bytes): (u32, _) = postcard::take_from_bytes(&bytes).map_err(io::Error::other)?;
+    if version != SCHEMA_TREE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported schema tree version {version}"),
+        ));
+    }
+    postcard::from_bytes(bytes)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+/// Replaces the schema tree of the index.
+///
+/// The tree only grows, leaf ids stay valid, so segments written with an older tree can be read
+/// with a newer one. The file is replaced with a rename like the segment list.
+pub(crate) fn write_schema_tree(index_folder: &Path, tree: &SchemaTree) -> io::Result<()> {
+    let bytes = postcard::to_allocvec(&(SCHEMA_TREE_VERSION, tree)).map_err(io::Error::other)?;
+    let tmp_path = index_folder.join(format!("{SCHEMA_TREE_FILE_NAME}.tmp"));
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, index_folder.join(SCHEMA_TREE_FILE_NAME))
+}
+
 struct ObjectSeedWithCallback<'a, F> {
     tree: &'a mut SchemaTree,
     node_id: NodeId,
@@ -594,6 +649,61 @@ mod tests {
         assert_eq!(tree.leaf_count(), 2);
     }
 
+    #[test]
+    fn counts_docs_per_schema_and_persists_them() {
+        let mut tree = SchemaTree::new();
+        let schema_id_1 = tree.ingest_json(r#"{"a": 1, "b": "x"}"#).unwrap();
+        let schema_id_2 = tree.ingest_json(r#"{"a": "y"}"#).unwrap();
+        tree.ingest_json(r#"{"a": 2, "b": "z"}"#).unwrap();
+        assert!(tree.ingest_json("[1]").is_err());
+        assert_eq!(tree.schemas(), [(&schema_id_1, 2), (&schema_id_2, 1)]);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(read_schema_tree(dir.path()).unwrap().is_none());
+        write_schema_tree(dir.path(), &tree).unwrap();
+        let read = read_schema_tree(dir.path()).unwrap().unwrap();
+        assert_eq!(read.schemas(), tree.schemas());
+        assert_eq!(read.leaf_ids_for_path("a"), [LeafId(0), LeafId(2)]);
+        assert_eq!(
+            schema_id_1.reconstruct_json(&read),
+            schema_id_1.reconstruct_json(&tree)
+        );
+
+        fs::write(dir.path().join(SCHEMA_TREE_FILE_NAME), [2, 0]).unwrap();
+        assert!(read_schema_tree(dir.path()).is_err());
+    }
+
+    #[test]
+    fn reconstructs_keys_in_document_order() {
+        let mut tree = SchemaTree::new();
+        let json = r#"{"z":0,"nested":{"y":"","x":false},"a":null,"arr":[{"q":1,"p":2}]}"#;
+        let schema_id = tree.ingest_json(json).unwrap();
+        let mut values = FxHashMap::default();
+        tree.ingest_json_with(json, |leaf_id, value| {
+            values.insert(
+                leaf_id,
+                serde_json::from_str::<JsonValue>(&value.to_string()).unwrap(),
+            );
+        })
+        .unwrap();
+        assert_eq!(
+            schema_id.reconstruct_json_with(&tree, |leaf_id, _| values[&leaf_id].clone()),
+            json
+        );
+
+        let reordered = tree
+            .ingest_json(r#"{"a":null,"z":1,"nested":{"x":true,"y":"a"}}"#)
+            .unwrap();
+        assert_ne!(reordered, schema_id);
+        assert_eq!(
+            reordered.reconstruct_json(&tree),
+            r#"{"a":null,"z":0,"nested":{"x":false,"y":""}}"#
+        );
+        // A repeated key keeps its first position and its last value.
+        let repeated = tree.ingest_json(r#"{"a":1,"b":2,"a":"x"}"#).unwrap();
+        assert_eq!(repeated.reconstruct_json(&tree), r#"{"a":"","b":0}"#);
+    }
+
     #[test]
     fn rejects_non_object_root() {
         let mut tree = SchemaTree::new();
@@ -623,6 +733,24 @@ mod tests {
         assert_eq!(seen[0].1, "true");
     }
 
+    #[test]
+    fn looks_up_leaf_ids_by_path() {
+        let mut tree = SchemaTree::new();
+        let schema_id = tree
+            .ingest_json(r#"{"a": {"b": 1, "c.d": "x"}, "e": null}"#)
+            .unwrap();
+        let [b, c_d, e] = schema_id.leaf_ids().try_into().unwrap();
+        tree.ingest_json(r#"{"a": {"b": "text"}}"#).unwrap();
+        let b_text = LeafId(3);
+
+        assert_eq!(tree.leaf_ids_for_path("a.b"), [b, b_text]);
+        assert_eq!(tree.leaf_ids_for_path("a.c.d"), [c_d]);
+        assert_eq!(tree.leaf_ids_for_path("e"), [e]);
+        assert!(tree.leaf_ids_for_path("a").is_empty());
+        assert!(tree.leaf_ids_for_path("a.c").is_empty());
+        assert!(tree.leaf_ids_for_path("missing").is_empty());
+    }
+
     #[test]
     fn reconstructs_leaf_key() {
         let mut tree = SchemaTree::new();
@@ -1,21 +1,784 @@
 use std::io::{self};
 
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 
-use crate::dict::SearchResult;
-use crate::index::Index;
-use crate::templates::MatchResult;
+use crate::automaton::{TermPattern, Wildcard, new_regex};
+use crate::columns::read::{ColumnFile, Columns};
+use crate::columns::{COLUMN_BLOCK_SIZE, ColumnStats};
+use crate::dict::{Postings, SearchResult};
+use crate::index::{Index, Segment};
+use crate::json::JsonDocs;
+use crate::parallel::map_parallel;
+use crate::query::{NumberRange, Query};
+use crate::schema::{LeafId, LeafKind};
+use crate::templates::{MatchResult, TemplateToken, TemplateWithId};
+use crate::timestamp::TimeRange;
+use crate::tokenizer::{Number, Token, Tokenizer, parse_number};
 use crate::{Doc, TemplateId};
 
 /// Searcher is responsible for searching terms in the index and retrieving documents
 pub struct Searcher {
     index: Index,
+    num_threads: usize,
+}
+
+/// Selects which part of the hits a search returns.
+#[derive(Debug, Clone, Default)]
+pub struct Pagination {
+    /// Maximum number of documents to return, `None` returns all hits.
+    pub limit: Option<usize>,
+    /// Number of hits to skip, counted from the cursor if one is set.
+    pub offset: usize,
+    /// Resume from the `next_cursor` of a previous page.
+    pub cursor: Option<Cursor>,
+}
+
+/// Position in the hit order of a search, used to resume paging.
+///
+/// A cursor is only meaningful for the index and query that produced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// First doc id that was not returned yet.
+    doc_id: u32,
+}
+
+/// One page of search hits.
+#[derive(Debug, Clone)]
+pub struct SearchPage {
+    /// The documents of this page.
+    pub docs: Vec<Doc>,
+    /// Cursor to fetch the next page, `None` if there are no more hits.
+    pub next_cursor: Option<Cursor>,
+}
+
+/// One page of the JSON documents matching a query.
+#[derive(Debug, Clone)]
+pub struct JsonSearchPage {
+    /// The doc ids of the documents of this page, see
+    /// [`IndexInner::retrieve_json`](crate::index::IndexInner::retrieve_json).
+    pub doc_ids: Vec<u32>,
+    /// Cursor to fetch the next page, `None` if there are no more hits.
+    pub next_cursor: Option<Cursor>,
+}
+
+/// A query with all dictionary lookups done, so it can be specialized per template.
+enum ResolvedQuery {
+    Term {
+        text: String,
+        search_result: Option<SearchResult>,
+    },
+    And(Vec<ResolvedQuery>),
+    Or(Vec<ResolvedQuery>),
+    Not(Box<ResolvedQuery>),
+    Phrase(Vec<PhraseToken>),
+    /// A wildcard or regex matched against whole tokens.
+    Pattern(PatternMatch),
+    Range(RangeMatch),
+    KeyRange(KeyRangeMatch),
+    TimeRange(TimeRange),
+}
+
+/// A pattern with the dictionary terms it matches.
+struct PatternMatch {
+    pattern: TermPattern,
+    /// The ids of all matching dictionary terms, grouped by the templates using them.
+    term_ids_by_template: FxHashMap<TemplateId, FxHashSet<u32>>,
+    /// The postings of the matching terms.
+    postings: Postings,
+}
+
+impl PatternMatch {
+    fn new(pattern: TermPattern, search_results: Vec<SearchResult>) -> Self {
+        PatternMatch {
+            pattern,
+            postings: collect_postings(&search_results),
+            term_ids_by_template: group_by_template(search_results),
+        }
+    }
+}
+
+/// The punctuation between the key and the number of a [`Query::KeyRange`].
+const KEY_SEPARATOR: &str = "[ \t]*[=:]?[ \t]*";
+
+/// A numeric range with the dictionary terms it matches.
+struct RangeMatch {
+    range: NumberRange,
+    /// The ids of all dictionary terms in the range, grouped by the templates using them.
+    term_ids_by_template: FxHashMap<TemplateId, FxHashSet<u32>>,
+    /// The postings of the terms in the range.
+    postings: Postings,
+}
+
+impl RangeMatch {
+    /// Only columns whose min/max overlap the range are checked. If all terms of a column are
+    /// numbers within the range, the whole template matches.
+    ///
+    /// The column stats are only read for templates whose constants don't match already.
+    fn row_filter(
+        &self,
+        segment: &Segment,
+        template: &TemplateWithId,
+    ) -> io::Result<RowFilter<'_>> {
+        let constant_in_range = |constant: &[u8]| {
+            parse_number(constant).is_some_and(|value| self.range.contains(value))
+        };
+        if template.template.check_match(constant_in_range) == MatchResult::Full {
+            return Ok(RowFilter::Const(true));
+        }
+        if template
+            .template
+            .column_indices()
+            .iter()
+            .all(Option::is_none)
+        {
+            return Ok(RowFilter::Const(false));
+        }
+        let column_stats = segment.open_column_stats(template.template_id)?;
+        Ok(RowFilter::or(column_stats.iter().enumerate().map(
+            |(column, stats)| self.column_filter(template.template_id, column, stats.as_ref()),
+        )))
+    }
+
+    /// Checks the numbers of a column of the template, `stats` are the stats of the column.
+    fn column_filter(
+        &self,
+        template_id: TemplateId,
+        column: usize,
+        stats: Option<&ColumnStats>,
+    ) -> RowFilter<'_> {
+        let Some(stats) = stats else {
+            return RowFilter::Const(false);
+        };
+        if !self.range.overlaps(stats.min, stats.max) {
+            RowFilter::Const(false)
+        } else if stats.all_numbers
+            && self.range.contains(stats.min)
+            && self.range.contains(stats.max)
+        {
+            RowFilter::Const(true)
+        } else if let Some(term_ids) = self.term_ids_by_template.get(&template_id) {
+            RowFilter::ColumnTermIdIn { column, term_ids }
+        } else {
+            RowFilter::Const(false)
+        }
+    }
+}
+
+/// A numeric range bound to the number after a key, e.g. `latency > 500`.
+struct KeyRangeMatch {
+    /// The tokens of the key, followed by a pattern for the separator before the number.
+    key: Vec<PhraseToken>,
+    range: RangeMatch,
+}
+
+impl KeyRangeMatch {
+    /// The key is matched like a phrase at every position of the template, and the part after
+    /// it is checked like a range.
+    ///
+    /// The column stats are only read if the key may be followed by a column.
+    fn row_filter(
+        &self,
+        segment: &Segment,
+        template: &TemplateWithId,
+    ) -> io::Result<RowFilter<'_>> {
+        let parts = &template.template.parts;
+        let column_indices = template.template.column_indices();
+        // The position of the number after each possible match of the key.
+        let mut key_matches = Vec::new();
+        for start in 0..parts.len().saturating_sub(self.key.len()) {
+            let key_filter = RowFilter::and(self.key.iter().enumerate().map(|(offset, token)| {
+                token.row_filter_at(template, &column_indices, start + offset)
+            }));
+            if key_filter != RowFilter::Const(false) {
+                key_matches.push((start + self.key.len(), key_filter));
+            }
+        }
+        let column_stats = if key_matches
+            .iter()
+            .any(|(position, _)| column_indices[*position].is_some())
+        {
+            segment.open_column_stats(template.template_id)?
+        } else {
+            Vec::new()
+        };
+        Ok(RowFilter::or(key_matches.into_iter().map(
+            |(position, key_filter)| {
+                let number_filter = match (&parts[position], column_indices[position]) {
+                    (TemplateToken::Constant(constant), _) => RowFilter::Const(
+                        parse_number(constant)
+                            .is_some_and(|value| self.range.range.contains(value)),
+                    ),
+                    (_, Some(column)) => self.range.column_filter(
+                        template.template_id,
+                        column,
+                        column_stats[column].as_ref(),
+                    ),
+                    (_, None) => RowFilter::Const(false),
+                };
+                RowFilter::and([key_filter, number_filter].into_iter())
+            },
+        )))
+    }
+}
+
+fn row_filter_all<'a>(
+    clauses: &'a [ResolvedQuery],
+    segment: &Segment,
+    template: &TemplateWithId,
+) -> io::Result<Vec<RowFilter<'a>>> {
+    clauses
+        .iter()
+        .map(|clause| clause.row_filter(segment, template))
+        .collect()
+}
+
+/// Collect the postings of the dictionary terms.
+fn collect_postings(search_results: &[SearchResult]) -> Postings {
+    let mut postings = Postings::default();
+    for search_result in search_results {
+        postings.add(search_result);
+    }
+    postings
+}
+
+/// Group the dictionary terms by the templates using them.
+fn group_by_template(search_results: Vec<SearchResult>) -> FxHashMap<TemplateId, FxHashSet<u32>> {
+    let mut term_ids_by_template: FxHashMap<TemplateId, FxHashSet<u32>> = FxHashMap::default();
+    for search_result in search_results {
+        for template_id in search_result.template_ids() {
+            term_ids_by_template
+                .entry(*template_id)
+                .or_default()
+                .insert(search_result.term_id());
+        }
+    }
+    term_ids_by_template
+}
+
+/// One token of a phrase query.
+enum PhraseToken {
+    Exact {
+        /// The token text, compared against template constants.
+        text: Vec<u8>,
+        search_result: Option<SearchResult>,
+    },
+    /// A token that only partially overlaps a template part, at the edges of a substring.
+    Pattern(PatternMatch),
+}
+
+impl PhraseToken {
+    fn collect_postings(&self, postings: &mut Postings) {
+        match self {
+            PhraseToken::Exact { search_result, .. } => {
+                search_result.iter().for_each(|result| postings.add(result))
+            }
+            PhraseToken::Pattern(pattern_match) => postings.extend(&pattern_match.postings),
+        }
+    }
+
+    /// Check the token against the template part at `position`.
+    fn row_filter_at(
+        &self,
+        template: &TemplateWithId,
+        column_indices: &[Option<usize>],
+        position: usize,
+    ) -> RowFilter<'_> {
+        let column = || column_indices[position].expect("variable should have a column");
+        match (self, &template.template.parts[position]) {
+            (PhraseToken::Exact { text, .. }, TemplateToken::Constant(constant)) => {
+                RowFilter::Const(constant == text)
+            }
+            (PhraseToken::Exact { search_result, .. }, TemplateToken::Variable(_)) => {
+                match term_id_in_template(search_result.as_ref(), template.template_id) {
+                    Some(term_id) => RowFilter::ColumnTermId {
+                        column: column(),
+                        term_id,
+                    },
+                    None => RowFilter::Const(false),
+                }
+            }
+            (PhraseToken::Pattern(pattern_match), TemplateToken::Constant(constant)) => {
+                RowFilter::Const(pattern_match.pattern.matches(constant))
+            }
+            (PhraseToken::Pattern(pattern_match), TemplateToken::Variable(_)) => {
+                match pattern_match
+                    .term_ids_by_template
+                    .get(&template.template_id)
+                {
+                    Some(term_ids) => RowFilter::ColumnTermIdIn {
+                        column: column(),
+                        term_ids,
+                    },
+                    None => RowFilter::Const(false),
+                }
+            }
+        }
+    }
+}
+
+/// Returns the term id if the dictionary lists the term as used in the template.
+fn term_id_in_template(
+    search_result: Option<&SearchResult>,
+    template_id: TemplateId,
+) -> Option<u32> {
+    search_result
+        .filter(|search_result| search_result.template_ids().contains(&template_id))
+        .map(SearchResult::term_id)
+}
+
+impl ResolvedQuery {
+    /// Specialize the query for a single template of the segment.
+    ///
+    /// Checks on template constants are resolved here, what remains are checks against the
+    /// term ids in the columns.
+    fn row_filter(
+        &self,
+        segment: &Segment,
+        template: &TemplateWithId,
+    ) -> io::Result<RowFilter<'_>> {
+        Ok(match self {
+            ResolvedQuery::Term {
+                text,
+                search_result,
+            } => match template
+                .template
+                .check_match(|constant| constant == text.as_bytes())
+            {
+                MatchResult::Full => RowFilter::Const(true),
+                MatchResult::VariableMayMatch => {
+                    match term_id_in_template(search_result.as_ref(), template.template_id) {
+                        Some(term_id) => RowFilter::TermId(term_id),
+                        None => RowFilter::Const(false),
+                    }
+                }
+                MatchResult::NoMatch => RowFilter::Const(false),
+            },
+            ResolvedQuery::Pattern(PatternMatch {
+                pattern,
+                term_ids_by_template,
+                ..
+            }) => match template
+                .template
+                .check_match(|constant| pattern.matches(constant))
+            {
+                MatchResult::Full => RowFilter::Const(true),
+                MatchResult::VariableMayMatch => {
+                    match term_ids_by_template.get(&template.template_id) {
+                        Some(term_ids) => RowFilter::TermIdIn(term_ids),
+                        None => RowFilter::Const(false),
+                    }
+                }
+                MatchResult::NoMatch => RowFilter::Const(false),
+            },
+            ResolvedQuery::Range(range_match) => range_match.row_filter(segment, template)?,
+            ResolvedQuery::KeyRange(key_range) => key_range.row_filter(segment, template)?,
+            ResolvedQuery::TimeRange(time_range) => match template.time_range {
+                Some((min, max)) if time_range.overlaps(min, max) => {
+                    RowFilter::TimeRange(*time_range)
+                }
+                _ => RowFilter::Const(false),
+            },
+            ResolvedQuery::And(clauses) => {
+                RowFilter::and(row_filter_all(clauses, segment, template)?.into_iter())
+            }
+            ResolvedQuery::Or(clauses) => {
+                RowFilter::or(row_filter_all(clauses, segment, template)?.into_iter())
+            }
+            ResolvedQuery::Not(clause) => RowFilter::not(clause.row_filter(segment, template)?),
+            ResolvedQuery::Phrase(tokens) => {
+                let num_parts = template.template.parts.len();
+                if tokens.is_empty() || tokens.len() > num_parts {
+                    return Ok(RowFilter::Const(false));
+                }
+                let column_indices = template.template.column_indices();
+                // Try every alignment of the phrase with the template parts.
+                RowFilter::or((0..=num_parts - tokens.len()).map(|start| {
+                    RowFilter::and(tokens.iter().enumerate().map(|(offset, token)| {
+                        token.row_filter_at(template, &column_indices, start + offset)
+                    }))
+                }))
+            }
+        })
+    }
+
+    /// Returns the postings of all terms of the query, which narrow down the rows to check.
+    ///
+    /// Terms below a `NOT` are left out, as their postings can't narrow down the rows.
+    fn postings(&self) -> Postings {
+        let mut postings = Postings::default();
+        self.collect_postings(&mut postings);
+        postings
+    }
+
+    fn collect_postings(&self, postings: &mut Postings) {
+        match self {
+            ResolvedQuery::Term { search_result, .. } => {
+                search_result.iter().for_each(|result| postings.add(result))
+            }
+            ResolvedQuery::Phrase(tokens) => tokens
+                .iter()
+                .for_each(|token| token.collect_postings(postings)),
+            ResolvedQuery::Pattern(pattern_match) => postings.extend(&pattern_match.postings),
+            ResolvedQuery::Range(range_match) => postings.extend(&range_match.postings),
+            ResolvedQuery::KeyRange(key_range) => {
+                key_range
+                    .key
+                    .iter()
+                    .for_each(|token| token.collect_postings(postings));
+                postings.extend(&key_range.range.postings);
+            }
+            ResolvedQuery::And(clauses) | ResolvedQuery::Or(clauses) => clauses
+                .iter()
+                .for_each(|clause| clause.collect_postings(postings)),
+            ResolvedQuery::Not(_) | ResolvedQuery::TimeRange(_) => {}
+        }
+    }
+
+    /// Specialize the query for the templates, skipping those that can't match.
+    fn candidates<'a>(
+        &'a self,
+        segment: &Segment,
+        templates: impl Iterator<Item = &'a TemplateWithId>,
+    ) -> io::Result<Vec<(&'a TemplateWithId, RowFilter<'a>)>> {
+        let mut candidates = Vec::new();
+        for template in templates {
+            let row_filter = self.row_filter(segment, template)?;
+            if row_filter != RowFilter::Const(false) {
+                candidates.push((template, row_filter));
+            }
+        }
+        Ok(candidates)
+    }
+}
+
+/// A per-row predicate for one template.
+///
+/// Constructors simplify eagerly, so a filter that doesn't depend on the row collapses to
+/// `Const` and the template can be skipped or taken whole without reading its columns.
+#[derive(Debug, PartialEq)]
+enum RowFilter<'a> {
+    Const(bool),
+    /// Any column of the row contains the term id.
+    TermId(u32),
+    /// Any column of the row contains one of the term ids.
+    TermIdIn(&'a FxHashSet<u32>),
+    /// The given column of the row contains the term id.
+    ColumnTermId {
+        column: usize,
+        term_id: u32,
+    },
+    /// The given column of the row contains one of the term ids.
+    ColumnTermIdIn {
+        column: usize,
+        term_ids: &'a FxHashSet<u32>,
+    },
+    /// The timestamp of the row is in the range.
+    TimeRange(TimeRange),
+    And(Vec<RowFilter<'a>>),
+    Or(Vec<RowFilter<'a>>),
+    Not(Box<RowFilter<'a>>),
+}
+
+/// The data of a template that is needed to evaluate a [`RowFilter`].
+struct TemplateRows {
+    column_file: ColumnFile,
+    /// The current block, only the columns checked by the filter are loaded.
+    columns: Columns,
+    /// Only loaded if the filter checks timestamps.
+    timestamps: Vec<Option<i64>>,
+    /// The sorted rows that may match according to the postings, `None` if every row may match.
+    candidate_rows: Option<Vec<u32>>,
+}
+
+impl TemplateRows {
+    /// Load the columns of a block that the filter checks.
+    ///
+    /// Returns false without loading anything if the term id ranges of the block show that no
+    /// row of the block matches.
+    fn load_block(&mut self, block: usize, row_filter: &RowFilter) -> io::Result<bool> {
+        if self.block_candidates(block, 0).next().is_none()
+            || !row_filter.may_match_block(&self.column_file, block)
+        {
+            return Ok(false);
+        }
+        self.columns = self
+            .column_file
+            .read_block(block, |column| row_filter.uses_column(column))?;
+        Ok(true)
+    }
+
+    /// The rows of a block that may match, starting at `first_row`.
+    fn block_candidates(&self, block: usize, first_row: u32) -> impl Iterator<Item = u32> + '_ {
+        let rows = self.column_file.block_rows(block);
+        let start = rows.start.max(first_row);
+        let all_rows = self
+            .candidate_rows
+            .is_none()
+            .then_some(start..rows.end)
+            .into_iter()
+            .flatten();
+        let candidate_rows = self
+            .candidate_rows
+            .as_deref()
+            .map_or(&[][..], |candidates| {
+                let from = candidates.partition_point(|row| *row < start);
+                let to = candidates.partition_point(|row| *row < rows.end);
+                &candidates[from..to.max(from)]
+            });
+        all_rows.chain(candidate_rows.iter().copied())
+    }
+}
+
+impl<'a> RowFilter<'a> {
+    fn and(clauses: impl Iterator<Item = RowFilter<'a>>) -> RowFilter<'a> {
+        let mut remaining = Vec::new();
+        for clause in clauses {
+            match clause {
+                RowFilter::Const(false) => return RowFilter::Const(false),
+                RowFilter::Const(true) => {}
+                clause => remaining.push(clause),
+            }
+        }
+        match remaining.len() {
+            0 => RowFilter::Const(true),
+            1 => remaining.pop().unwrap(),
+            _ => RowFilter::And(remaining),
+        }
+    }
+
+    fn or(clauses: impl Iterator<Item = RowFilter<'a>>) -> RowFilter<'a> {
+        let mut remaining = Vec::new();
+        for clause in clauses {
+            match clause {
+                RowFilter::Const(true) => return RowFilter::Const(true),
+                RowFilter::Const(false) => {}
+                clause => remaining.push(clause),
+            }
+        }
+        match remaining.len() {
+            0 => RowFilter::Const(false),
+            1 => remaining.pop().unwrap(),
+            _ => RowFilter::Or(remaining),
+        }
+    }
+
+    fn not(clause: RowFilter<'a>) -> RowFilter<'a> {
+        match clause {
+            RowFilter::Const(value) => RowFilter::Const(!value),
+            RowFilter::Not(inner) => *inner,
+            clause => RowFilter::Not(Box::new(clause)),
+        }
+    }
+
+    fn uses_timestamps(&self) -> bool {
+        match self {
+            RowFilter::TimeRange(_) => true,
+            RowFilter::And(clauses) | RowFilter::Or(clauses) => {
+                clauses.iter().any(RowFilter::uses_timestamps)
+            }
+            RowFilter::Not(clause) => clause.uses_timestamps(),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the filter checks term ids, so postings may narrow down the rows.
+    fn may_use_postings(&self) -> bool {
+        match self {
+            RowFilter::TermId(_)
+            | RowFilter::TermIdIn(_)
+            | RowFilter::ColumnTermId { .. }
+            | RowFilter::ColumnTermIdIn { .. } => true,
+            RowFilter::And(clauses) | RowFilter::Or(clauses) => {
+                clauses.iter().any(RowFilter::may_use_postings)
+            }
+            RowFilter::Not(_) | RowFilter::Const(_) | RowFilter::TimeRange(_) => false,
+        }
+    }
+
+    /// Returns the sorted rows of the template that may match according to the postings, `None`
+    /// if the postings don't rule out any row.
+    ///
+    /// The rows of a term are exact, but `And` keeps only the rows of its most selective clause,
+    /// so the filter still has to be checked on each candidate.
+    fn candidate_rows(&self, postings: &Postings, template_id: TemplateId) -> Option<Vec<u32>> {
+        let rows = |term_id: u32| postings.rows(template_id, term_id).map(<[u32]>::to_vec);
+        match self {
+            RowFilter::Const(false) => Some(Vec::new()),
+            RowFilter::TermId(term_id) | RowFilter::ColumnTermId { term_id, .. } => rows(*term_id),
+            RowFilter::TermIdIn(term_ids) | RowFilter::ColumnTermIdIn { term_ids, .. } => {
+                union_rows(term_ids.iter().map(|term_id| rows(*term_id)))
+            }
+            RowFilter::And(clauses) => clauses
+                .iter()
+                .filter_map(|clause| clause.candidate_rows(postings, template_id))
+                .min_by_key(Vec::len),
+            RowFilter::Or(clauses) => union_rows(
+                clauses
+                    .iter()
+                    .map(|clause| clause.candidate_rows(postings, template_id)),
+            ),
+            RowFilter::Const(true) | RowFilter::TimeRange(_) | RowFilter::Not(_) => None,
+        }
+    }
+
+    /// Returns true if the filter checks the given column.
+    fn uses_column(&self, column: usize) -> bool {
+        match self {
+            RowFilter::TermId(_) | RowFilter::TermIdIn(_) => true,
+            RowFilter::ColumnTermId { column: used, .. }
+            | RowFilter::ColumnTermIdIn { column: used, .. } => *used == column,
+            RowFilter::And(clauses) | RowFilter::Or(clauses) => {
+                clauses.iter().any(|clause| clause.uses_column(column))
+            }
+            RowFilter::Not(clause) => clause.uses_column(column),
+            RowFilter::Const(_) | RowFilter::TimeRange(_) => false,
+        }
+    }
+
+    /// Returns false if no row of the block can match, based on the term id range of each
+    /// column in the block.
+    fn may_match_block(&self, column_file: &ColumnFile, block: usize) -> bool {
+        let may_contain =
+            |column: usize, term_id: u32| column_file.block_may_contain(column, block, term_id);
+        // Checking more term ids than the block has rows costs more than scanning the block.
+        let may_contain_any = |column: usize, term_ids: &FxHashSet<u32>| {
+            term_ids.len() > COLUMN_BLOCK_SIZE
+                || term_ids.iter().any(|term_id| may_contain(column, *term_id))
+        };
+        let columns = 0..column_file.num_columns();
+        match self {
+            RowFilter::Const(value) => *value,
+            RowFilter::TermId(term_id) => {
+                columns.clone().any(|column| may_contain(column, *term_id))
+            }
+            RowFilter::TermIdIn(term_ids) => columns
+                .clone()
+                .any(|column| may_contain_any(column, term_ids)),
+            RowFilter::ColumnTermId { column, term_id } => may_contain(*column, *term_id),
+            RowFilter::ColumnTermIdIn { column, term_ids } => may_contain_any(*column, term_ids),
+            RowFilter::And(clauses) => clauses
+                .iter()
+                .all(|clause| clause.may_match_block(column_file, block)),
+            RowFilter::Or(clauses) => clauses
+                .iter()
+                .any(|clause| clause.may_match_block(column_file, block)),
+            RowFilter::TimeRange(_) | RowFilter::Not(_) => true,
+        }
+    }
+
+    fn matches(&self, rows: &TemplateRows, row: u32) -> bool {
+        let columns = &rows.columns;
+        match self {
+            RowFilter::Const(value) => *value,
+            RowFilter::TermId(term_id) => columns.get_term_ids(row).any(|hit| hit == *term_id),
+            RowFilter::TermIdIn(term_ids) => {
+                columns.get_term_ids(row).any(|hit| term_ids.contains(&hit))
+            }
+            RowFilter::ColumnTermId { column, term_id } => {
+                columns.term_at(*column, row) == Some(*term_id)
+            }
+            RowFilter::ColumnTermIdIn { column, term_ids } => columns
+                .term_at(*column, row)
+                .is_some_and(|term_id| term_ids.contains(&term_id)),
+            RowFilter::TimeRange(time_range) => rows
+                .timestamps
+                .get(row as usize)
+                .copied()
+                .flatten()
+                .is_some_and(|timestamp| time_range.contains(timestamp)),
+            RowFilter::And(clauses) => clauses.iter().all(|clause| clause.matches(rows, row)),
+            RowFilter::Or(clauses) => clauses.iter().any(|clause| clause.matches(rows, row)),
+            RowFilter::Not(clause) => !clause.matches(rows, row),
+        }
+    }
+}
+
+/// Returns the sorted union of the rows, `None` if any of them is `None`.
+fn union_rows(rows: impl Iterator<Item = Option<Vec<u32>>>) -> Option<Vec<u32>> {
+    let mut union = Vec::new();
+    for rows in rows {
+        union.extend(rows?);
+    }
+    union.sort_unstable();
+    union.dedup();
+    Some(union)
+}
+
+fn not_json() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the index doesn't contain JSON documents",
+    )
+}
+
+/// Returns true if a term or range matches the value of a null, bool or number leaf, or one of
+/// these values in an array leaf.
+///
+/// Numbers are compared by value, so `1.0` matches `1`.
+fn typed_value_matches(query: &Query, value: &JsonValue) -> bool {
+    match (query, value) {
+        (_, JsonValue::Array(values)) => {
+            values.iter().any(|value| typed_value_matches(query, value))
+        }
+        (_, JsonValue::Object(map)) => map.values().any(|value| typed_value_matches(query, value)),
+        (Query::Term(text), JsonValue::Number(number)) => {
+            let Ok(term) = text.parse::<serde_json::Number>() else {
+                return false;
+            };
+            match (
+                term.as_i64(),
+                number.as_i64(),
+                term.as_u64(),
+                number.as_u64(),
+            ) {
+                (Some(term), Some(value), _, _) => term == value,
+                (_, _, Some(term), Some(value)) => term == value,
+                _ => term.as_f64() == number.as_f64(),
+            }
+        }
+        (Query::Term(text), JsonValue::Null) => text == "null",
+        (Query::Term(text), JsonValue::Bool(value)) => {
+            *text == if *value { "true" } else { "false" }
+        }
+        (Query::Range(range), JsonValue::Number(number)) => match number.as_u64() {
+            Some(value) => range.contains(value),
+            None => number
+                .as_f64()
+                .is_some_and(|value| range.contains_f64(value)),
+        },
+        _ => false,
+    }
+}
+
+/// Returns the values in both sorted lists.
+fn intersect_sorted(left: &[u32], right: &[u32]) -> Vec<u32> {
+    let mut right = right.iter().peekable();
+    left.iter()
+        .copied()
+        .filter(|value| {
+            while right.next_if(|right| *right < value).is_some() {}
+            right.peek() == Some(&value)
+        })
+        .collect()
 }
 
 impl Searcher {
     /// Create a new Searcher with the given index.
+    ///
+    /// Templates are searched on as many threads as there are cores.
     pub fn new(index: Index) -> Self {
-        Searcher { index }
+        let num_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Searcher { index, num_threads }
+    }
+
+    /// Search the templates of a segment on `num_threads` threads.
+    ///
+    /// The order of the hits doesn't depend on the number of threads.
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = num_threads.max(1);
+        self
     }
 
     /// Search for a term and retrieve the documents that match the term.
@@ -24,95 +787,567 @@ impl Searcher {
         self.index.retrieve_doc(&docs)
     }
 
-    /// Search for a term and retrieve potential templates that match the term.
-    fn get_potential_templates(&self, query: &str) -> FxHashMap<TemplateId, MatchResult> {
-        // Get potential matches
-        let matching_template_ids: FxHashMap<TemplateId, MatchResult> = self
-            .index
-            .templates
+    /// Look up every term of the query in the dictionary of the segment.
+    fn resolve(&self, segment: &Segment, query: &Query) -> io::Result<ResolvedQuery> {
+        let resolve_all = |clauses: &[Query]| -> io::Result<Vec<ResolvedQuery>> {
+            clauses
+                .iter()
+                .map(|clause| self.resolve(segment, clause))
+                .collect()
+        };
+        Ok(match query {
+            Query::Term(text) if Tokenizer::new(text).nth(1).is_some() => {
+                ResolvedQuery::Phrase(self.resolve_substring(segment, text)?)
+            }
+            Query::Term(text) => {
+                // Without `number_as_string` the dictionary has the binary term of a number.
+                let token = Tokenizer::new(text).next();
+                let term = token.as_ref().and_then(|token| token.as_bytes(text));
+                ResolvedQuery::Term {
+                    text: text.clone(),
+                    // The term may not exist in the dictionary, only in the templates.
+                    search_result: segment
+                        .dictionary
+                        .search_single_term(term.unwrap_or(text.as_bytes()))?,
+                }
+            }
+            Query::Wildcard(pattern) => {
+                ResolvedQuery::Pattern(self.resolve_wildcard(segment, Wildcard::new(pattern)?)?)
+            }
+            Query::Regex(pattern) => {
+                let regex = new_regex(pattern)?;
+                let search_results = segment.dictionary.search_automaton(&regex)?;
+                ResolvedQuery::Pattern(PatternMatch::new(TermPattern::Regex(regex), search_results))
+            }
+            Query::Range(range) => ResolvedQuery::Range(self.resolve_range(segment, range)?),
+            Query::KeyRange { key, range } => {
+                let mut tokens = Tokenizer::new(key)
+                    .map(|token| self.resolve_exact_token(segment, &token, key))
+                    .collect::<io::Result<Vec<_>>>()?;
+                let separator = new_regex(KEY_SEPARATOR)?;
+                let search_results = segment.dictionary.search_automaton(&separator)?;
+                tokens.push(PhraseToken::Pattern(PatternMatch::new(
+                    TermPattern::Regex(separator),
+                    search_results,
+                )));
+                ResolvedQuery::KeyRange(KeyRangeMatch {
+                    key: tokens,
+                    range: self.resolve_range(segment, range)?,
+                })
+            }
+            Query::TimeRange(time_range) => ResolvedQuery::TimeRange(*time_range),
+            Query::Field { path, .. } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("field {path:?} can only be searched in JSON documents"),
+                ));
+            }
+            Query::And(clauses) => ResolvedQuery::And(resolve_all(clauses)?),
+            Query::Or(clauses) => ResolvedQuery::Or(resolve_all(clauses)?),
+            Query::Not(clause) => ResolvedQuery::Not(Box::new(self.resolve(segment, clause)?)),
+            Query::Phrase(phrase) => {
+                let tokens = Tokenizer::new(phrase)
+                    .map(|token| self.resolve_exact_token(segment, &token, phrase))
+                    .collect::<io::Result<Vec<_>>>()?;
+                ResolvedQuery::Phrase(tokens)
+            }
+        })
+    }
+
+    fn resolve_range(&self, segment: &Segment, range: &NumberRange) -> io::Result<RangeMatch> {
+        let search_results = segment.dictionary.search_by(|term| {
+            Number::term_value(term).is_some_and(|value| range.contains(value))
+        })?;
+        Ok(RangeMatch {
+            range: *range,
+            postings: collect_postings(&search_results),
+            term_ids_by_template: group_by_template(search_results),
+        })
+    }
+
+    fn resolve_wildcard(&self, segment: &Segment, wildcard: Wildcard) -> io::Result<PatternMatch> {
+        let search_results = match wildcard.as_prefix() {
+            Some(prefix) => segment.dictionary.search_prefix(prefix.as_bytes())?,
+            None => segment.dictionary.search_automaton(wildcard.automaton())?,
+        };
+        Ok(PatternMatch::new(
+            TermPattern::Wildcard(wildcard),
+            search_results,
+        ))
+    }
+
+    /// Resolve a term that spans several tokens, e.g. `10.10.34.11:50010`.
+    ///
+    /// The term may start and end inside a token of the line, so the first token has to be a
+    /// suffix and the last token a prefix of a template part. The tokens in between have to
+    /// match exactly.
+    ///
+    /// Without `number_as_string` numbers are stored as binary in the dictionary, so numbers at
+    /// the edges have to match exactly too.
+    fn resolve_substring(&self, segment: &Segment, text: &str) -> io::Result<Vec<PhraseToken>> {
+        let tokens: Vec<_> = Tokenizer::new(text).collect();
+        let last = tokens.len() - 1;
+        tokens
             .iter()
-            .filter_map(|template| {
-                let match_result = template.template.check_match(query);
-                match match_result {
-                    MatchResult::Full | MatchResult::VariableMayMatch => {
-                        Some((template.template_id, match_result))
-                    }
-                    MatchResult::NoMatch => None,
+            .enumerate()
+            .map(|(position, token)| {
+                let token_text = token.to_string(text);
+                let is_binary_number =
+                    cfg!(not(feature = "number_as_string")) && matches!(token, Token::Number(_));
+                if is_binary_number {
+                    self.resolve_exact_token(segment, token, text)
+                } else if position == 0 {
+                    let wildcard = Wildcard::new(&format!("*{token_text}"))?;
+                    Ok(PhraseToken::Pattern(
+                        self.resolve_wildcard(segment, wildcard)?,
+                    ))
+                } else if position == last {
+                    let wildcard = Wildcard::new(&format!("{token_text}*"))?;
+                    Ok(PhraseToken::Pattern(
+                        self.resolve_wildcard(segment, wildcard)?,
+                    ))
+                } else {
+                    self.resolve_exact_token(segment, token, text)
                 }
             })
-            .collect();
-        matching_template_ids
+            .collect()
+    }
+
+    fn resolve_exact_token(
+        &self,
+        segment: &Segment,
+        token: &Token,
+        text: &str,
+    ) -> io::Result<PhraseToken> {
+        let bytes = token.as_bytes(text).expect("Token should have bytes");
+        Ok(PhraseToken::Exact {
+            text: token.to_string(text).into_bytes(),
+            search_result: segment.dictionary.search_single_term(bytes)?,
+        })
+    }
+
+    /// Load what the row filter needs to check the rows of a template.
+    ///
+    /// No columns are decompressed yet, see [`TemplateRows::load_block`]. The postings of the
+    /// query narrow down the rows if the filter checks term ids.
+    fn open_rows(
+        &self,
+        segment: &Segment,
+        template_id: TemplateId,
+        row_filter: &RowFilter,
+        postings: &Postings,
+    ) -> io::Result<TemplateRows> {
+        let timestamps = if row_filter.uses_timestamps() {
+            segment.open_timestamps(template_id)?
+        } else {
+            Vec::new()
+        };
+        let candidate_rows = if !postings.is_empty() && row_filter.may_use_postings() {
+            row_filter.candidate_rows(postings, template_id)
+        } else {
+            None
+        };
+        Ok(TemplateRows {
+            column_file: segment.open_column_file(template_id)?,
+            columns: Columns::default(),
+            timestamps,
+            candidate_rows,
+        })
     }
 
-    /// Get documents from templates based on the matching template IDs and search result.
+    /// Collect the requested page of hits, ordered by doc id.
+    ///
+    /// Rows of a template are sorted by doc id, so each template contributes at most
+    /// `offset + limit + 1` hits after the cursor. The extra hit tells if there is a next page.
+    /// Segments are visited in doc id order, so later segments are skipped once there are
+    /// enough hits.
     fn get_doc_from_templates(
         &self,
-        matching_template_ids: FxHashMap<TemplateId, MatchResult>,
-        search_result: Option<&SearchResult>,
+        query: &Query,
+        pagination: &Pagination,
+    ) -> io::Result<SearchPage> {
+        let start_doc_id = pagination.cursor.map(|cursor| cursor.doc_id).unwrap_or(0);
+        let max_hits = pagination
+            .limit
+            .map(|limit| pagination.offset.saturating_add(limit).saturating_add(1));
+        let mut hits: Vec<Doc> = Vec::new();
+        for segment in &self.index.segments {
+            if segment.end_doc_id() <= start_doc_id {
+                continue;
+            }
+            if max_hits.is_some_and(|max_hits| hits.len() >= max_hits) {
+                break;
+            }
+            let resolved = self.resolve(segment, query)?;
+            let postings = resolved.postings();
+            let start_doc_id = start_doc_id.saturating_sub(segment.doc_id_offset);
+            let candidates = resolved.candidates(
+                segment,
+                segment
+                    .templates
+                    .iter()
+                    .filter(|template| template.doc_id_range.1 >= start_doc_id),
+            )?;
+            let template_hits =
+                map_parallel(&candidates, self.num_threads, |(template, row_filter)| {
+                    self.template_hits(
+                        segment,
+                        template,
+                        row_filter,
+                        &postings,
+                        start_doc_id,
+                        max_hits,
+                    )
+                });
+            for template_hits in template_hits {
+                hits.extend(template_hits?);
+            }
+
+            // Bound the memory, only the first `max_hits` across all segments are needed.
+            if let Some(max_hits) = max_hits
+                && hits.len() > max_hits
+            {
+                hits.sort_unstable_by_key(|doc| doc.doc_id);
+                hits.truncate(max_hits);
+            }
+        }
+
+        hits.sort_unstable_by_key(|doc| doc.doc_id);
+        let mut hits = hits.into_iter().skip(pagination.offset);
+        let docs: Vec<Doc> = match pagination.limit {
+            Some(limit) => hits.by_ref().take(limit).collect(),
+            None => hits.by_ref().collect(),
+        };
+        let next_cursor = hits.next().map(|doc| Cursor { doc_id: doc.doc_id });
+        Ok(SearchPage { docs, next_cursor })
+    }
+
+    /// Returns up to `max_hits` matching rows of a template, starting at the local doc id
+    /// `start_doc_id`.
+    fn template_hits(
+        &self,
+        segment: &Segment,
+        template: &TemplateWithId,
+        row_filter: &RowFilter,
+        postings: &Postings,
+        start_doc_id: u32,
+        max_hits: Option<usize>,
     ) -> io::Result<Vec<Doc>> {
-        let mut matching_documents: Vec<Doc> = Vec::new();
-        for (template_id, match_result) in matching_template_ids.into_iter() {
-            let docs = match match_result {
-                MatchResult::Full => {
-                    // Constant in template matches
-                    self.search_in_zstd_column(|_| true, template_id, Some(10))?
-                }
-                MatchResult::VariableMayMatch => {
-                    if let Some(search_result) = search_result
-                        && search_result.template_ids().contains(&template_id)
-                    {
-                        // Check if the term ID exists in the zstd column.
-                        let term_id = search_result.term_id();
-                        self.search_in_zstd_column(|hit| term_id == hit, template_id, Some(10))?
-                    } else {
-                        // If the term ID is not found, we skip this template.
-                        continue;
-                    }
+        let template_id = template.template_id;
+        let doc_ids = segment.open_doc_ids(template_id)?;
+        let first_row = doc_ids.partition_point(|doc_id| *doc_id < start_doc_id);
+        let mut rows = self.open_rows(segment, template_id, row_filter, postings)?;
+        let mut hits = Vec::new();
+        let mut hit_rows = Vec::new();
+        for block in first_row / COLUMN_BLOCK_SIZE..rows.column_file.num_blocks() {
+            if Some(hits.len()) == max_hits {
+                break;
+            }
+            if !rows.load_block(block, row_filter)? {
+                continue;
+            }
+            hit_rows.clear();
+            for row in rows.block_candidates(block, first_row as u32) {
+                if Some(hits.len() + hit_rows.len()) == max_hits {
+                    break;
                 }
-                MatchResult::NoMatch => {
-                    continue;
+                if row_filter.matches(&rows, row) {
+                    hit_rows.push(row);
                 }
-            };
-            matching_documents.extend(docs.into_iter().map(|term_ids| Doc {
+            }
+            if hit_rows.is_empty() {
+                continue;
+            }
+            // The other columns are only needed to return the term ids of the hits.
+            rows.column_file.read_missing_columns(&mut rows.columns)?;
+            hits.extend(hit_rows.iter().map(|row| Doc {
+                doc_id: segment.doc_id_offset + doc_ids[*row as usize],
                 template_id,
-                term_ids,
+                term_ids: rows.columns.get_term_ids(*row).collect(),
             }));
         }
+        Ok(hits)
+    }
+
+    /// Parse the query with [`Query::parse`] and return all matching documents.
+    pub fn search(&self, query: &str) -> io::Result<Vec<Doc>> {
+        let query = Query::parse(query)?;
+        Ok(self.search_query(&query, &Pagination::default())?.docs)
+    }
 
-        Ok(matching_documents)
+    /// Parse the query with [`Query::parse`] and return one page of matching documents.
+    pub fn search_page(&self, query: &str, pagination: &Pagination) -> io::Result<SearchPage> {
+        let query = Query::parse(query)?;
+        self.search_query(&query, pagination)
     }
 
-    /// TODO: Only single term search is implemented.
+    /// Each segment is searched on its own:
     ///
-    /// 1. Search for a term in the dictionary - this will return the term ID and associated
+    /// 1. Search every term in the dictionary - this will return the term ID and associated
     ///    template IDs.
-    /// 2. Check which of the templates match the term.
-    /// 3. Scan the zstd column files for the term ID to see if it exists in the template.
-    /// 4. If the term ID exists in the template, return all term IDs of the document.
+    /// 2. Specialize the query per template: constants are checked once per template, and
+    ///    templates that can't match are skipped.
+    /// 3. Decompress the columns the query checks in the remaining templates and check the term
+    ///    IDs of each row. Blocks of rows whose term ID ranges can't match are skipped, and for
+    ///    terms with postings only their rows are checked.
+    /// 4. For matching rows, decompress the other columns and return all term IDs of the
+    ///    document.
     /// 5. Use the term IDs with the template to reconstruct the documents.
     ///
-    pub fn search(&self, query: &str) -> io::Result<Vec<Doc>> {
-        let term = query.as_bytes();
-        // The term may not exist in the dictionary, only in the templates.
-        let search_result = self.index.dictionary.search_single_term(term)?;
+    /// Hits are ordered by doc id, i.e. by their position in the indexed input.
+    pub fn search_query(&self, query: &Query, pagination: &Pagination) -> io::Result<SearchPage> {
+        self.get_doc_from_templates(query, pagination)
+    }
+
+    /// Returns the exact number of documents matching the query.
+    ///
+    /// Templates where the query matches a constant are counted without reading their columns.
+    pub fn count(&self, query: &str) -> io::Result<usize> {
+        self.count_query(&Query::parse(query)?)
+    }
+
+    /// Returns the exact number of documents matching the parsed query.
+    pub fn count_query(&self, query: &Query) -> io::Result<usize> {
+        let mut num_hits = 0;
+        for segment in &self.index.segments {
+            let resolved = self.resolve(segment, query)?;
+            let postings = resolved.postings();
+            let candidates = resolved.candidates(segment, segment.templates.iter())?;
+            let counts = map_parallel(
+                &candidates,
+                self.num_threads,
+                |(template, row_filter)| -> io::Result<usize> {
+                    let num_docs = template.num_docs() as u32;
+                    if *row_filter == RowFilter::Const(true) {
+                        return Ok(num_docs as usize);
+                    }
+                    let mut rows =
+                        self.open_rows(segment, template.template_id, row_filter, &postings)?;
+                    // The postings of a single term are exact.
+                    if let RowFilter::TermId(_) = row_filter
+                        && let Some(candidate_rows) = &rows.candidate_rows
+                    {
+                        return Ok(candidate_rows.len());
+                    }
+                    let mut count = 0;
+                    for block in 0..rows.column_file.num_blocks() {
+                        if rows.load_block(block, row_filter)? {
+                            count += rows
+                                .block_candidates(block, 0)
+                                .filter(|row| row_filter.matches(&rows, *row))
+                                .count();
+                        }
+                    }
+                    Ok(count)
+                },
+            );
+            for count in counts {
+                num_hits += count?;
+            }
+        }
+        Ok(num_hits)
+    }
+
+    /// Parse the query with [`Query::parse_fields`] and return the doc ids of all matching JSON
+    /// documents, see [`Searcher::search_json_query`].
+    pub fn search_json(&self, query: &str) -> io::Result<Vec<u32>> {
+        let query = Query::parse_fields(query)?;
+        Ok(self
+            .search_json_query(&query, &Pagination::default())?
+            .doc_ids)
+    }
 
-        // Get potential matches
-        let matching_template_ids: FxHashMap<TemplateId, MatchResult> =
-            self.get_potential_templates(query);
+    /// Search an index of JSON documents and return one page of matching doc ids.
+    ///
+    /// Terms, phrases, patterns and ranges match the string leaves of the documents. `AND`, `OR`
+    /// and `NOT` combine documents, so the clauses of an `AND` may match different leaves of a
+    /// document. Hits are ordered by doc id.
+    ///
+    /// A [`Query::Field`] only matches documents that have a leaf at its path, and its query
+    /// only matches the values of these leaves. Terms and ranges in a field also match bool,
+    /// number and null leaves. A query on an array matches if one of its elements matches. A path
+    /// that is not in the index matches nothing.
+    ///
+    /// The other search methods match single string leaves instead of documents.
+    pub fn search_json_query(
+        &self,
+        query: &Query,
+        pagination: &Pagination,
+    ) -> io::Result<JsonSearchPage> {
+        if self.index.schema_tree().is_none() {
+            return Err(not_json());
+        }
+        let start_doc_id = pagination.cursor.map(|cursor| cursor.doc_id).unwrap_or(0);
+        let max_hits = pagination
+            .limit
+            .map(|limit| pagination.offset.saturating_add(limit).saturating_add(1));
+        let mut hits = Vec::new();
+        for segment in &self.index.segments {
+            let Some(json_docs) = &segment.json_docs else {
+                continue;
+            };
+            if segment.end_json_doc_id() <= start_doc_id {
+                continue;
+            }
+            if max_hits.is_some_and(|max_hits| hits.len() >= max_hits) {
+                break;
+            }
+            let docs = self.json_docs_matching(segment, json_docs, query, None)?;
+            hits.extend(
+                docs.into_iter()
+                    .map(|doc| segment.json_doc_offset + doc)
+                    .filter(|doc_id| *doc_id >= start_doc_id),
+            );
+        }
+
+        let mut hits = hits.into_iter().skip(pagination.offset);
+        let doc_ids: Vec<u32> = match pagination.limit {
+            Some(limit) => hits.by_ref().take(limit).collect(),
+            None => hits.by_ref().collect(),
+        };
+        let next_cursor = hits.next().map(|doc_id| Cursor { doc_id });
+        Ok(JsonSearchPage {
+            doc_ids,
+            next_cursor,
+        })
+    }
+
+    /// Returns the sorted JSON documents of the segment matching the query, local to the
+    /// segment.
+    ///
+    /// Inside a field query, `leaf_ids` are the leaves at its path.
+    fn json_docs_matching(
+        &self,
+        segment: &Segment,
+        json_docs: &JsonDocs,
+        query: &Query,
+        leaf_ids: Option<&[LeafId]>,
+    ) -> io::Result<Vec<u32>> {
+        Ok(match query {
+            Query::Field { path, query } => {
+                let tree = self.index.schema_tree().ok_or_else(not_json)?;
+                let leaf_ids = tree.leaf_ids_for_path(path);
+                let docs = json_docs.docs_with_leaves(&leaf_ids);
+                if docs.is_empty() {
+                    return Ok(docs);
+                }
+                let matching =
+                    self.json_docs_matching(segment, json_docs, query, Some(&leaf_ids))?;
+                intersect_sorted(&docs, &matching)
+            }
+            Query::And(clauses) => {
+                let mut docs: Vec<u32> = (0..json_docs.num_docs()).collect();
+                for clause in clauses {
+                    if docs.is_empty() {
+                        break;
+                    }
+                    let clause_docs =
+                        self.json_docs_matching(segment, json_docs, clause, leaf_ids)?;
+                    docs = intersect_sorted(&docs, &clause_docs);
+                }
+                docs
+            }
+            Query::Or(clauses) => {
+                let mut docs = Vec::new();
+                for clause in clauses {
+                    docs.extend(self.json_docs_matching(segment, json_docs, clause, leaf_ids)?);
+                }
+                docs.sort_unstable();
+                docs.dedup();
+                docs
+            }
+            Query::Not(clause) => {
+                let excluded = self.json_docs_matching(segment, json_docs, clause, leaf_ids)?;
+                let mut excluded = excluded.iter().peekable();
+                (0..json_docs.num_docs())
+                    .filter(|doc| excluded.next_if_eq(&doc).is_none())
+                    .collect()
+            }
+            _ => match leaf_ids {
+                None => json_docs.docs_of_lines(&self.lines_matching(segment, query)?),
+                Some(leaf_ids) => self.leaf_docs_matching(segment, json_docs, query, leaf_ids)?,
+            },
+        })
+    }
+
+    /// Returns the sorted JSON documents of the segment where the value of one of the leaves
+    /// matches the query.
+    fn leaf_docs_matching(
+        &self,
+        segment: &Segment,
+        json_docs: &JsonDocs,
+        query: &Query,
+        leaf_ids: &[LeafId],
+    ) -> io::Result<Vec<u32>> {
+        let tree = self.index.schema_tree().ok_or_else(not_json)?;
+        // The strings of arrays are lines, their other values are in the typed columns.
+        let line_leaves: Vec<LeafId> = leaf_ids
+            .iter()
+            .copied()
+            .filter(|leaf_id| {
+                matches!(
+                    tree.leaf_info(*leaf_id).kind,
+                    LeafKind::String | LeafKind::Array
+                )
+            })
+            .collect();
+        let typed_leaves: Vec<LeafId> = leaf_ids
+            .iter()
+            .copied()
+            .filter(|leaf_id| tree.leaf_info(*leaf_id).kind != LeafKind::String)
+            .collect();
+        let mut docs = Vec::new();
+        if !line_leaves.is_empty() {
+            let lines = self.lines_matching(segment, query)?;
+            let lines = json_docs.lines_of_leaves(&lines, &line_leaves);
+            docs = json_docs.docs_of_lines(&lines);
+        }
+        if !typed_leaves.is_empty() && matches!(query, Query::Term(_) | Query::Range(_)) {
+            let columns = segment.open_json_columns()?;
+            for leaf_id in typed_leaves {
+                docs.extend(
+                    json_docs.docs_with_leaf_value(tree, &columns, leaf_id, |value| {
+                        typed_value_matches(query, value)
+                    })?,
+                );
+            }
+            docs.sort_unstable();
+            docs.dedup();
+        }
+        Ok(docs)
+    }
 
-        let matching_documents: Vec<Doc> =
-            self.get_doc_from_templates(matching_template_ids, search_result.as_ref())?;
-        Ok(matching_documents)
+    /// Returns the sorted lines of the segment matching the query, local to the segment.
+    fn lines_matching(&self, segment: &Segment, query: &Query) -> io::Result<Vec<u32>> {
+        let resolved = self.resolve(segment, query)?;
+        let postings = resolved.postings();
+        let candidates = resolved.candidates(segment, segment.templates.iter())?;
+        let template_hits =
+            map_parallel(&candidates, self.num_threads, |(template, row_filter)| {
+                self.template_hits(segment, template, row_filter, &postings, 0, None)
+            });
+        let mut lines = Vec::new();
+        for template_hits in template_hits {
+            lines.extend(
+                template_hits?
+                    .into_iter()
+                    .map(|doc| doc.doc_id - segment.doc_id_offset),
+            );
+        }
+        lines.sort_unstable();
+        Ok(lines)
     }
 
-    /// Returns the term ids of each document
+    /// Returns the term ids of each document of a template in the segment at `segment_ord` that
+    /// contains `term_id` in any column.
     pub fn search_in_zstd_column(
         &self,
-        match_fn: impl Fn(u32) -> bool,
+        term_id: u32,
+        segment_ord: usize,
         template_id: TemplateId,
         max_hits: Option<usize>,
     ) -> io::Result<Vec<Vec<u32>>> {
         self.index
-            .search_in_zstd_column(match_fn, template_id, max_hits)
+            .search_in_zstd_column(term_id, segment_ord, template_id, max_hits)
     }
 }
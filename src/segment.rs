@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::SEGMENTS_FILE_NAME;
+
+/// A batch of documents indexed together, with its own dictionary, templates and columns.
+///
+/// Segments are stored in subfolders of the index folder, and their doc ids continue where the
+/// previous segment ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SegmentMeta {
+    /// Unique within the index, determines the folder of the segment.
+    pub id: u32,
+    pub num_docs: u32,
+    /// The last line of the segment had no `\n` in the input, see
+    /// [`IndexWriter::with_unterminated_last_line`](crate::indexing::IndexWriter::with_unterminated_last_line).
+    pub last_line_unterminated: bool,
+}
+
+impl SegmentMeta {
+    pub fn folder(&self, index_folder: &Path) -> PathBuf {
+        index_folder.join(format!("segment_{}", self.id))
+    }
+
+    /// Creates the folder of a new segment after the existing ones, its number of docs is set
+    /// once written.
+    ///
+    /// Ids whose folder exists are skipped, e.g. the folder of a segment that was not added to
+    /// the list because the process stopped while writing it.
+    pub fn create(index_folder: &Path, segment_metas: &[SegmentMeta]) -> io::Result<SegmentMeta> {
+        let mut meta = SegmentMeta {
+            id: segment_metas
+                .iter()
+                .map(|meta| meta.id + 1)
+                .max()
+                .unwrap_or(0),
+            num_docs: 0,
+            last_line_unterminated: false,
+        };
+        loop {
+            match fs::create_dir(meta.folder(index_folder)) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => meta.id += 1,
+                result => return result.map(|_| meta),
+            }
+        }
+    }
+}
+
+/// Writes a new segment into its folder, and removes the folder if writing fails, so the
+/// folder of a segment that is not in the list doesn't stay behind.
+pub(crate) fn write_new_segment<T>(
+    segment_folder: &Path,
+    write: impl FnOnce() -> io::Result<T>,
+) -> io::Result<T> {
+    let result = write();
+    if result.is_err() {
+        // The error of the write is more useful than one of the cleanup.
+        let _ = fs::remove_dir_all(segment_folder);
+    }
+    result
+}
+
+/// Read the segments of the index in doc id order.
+pub(crate) fn read_segment_metas(index_folder: &Path) -> io::Result<Vec<SegmentMeta>> {
+    let bytes = fs::read(index_folder.join(SEGMENTS_FILE_NAME))?;
+    postcard::from_bytes(&bytes).map_err(io::Error::other)
+}
+
+/// Replace the list of segments of the index.
+///
+/// The file is replaced with a rename, so readers see either the old or the new list.
+pub(crate) fn write_segment_metas(index_folder: &Path, metas: &[SegmentMeta]) -> io::Result<()> {
+    let bytes = postcard::to_allocvec(metas).map_err(io::Error::other)?;
+    let tmp_path = index_folder.join(format!("{SEGMENTS_FILE_NAME}.tmp"));
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(tmp_path, index_folder.join(SEGMENTS_FILE_NAME))
+}
@@ -0,0 +1,232 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use crate::constants::{SETTINGS_FILE_NAME, ZSTD_DICTIONARY_NAME};
+
+/// The zstd level used if none is configured.
+pub const DEFAULT_ZSTD_LEVEL: i32 = 6;
+
+/// How the files of the templates of an index are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Zstd with the given level, higher levels are smaller but slower to write.
+    Zstd {
+        /// The zstd compression level, 1 to 22.
+        level: i32,
+    },
+    /// Lz4, fast to write and read but larger than zstd.
+    Lz4,
+    /// Stored as is.
+    None,
+}
+
+impl Default for Codec {
+    fn default() -> Self {
+        Codec::Zstd {
+            level: DEFAULT_ZSTD_LEVEL,
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = io::Error;
+
+    /// Parses `zstd`, `zstd:<level>`, `lz4` or `none`.
+    fn from_str(codec: &str) -> io::Result<Self> {
+        match codec {
+            "zstd" => Ok(Codec::default()),
+            "lz4" => Ok(Codec::Lz4),
+            "none" => Ok(Codec::None),
+            _ => codec
+                .strip_prefix("zstd:")
+                .and_then(|level| level.parse().ok())
+                .map(|level| Codec::Zstd { level })
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown codec {codec:?}"),
+                    )
+                }),
+        }
+    }
+}
+
+/// Settings fixed when an index is created, stored in the index folder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSettings {
+    /// The codec of the columns, doc ids and timestamps of the templates.
+    pub codec: Codec,
+    /// Train a zstd dictionary of at most this many bytes per segment.
+    ///
+    /// The dictionary is trained on the column blocks of the small templates, which compress
+    /// poorly on their own. Only used with [`Codec::Zstd`].
+    pub zstd_dictionary_size: Option<usize>,
+    /// Write postings for the terms in at most this many rows of a template.
+    ///
+    /// The postings are stored in the dictionary, next to the templates of each term. Stored so
+    /// that merges keep the postings of the merged templates too, see
+    /// [`IndexWriter::with_postings_threshold`](crate::indexing::IndexWriter::with_postings_threshold).
+    pub postings_threshold: Option<usize>,
+}
+
+/// Reads the settings of the index, the default settings if the index has no settings file.
+pub(crate) fn read_settings(index_folder: &Path) -> io::Result<IndexSettings> {
+    match fs::read(index_folder.join(SETTINGS_FILE_NAME)) {
+        Ok(bytes) => postcard::from_bytes(&bytes).map_err(io::Error::other),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(IndexSettings::default()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes the settings of the index.
+pub(crate) fn write_settings(index_folder: &Path, settings: &IndexSettings) -> io::Result<()> {
+    let bytes = postcard::to_allocvec(settings).map_err(io::Error::other)?;
+    fs::write(index_folder.join(SETTINGS_FILE_NAME), bytes)
+}
+
+/// The codec of a segment, with the zstd dictionary trained for the segment if any.
+#[derive(Clone, Default)]
+pub(crate) struct Compression {
+    codec: Codec,
+    dictionary: Option<Arc<ZstdDictionary>>,
+}
+
+struct ZstdDictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl fmt::Debug for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compression")
+            .field("codec", &self.codec)
+            .field("has_dictionary", &self.dictionary.is_some())
+            .finish()
+    }
+}
+
+impl Compression {
+    /// Compression with the codec and no dictionary.
+    pub(crate) fn new(codec: Codec) -> Self {
+        Compression {
+            codec,
+            dictionary: None,
+        }
+    }
+
+    /// Opens the compression of a segment, with its zstd dictionary if it has one.
+    pub(crate) fn open(segment_folder: &Path, codec: Codec) -> io::Result<Self> {
+        match fs::read(segment_folder.join(ZSTD_DICTIONARY_NAME)) {
+            Ok(dictionary) => Ok(Self::new(codec).with_dictionary(&dictionary)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::new(codec)),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Trains a zstd dictionary of at most `max_size` bytes on the samples and writes it to the
+    /// segment folder.
+    ///
+    /// Without samples, with a codec other than zstd, or if the training fails, e.g. because
+    /// the samples are too small, no dictionary is used.
+    pub(crate) fn train(
+        self,
+        segment_folder: &Path,
+        samples: &[Vec<u8>],
+        max_size: usize,
+    ) -> io::Result<Self> {
+        if !matches!(self.codec, Codec::Zstd { .. }) || samples.is_empty() {
+            return Ok(self);
+        }
+        let Ok(dictionary) = zstd::dict::from_samples(samples, max_size) else {
+            return Ok(self);
+        };
+        fs::write(segment_folder.join(ZSTD_DICTIONARY_NAME), &dictionary)?;
+        Ok(self.with_dictionary(&dictionary))
+    }
+
+    fn with_dictionary(mut self, dictionary: &[u8]) -> Self {
+        if let Codec::Zstd { level } = self.codec {
+            self.dictionary = Some(Arc::new(ZstdDictionary {
+                encoder: EncoderDictionary::copy(dictionary, level),
+                decoder: DecoderDictionary::copy(dictionary),
+            }));
+        }
+        self
+    }
+
+    /// Appends the compressed data to `output`.
+    pub(crate) fn compress(&self, data: &[u8], output: &mut Vec<u8>) -> io::Result<()> {
+        match (self.codec, &self.dictionary) {
+            (Codec::Zstd { .. }, Some(dictionary)) => {
+                let mut encoder =
+                    zstd::stream::Encoder::with_prepared_dictionary(output, &dictionary.encoder)?;
+                encoder.write_all(data)?;
+                encoder.finish()?;
+            }
+            (Codec::Zstd { level }, None) => zstd::stream::copy_encode(data, output, level)?,
+            (Codec::Lz4, _) => output.extend_from_slice(&lz4_flex::compress_prepend_size(data)),
+            (Codec::None, _) => output.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// Decompresses data written by [`Compression::compress`].
+    pub(crate) fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match (self.codec, &self.dictionary) {
+            (Codec::Zstd { .. }, Some(dictionary)) => {
+                let mut decoder =
+                    zstd::stream::Decoder::with_prepared_dictionary(data, &dictionary.decoder)?;
+                let mut decompressed = Vec::new();
+                decoder.read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            (Codec::Zstd { .. }, None) => zstd::stream::decode_all(data),
+            (Codec::Lz4, _) => lz4_flex::decompress_size_prepended(data)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            (Codec::None, _) => Ok(data.to_vec()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_codec() {
+        assert_eq!("zstd".parse::<Codec>().unwrap(), Codec::Zstd { level: 6 });
+        assert_eq!(
+            "zstd:19".parse::<Codec>().unwrap(),
+            Codec::Zstd { level: 19 }
+        );
+        assert_eq!("lz4".parse::<Codec>().unwrap(), Codec::Lz4);
+        assert_eq!("none".parse::<Codec>().unwrap(), Codec::None);
+        assert!("zstd:high".parse::<Codec>().is_err());
+        assert!("gzip".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let data: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| (i % 300).to_le_bytes())
+            .collect();
+        for codec in [
+            Codec::default(),
+            Codec::Zstd { level: 1 },
+            Codec::Lz4,
+            Codec::None,
+        ] {
+            let compression = Compression::new(codec);
+            let mut compressed = vec![1, 2];
+            compression.compress(&data, &mut compressed).unwrap();
+            assert_eq!(compression.decompress(&compressed[2..]).unwrap(), data);
+        }
+    }
+}
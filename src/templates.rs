@@ -8,6 +8,7 @@ use crate::TemplateId;
 use crate::constants::{TEMPLATE_DEBUG_FILE_NAME, TEMPLATE_FILE_NAME};
 use crate::dict::Dict;
 use crate::indexing::{self, IndexingTemplate, IndexingTemplateToken, PreliminaryIndex};
+use crate::tokenizer::{Number, TokenType};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum MatchResult {
@@ -19,11 +20,15 @@ pub enum MatchResult {
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct TemplateWithId {
     pub num_docs: usize,
+    /// The first and last doc id (line number) in this template.
+    pub doc_id_range: (u32, u32),
+    /// The min and max timestamp of the documents, `None` if no document has a timestamp.
+    pub time_range: Option<(i64, i64)>,
     pub template_id: TemplateId,
     pub template: Template,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct Template {
     pub(crate) parts: Vec<TemplateToken>,
 }
@@ -32,6 +37,11 @@ impl TemplateWithId {
     pub(crate) fn num_docs(&self) -> usize {
         self.num_docs
     }
+
+    /// Returns true if the doc id may be stored in this template.
+    pub(crate) fn may_contain_doc_id(&self, doc_id: u32) -> bool {
+        self.doc_id_range.0 <= doc_id && doc_id <= self.doc_id_range.1
+    }
 }
 
 impl Template {
@@ -43,7 +53,7 @@ impl Template {
                 TemplateToken::Constant(bytes) => {
                     out.push_str(std::str::from_utf8(bytes).unwrap());
                 }
-                TemplateToken::Variable => {
+                TemplateToken::Variable(_) => {
                     out.push('?');
                 }
             }
@@ -51,30 +61,85 @@ impl Template {
         out
     }
     pub fn reconstruct(&self, term_ids: &[u32], dict: &Dict) -> io::Result<String> {
-        let mut reconstructed = String::new();
+        let mut reconstructed = Vec::new();
+        let mut term = Vec::new();
+        self.reconstruct_into(
+            term_ids,
+            |term_id, out| {
+                term.clear();
+                if !dict.term_bytes_for_ord(term_id, &mut term)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Term ID {term_id} out of bounds"),
+                    ));
+                }
+                out.extend_from_slice(&term);
+                Ok(())
+            },
+            &mut reconstructed,
+        )?;
+        String::from_utf8(reconstructed).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Failed to convert reconstructed bytes to String",
+            )
+        })
+    }
+
+    /// Appends the document with the given term ids to `out`.
+    ///
+    /// `write_term` appends the term bytes of a term id, as stored in the dictionary.
+    pub(crate) fn reconstruct_into(
+        &self,
+        term_ids: &[u32],
+        mut write_term: impl FnMut(u32, &mut Vec<u8>) -> io::Result<()>,
+        out: &mut Vec<u8>,
+    ) -> io::Result<()> {
         let mut term_id_idx = 0;
+        let mut number_term = Vec::new();
         for token in &self.parts {
             match token {
                 TemplateToken::Constant(constant) => {
-                    // TODO: FIX numbers(number_as_string feature flag)
-                    reconstructed.push_str(std::str::from_utf8(constant).unwrap());
+                    out.extend_from_slice(constant);
                 }
-                TemplateToken::Variable => {
-                    let term = dict
-                        .get_term_for_ord(term_ids[term_id_idx])?
-                        .expect("Term ID out of bounds");
-                    reconstructed.push_str(&term);
+                TemplateToken::Variable(TokenType::Number) => {
+                    number_term.clear();
+                    write_term(term_ids[term_id_idx], &mut number_term)?;
+                    Number::write_term_text(&number_term, out);
+                    term_id_idx += 1;
+                }
+                TemplateToken::Variable(_) => {
+                    write_term(term_ids[term_id_idx], out)?;
                     term_id_idx += 1;
                 }
             }
         }
-        Ok(reconstructed)
+        Ok(())
+    }
+    /// Returns for each part the index of its column in the column file, `None` for constants.
+    ///
+    /// Columns are stored in the order of the variables in the template.
+    pub(crate) fn column_indices(&self) -> Vec<Option<usize>> {
+        let mut next_column = 0;
+        self.parts
+            .iter()
+            .map(|token| match token {
+                TemplateToken::Constant(_) => None,
+                TemplateToken::Variable(_) => {
+                    next_column += 1;
+                    Some(next_column - 1)
+                }
+            })
+            .collect()
     }
+
     // If any of the tokens match, the whole template matches.
-    pub fn check_match(&self, term: &str) -> MatchResult {
+    //
+    // `matches_term` decides if a constant matches, e.g. exact equality or a wildcard pattern.
+    pub fn check_match(&self, matches_term: impl Fn(&[u8]) -> bool) -> MatchResult {
         let mut match_result = MatchResult::NoMatch;
         for token in &self.parts {
-            let result = token.check_match(term);
+            let result = token.check_match(&matches_term);
             match result {
                 MatchResult::Full => return MatchResult::Full,
                 MatchResult::VariableMayMatch => match_result = MatchResult::VariableMayMatch,
@@ -88,6 +153,8 @@ impl From<&IndexingTemplate> for TemplateWithId {
     fn from(template: &IndexingTemplate) -> Self {
         TemplateWithId {
             num_docs: template.num_docs,
+            doc_id_range: template.doc_id_range,
+            time_range: template.time_range,
             template_id: template.template_id,
             template: Template {
                 parts: template
@@ -100,22 +167,23 @@ impl From<&IndexingTemplate> for TemplateWithId {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum TemplateToken {
+    /// The text of the constant, numbers are stored as text regardless of `number_as_string`.
     Constant(Vec<u8>),
-    Variable,
+    Variable(TokenType),
 }
 impl TemplateToken {
-    pub fn check_match(&self, term: &str) -> MatchResult {
+    pub fn check_match(&self, matches_term: impl Fn(&[u8]) -> bool) -> MatchResult {
         match self {
             TemplateToken::Constant(constant) => {
-                if term.as_bytes() == constant {
+                if matches_term(constant) {
                     MatchResult::Full
                 } else {
                     MatchResult::NoMatch
                 }
             }
-            TemplateToken::Variable => MatchResult::VariableMayMatch,
+            TemplateToken::Variable(_) => MatchResult::VariableMayMatch,
         }
     }
 }
@@ -123,29 +191,38 @@ impl From<&IndexingTemplateToken> for TemplateToken {
     fn from(token: &IndexingTemplateToken) -> Self {
         match token {
             indexing::IndexingTemplateToken::Constant(const_token) => {
-                TemplateToken::Constant(const_token.text.to_vec())
+                if const_token.token_type == TokenType::Number {
+                    let mut text = Vec::new();
+                    Number::write_term_text(&const_token.text, &mut text);
+                    TemplateToken::Constant(text)
+                } else {
+                    TemplateToken::Constant(const_token.text.to_vec())
+                }
+            }
+            indexing::IndexingTemplateToken::Variable { token_type, .. } => {
+                TemplateToken::Variable(*token_type)
             }
-            indexing::IndexingTemplateToken::Variable { .. } => TemplateToken::Variable,
         }
     }
 }
 
 pub fn write_templates(index: &PreliminaryIndex, folder: &Path) -> io::Result<()> {
-    let path = folder.join(TEMPLATE_FILE_NAME);
-    let mut writer = BufWriter::new(File::create(path)?);
     let templates_only: Vec<TemplateWithId> =
         index.iter_templates().map(TemplateWithId::from).collect();
-    let bytes: Vec<u8> = postcard::to_allocvec(&templates_only).map_err(io::Error::other)?;
+    write_template_list(&templates_only, folder)
+}
+pub fn write_template_list(templates: &[TemplateWithId], folder: &Path) -> io::Result<()> {
+    let path = folder.join(TEMPLATE_FILE_NAME);
+    let mut writer = BufWriter::new(File::create(path)?);
+    let bytes: Vec<u8> = postcard::to_allocvec(templates).map_err(io::Error::other)?;
     writer.write_all(&bytes)?;
     writer.flush()?;
 
     if std::env::var("DEBUG_TEMPLATES").is_ok() {
         let path = folder.join(TEMPLATE_DEBUG_FILE_NAME);
         let mut writer = BufWriter::new(File::create(path)?);
-        for template in index.iter_templates() {
-            let template = TemplateWithId::from(template);
-            let template = template.template;
-            writer.write_all(template.ser_readable().as_bytes())?;
+        for template in templates {
+            writer.write_all(template.template.ser_readable().as_bytes())?;
             writer.write_all(b"\n")?;
         }
     }
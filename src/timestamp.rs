@@ -0,0 +1,262 @@
+//! Recognizes the timestamp at the start of a log line.
+//!
+//! Timestamps are stored as milliseconds since the unix epoch (UTC), next to the columns of each
+//! template. The line itself is indexed unchanged, so it can still be restored byte for byte.
+
+use std::io;
+use std::str::FromStr;
+
+/// A supported timestamp format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `2024-01-31T12:34:56.789+01:00`, a space may replace the `T`. The fraction and the offset
+    /// are optional, without offset the time is read as UTC.
+    Iso8601,
+    /// `Jan 31 12:34:56` as in RFC 3164. The format has no year, so it is configured.
+    Syslog {
+        /// The year of the timestamps.
+        year: i32,
+    },
+    /// Seconds since the unix epoch, 10 digits.
+    EpochSeconds,
+    /// Milliseconds since the unix epoch, 13 digits.
+    EpochMillis,
+}
+
+impl TimestampFormat {
+    /// Parses a timestamp at the start of `text` and returns it in milliseconds since the epoch.
+    pub fn parse_prefix(&self, text: &str) -> Option<i64> {
+        let bytes = text.as_bytes();
+        match self {
+            TimestampFormat::Iso8601 => parse_iso8601(bytes),
+            TimestampFormat::Syslog { year } => parse_syslog(bytes, *year),
+            TimestampFormat::EpochSeconds => parse_epoch(bytes, 10).map(|secs| secs * 1000),
+            TimestampFormat::EpochMillis => parse_epoch(bytes, 13),
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = io::Error;
+
+    /// Parses `iso8601`, `syslog:<year>`, `epoch-seconds` or `epoch-millis`.
+    fn from_str(format: &str) -> io::Result<Self> {
+        match format {
+            "iso8601" => Ok(TimestampFormat::Iso8601),
+            "epoch-seconds" => Ok(TimestampFormat::EpochSeconds),
+            "epoch-millis" => Ok(TimestampFormat::EpochMillis),
+            _ => format
+                .strip_prefix("syslog:")
+                .and_then(|year| year.parse().ok())
+                .map(|year| TimestampFormat::Syslog { year })
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown timestamp format {format:?}"),
+                    )
+                }),
+        }
+    }
+}
+
+/// Returns the timestamp of the first format that matches the start of the line.
+pub fn extract_timestamp(line: &str, formats: &[TimestampFormat]) -> Option<i64> {
+    formats.iter().find_map(|format| format.parse_prefix(line))
+}
+
+/// A half-open time range `[from, to)` in milliseconds since the epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    /// Inclusive start.
+    pub from: i64,
+    /// Exclusive end.
+    pub to: i64,
+}
+
+impl TimeRange {
+    /// Returns true if the timestamp is in the range.
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.from <= timestamp && timestamp < self.to
+    }
+
+    /// Returns true if any timestamp in `min..=max` is in the range.
+    pub fn overlaps(&self, min: i64, max: i64) -> bool {
+        self.from <= max && min < self.to
+    }
+}
+
+/// Reads exactly `len` ascii digits at `pos`.
+fn digits(bytes: &[u8], pos: usize, len: usize) -> Option<i64> {
+    let digits = bytes.get(pos..pos + len)?;
+    digits.iter().try_fold(0i64, |value, byte| {
+        byte.is_ascii_digit()
+            .then(|| value * 10 + (byte - b'0') as i64)
+    })
+}
+
+fn expect(bytes: &[u8], pos: usize, expected: &[u8]) -> Option<()> {
+    expected.contains(bytes.get(pos)?).then_some(())
+}
+
+fn parse_iso8601(bytes: &[u8]) -> Option<i64> {
+    let year = digits(bytes, 0, 4)?;
+    expect(bytes, 4, b"-")?;
+    let month = digits(bytes, 5, 2)?;
+    expect(bytes, 7, b"-")?;
+    let day = digits(bytes, 8, 2)?;
+    expect(bytes, 10, b"T ")?;
+    let millis_of_day = parse_time_of_day(bytes, 11)?;
+    let mut pos = 19;
+
+    let mut millis = 0;
+    if expect(bytes, pos, b".,").is_some() {
+        pos += 1;
+        let num_digits = bytes[pos..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        if num_digits == 0 {
+            return None;
+        }
+        // Only millisecond precision is kept.
+        let fraction = digits(bytes, pos, num_digits.min(3))?;
+        millis = fraction * 10i64.pow(3 - num_digits.min(3) as u32);
+        pos += num_digits;
+    }
+
+    let offset_minutes = parse_offset(bytes, pos);
+    let days = days_from_civil(year, month, day)?;
+    Some(days * 86_400_000 + millis_of_day + millis - offset_minutes * 60_000)
+}
+
+/// Parses a UTC offset like `Z`, `+01:00`, `-0130` or `+01` in minutes, 0 if there is none.
+fn parse_offset(bytes: &[u8], pos: usize) -> i64 {
+    let sign = match bytes.get(pos) {
+        Some(b'+') => 1,
+        Some(b'-') => -1,
+        _ => return 0,
+    };
+    let Some(hours) = digits(bytes, pos + 1, 2) else {
+        return 0;
+    };
+    let minutes_pos = if bytes.get(pos + 3) == Some(&b':') {
+        pos + 4
+    } else {
+        pos + 3
+    };
+    let minutes = digits(bytes, minutes_pos, 2).unwrap_or(0);
+    sign * (hours * 60 + minutes)
+}
+
+const MONTHS: [&[u8]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+fn parse_syslog(bytes: &[u8], year: i32) -> Option<i64> {
+    let month = MONTHS.iter().position(|month| bytes.starts_with(month))? as i64 + 1;
+    expect(bytes, 3, b" ")?;
+    // The day is padded with a space, e.g. `Jan  1`.
+    let day_pos = if bytes.get(4) == Some(&b' ') { 5 } else { 4 };
+    let (day, time_pos) = match digits(bytes, day_pos, 2) {
+        Some(day) => (day, day_pos + 3),
+        None => (digits(bytes, day_pos, 1)?, day_pos + 2),
+    };
+    expect(bytes, time_pos - 1, b" ")?;
+    let millis_of_day = parse_time_of_day(bytes, time_pos)?;
+    let days = days_from_civil(year as i64, month, day)?;
+    Some(days * 86_400_000 + millis_of_day)
+}
+
+/// Parses `HH:MM:SS` at `pos` and returns the milliseconds since midnight.
+fn parse_time_of_day(bytes: &[u8], pos: usize) -> Option<i64> {
+    let hour = digits(bytes, pos, 2)?;
+    expect(bytes, pos + 2, b":")?;
+    let minute = digits(bytes, pos + 3, 2)?;
+    expect(bytes, pos + 5, b":")?;
+    // Allow a leap second.
+    let second = digits(bytes, pos + 6, 2)?;
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    Some(((hour * 60 + minute) * 60 + second) * 1000)
+}
+
+/// Reads a number of exactly `num_digits` digits, which must not be followed by another digit.
+fn parse_epoch(bytes: &[u8], num_digits: usize) -> Option<i64> {
+    if bytes.get(num_digits).is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+    digits(bytes, 0, num_digits)
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: i64, month: i64, day: i64) -> Option<i64> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_iso8601() {
+        let format = TimestampFormat::Iso8601;
+        assert_eq!(format.parse_prefix("1970-01-01T00:00:00Z start"), Some(0));
+        assert_eq!(
+            format.parse_prefix("2016-04-13 06:46:53,123 INFO"),
+            Some(1_460_530_013_123)
+        );
+        assert_eq!(
+            format.parse_prefix("2016-04-13T08:46:53.5+02:00"),
+            Some(1_460_530_013_500)
+        );
+        assert_eq!(format.parse_prefix("2016-13-13T08:46:53"), None);
+        assert_eq!(format.parse_prefix("INFO 2016-04-13T08:46:53"), None);
+    }
+
+    #[test]
+    fn parses_syslog_and_epoch() {
+        let format = TimestampFormat::Syslog { year: 2016 };
+        assert_eq!(
+            format.parse_prefix("Apr 13 06:46:53 host sshd[1]:"),
+            Some(1_460_530_013_000)
+        );
+        assert_eq!(
+            format.parse_prefix("Apr  1 00:00:00 host"),
+            Some(1_459_468_800_000)
+        );
+
+        assert_eq!(
+            TimestampFormat::EpochSeconds.parse_prefix("1460530013 INFO"),
+            Some(1_460_530_013_000)
+        );
+        assert_eq!(
+            TimestampFormat::EpochMillis.parse_prefix("1460530013123"),
+            Some(1_460_530_013_123)
+        );
+        assert_eq!(
+            TimestampFormat::EpochSeconds.parse_prefix("14605300131"),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_format_names() {
+        assert_eq!(
+            "syslog:2024".parse::<TimestampFormat>().unwrap(),
+            TimestampFormat::Syslog { year: 2024 }
+        );
+        assert!("unknown".parse::<TimestampFormat>().is_err());
+    }
+}
@@ -230,6 +230,14 @@ fn is_number(bytes: &[u8]) -> Option<usize> {
     )
 }
 
+/// Parses the text of a number token, `None` if the text is no number or doesn't fit a `u64`.
+pub(crate) fn parse_number(text: &[u8]) -> Option<u64> {
+    if text.is_empty() || is_number(text) != Some(text.len()) {
+        return None;
+    }
+    std::str::from_utf8(text).ok()?.parse().ok()
+}
+
 /// Simple UUID v4-ish check (8-4-4-4-12 pattern, 36 bytes total)
 /// Returns the number of bytes consumed (36) on success.
 #[inline]
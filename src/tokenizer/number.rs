@@ -1,51 +1,138 @@
+//! Numbers stored as binary terms, a tag byte followed by the little-endian bytes of the value.
 use std::ops::Range;
 
-#[derive(Debug, Copy, Clone)]
+use super::{TokenType, parse_number};
+
+/// The tag of the term of a number parsed as `u64`.
+const U64_TAG: u8 = 0;
+/// The tag of the term of a number parsed as `f64`.
+const F64_TAG: u8 = 1;
+
+/// A number token, stored in the dictionary as a tagged binary value.
+///
+/// Numbers whose text can't be restored from the value, e.g. `007`, are stored as text. Text
+/// terms start with a digit, so they can't be confused with the tags.
+#[derive(Debug, Clone)]
 pub enum Number {
-    F64([u8; 8]),
-    /// Represents u64 as little-endian bytes
-    U64([u8; 8]),
+    /// The `f64` tag followed by the little-endian bytes of the value.
+    F64([u8; 9]),
+    /// The `u64` tag followed by the little-endian bytes of the value.
+    U64([u8; 9]),
+    /// The byte range of a number that is stored as its text.
+    Text(Range<usize>),
 }
 impl From<u64> for Number {
     #[inline]
     fn from(num: u64) -> Self {
-        Number::U64(num.to_le_bytes())
+        Number::U64(tagged(U64_TAG, num.to_le_bytes()))
     }
 }
 impl From<f64> for Number {
     #[inline]
     fn from(num: f64) -> Self {
-        Number::F64(num.to_le_bytes())
+        Number::F64(tagged(F64_TAG, num.to_le_bytes()))
     }
 }
 
+fn tagged(tag: u8, bytes: [u8; 8]) -> [u8; 9] {
+    let mut term = [tag; 9];
+    term[1..].copy_from_slice(&bytes);
+    term
+}
+
+/// Returns the tag and the value bytes of a binary number term.
+fn split_tag(term: &[u8]) -> Option<(u8, [u8; 8])> {
+    let (tag, bytes) = term.split_first()?;
+    Some((*tag, bytes.try_into().ok()?))
+}
+
 impl Number {
+    /// Parses the number in `input[range]`.
+    ///
+    /// The text is kept if formatting the parsed value doesn't give it back, so every number
+    /// can be restored byte for byte.
     #[inline]
     pub fn new(input: &str, range: Range<usize>) -> Self {
-        let num_str = &input[range];
-        let number = num_str.parse::<u64>();
-        match number {
-            Ok(n) => n.into(),
-            Err(_) => {
-                let num = num_str
-                    .parse::<f64>()
-                    .expect("Failed to parse number as f64");
-                num.into()
-            }
+        let num_str = &input[range.clone()];
+        let has_leading_zero = num_str.len() > 1 && num_str.starts_with('0');
+        if let Some(num) = parse_number(num_str.as_bytes())
+            && !has_leading_zero
+        {
+            return num.into();
+        }
+        match num_str.parse::<f64>() {
+            Ok(num) if num.to_string() == num_str => num.into(),
+            _ => Number::Text(range),
         }
     }
+    /// Returns the term of the number, see [`Number`].
     #[inline]
-    pub fn as_bytes(&self, _input: &str) -> &[u8] {
+    pub fn as_bytes<'a>(&'a self, input: &'a str) -> &'a [u8] {
         match self {
             Number::F64(bytes) => bytes,
             Number::U64(bytes) => bytes,
+            Number::Text(range) => &input.as_bytes()[range.clone()],
+        }
+    }
+    /// Appends the text of a number stored as term bytes (see [`Number::as_bytes`]) to `out`.
+    pub fn write_term_text(term: &[u8], out: &mut Vec<u8>) {
+        match split_tag(term) {
+            Some((U64_TAG, bytes)) => {
+                out.extend_from_slice(u64::from_le_bytes(bytes).to_string().as_bytes())
+            }
+            Some((F64_TAG, bytes)) => {
+                out.extend_from_slice(f64::from_le_bytes(bytes).to_string().as_bytes())
+            }
+            _ => out.extend_from_slice(term),
         }
     }
+    /// Returns the value of a number stored as term bytes, `None` if it doesn't fit a `u64`.
+    ///
+    /// Only meaningful for terms of number columns, see [`Number::column_may_hold_numbers`].
     #[inline]
-    pub fn to_string(&self, _input: &str) -> String {
+    pub(crate) fn term_value(term: &[u8]) -> Option<u64> {
+        match split_tag(term) {
+            Some((U64_TAG, bytes)) => Some(u64::from_le_bytes(bytes)),
+            Some((F64_TAG, _)) => None,
+            _ => parse_number(term),
+        }
+    }
+
+    /// Number columns are never merged with other token types, so only they contain numbers.
+    #[inline]
+    pub(crate) fn column_may_hold_numbers(token_type: TokenType) -> bool {
+        token_type == TokenType::Number
+    }
+    /// Returns the text of the number.
+    #[inline]
+    pub fn to_string(&self, input: &str) -> String {
         match self {
-            Number::F64(bytes) => f64::from_le_bytes(*bytes).to_string(),
-            Number::U64(bytes) => u64::from_le_bytes(*bytes).to_string(),
+            Number::F64(bytes) => f64::from_le_bytes(bytes[1..].try_into().unwrap()).to_string(),
+            Number::U64(bytes) => u64::from_le_bytes(bytes[1..].try_into().unwrap()).to_string(),
+            Number::Text(range) => input[range.clone()].to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_terms_round_trip() {
+        let input = "3.5 42 007 0 123456789012345678901234 100000000000000000000";
+        for text in input.split(' ') {
+            let start = text.as_ptr() as usize - input.as_ptr() as usize;
+            let number = Number::new(input, start..start + text.len());
+            let mut restored = Vec::new();
+            Number::write_term_text(number.as_bytes(input), &mut restored);
+            assert_eq!(restored, text.as_bytes());
+            assert_eq!(number.to_string(input), text);
         }
+        let forty_two = Number::new("42", 0..2);
+        assert_eq!(Number::term_value(forty_two.as_bytes("42")), Some(42));
+        assert_eq!(Number::term_value(b"007"), Some(7));
+        assert!(matches!(Number::new("3.5", 0..3), Number::F64(_)));
+        assert!(matches!(Number::new("007", 0..3), Number::Text(_)));
     }
 }
@@ -1,5 +1,7 @@
 use std::ops::Range;
 
+use super::{TokenType, parse_number};
+
 /// Represents a substring of the input, identified by its byte range.
 /// Stores only the raw text slice; no numeric parsing is performed.
 #[derive(Debug, Clone)]
@@ -21,6 +23,25 @@ impl Number {
         &input.as_bytes()[self.str_range.start..self.str_range.end]
     }
 
+    /// Appends the text of a number stored as term bytes (see [`Number::as_bytes`]) to `out`.
+    #[inline]
+    pub fn write_term_text(term: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(term);
+    }
+
+    /// Returns the value of a number stored as term bytes, `None` if the term is no number.
+    #[inline]
+    pub(crate) fn term_value(term: &[u8]) -> Option<u64> {
+        parse_number(term)
+    }
+
+    /// Numbers are stored as text, so columns of any token type may contain numbers after
+    /// templates are merged.
+    #[inline]
+    pub(crate) fn column_may_hold_numbers(_token_type: TokenType) -> bool {
+        true
+    }
+
     /// Converts the stored substring into a standalone `String`.
     #[inline]
     pub fn to_string(&self, input: &str) -> String {
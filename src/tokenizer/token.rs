@@ -22,14 +22,14 @@ impl Token {
     /// Compares with another token to see if they are the same type, but NOT range.
     #[inline]
     pub fn matches(&self, other: &Token) -> bool {
-        match (self, other) {
-            (Token::Word(_), Token::Word(_)) => true,
-            (Token::Number(_), Token::Number(_)) => true,
-            (Token::IPv4(_), Token::IPv4(_)) => true,
-            (Token::Uuid(_), Token::Uuid(_)) => true,
-            (Token::Punctuation(_), Token::Punctuation(_)) => true,
-            _ => false,
-        }
+        matches!(
+            (self, other),
+            (Token::Word(_), Token::Word(_))
+                | (Token::Number(_), Token::Number(_))
+                | (Token::IPv4(_), Token::IPv4(_))
+                | (Token::Uuid(_), Token::Uuid(_))
+                | (Token::Punctuation(_), Token::Punctuation(_))
+        )
     }
 }
 